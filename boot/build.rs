@@ -1,3 +1,32 @@
+use std::{env, fs, path::Path};
+
+/// DER prefix of an Ed25519 public key in a SubjectPublicKeyInfo, as written
+/// by `openssl pkey -pubout -outform DER`.
+const SPKI_PREFIX: [u8; 12] = [0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+
 pub fn main() {
     println!("cargo:rerun-if-changed=.cargo/linker.ld");
-}
\ No newline at end of file
+
+    if env::var_os("CARGO_FEATURE_SIGNED").is_some() {
+        embed_public_key();
+    }
+}
+
+/// Copies the public key in the file named by `BOOT_PUBLIC_KEY`, either the
+/// raw 32 bytes or DER, to `public_key.bin` in `OUT_DIR` for `include_bytes!`.
+fn embed_public_key() {
+    println!("cargo:rerun-if-env-changed=BOOT_PUBLIC_KEY");
+    let path = env::var("BOOT_PUBLIC_KEY")
+        .expect("the `signed` feature needs BOOT_PUBLIC_KEY set to an Ed25519 public key file");
+    println!("cargo:rerun-if-changed={}", path);
+
+    let file = fs::read(&path).unwrap_or_else(|e| panic!("reading {}: {}", path, e));
+    let key = match (file.len(), file.strip_prefix(&SPKI_PREFIX[..])) {
+        (32, _) => &file[..],
+        (44, Some(key)) => key,
+        _ => panic!("{} is neither a raw nor a DER Ed25519 public key", path),
+    };
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("public_key.bin");
+    fs::write(out, key).expect("writable OUT_DIR");
+}
@@ -0,0 +1,91 @@
+//! Command mode: serves the requests of `bootimg::proto`.
+
+use core::time::Duration;
+
+use bootimg::crc32;
+use bootimg::proto::{encode_frame, Decoded, Decoder, Info, Request, Status, MAX_DATA, MAX_FRAME, PROTOCOL_VERSION};
+use pi::timer;
+use pi::uart::MiniUart;
+
+use crate::{load_end, BINARY_START_ADDR};
+
+/// How long command mode waits for a request before going back to waiting for
+/// an XMODEM upload.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Serves requests on `uart` until one asks to jump to an address, which is
+/// returned, or until none arrives for `IDLE_TIMEOUT`.
+pub fn serve(uart: &mut MiniUart) -> Option<*mut u8> {
+    let mut decoder = Decoder::new();
+    let mut last_byte = timer::current_time();
+    loop {
+        if uart.wait_for_byte().is_err() {
+            if timer::current_time() - last_byte > IDLE_TIMEOUT {
+                return None;
+            }
+            continue;
+        }
+
+        last_byte = timer::current_time();
+        match decoder.push(uart.read_byte()) {
+            Decoded::Pending => continue,
+            Decoded::BadFrame => respond(uart, Status::BadFrame, &[]),
+            Decoded::Frame => {
+                let (kind, payload) = decoder.frame();
+                match Request::decode(kind, payload) {
+                    Ok(Request::Go { addr }) if in_range(addr, 1) => {
+                        respond(uart, Status::Ok, &[]);
+                        while !uart.is_idle() {}
+                        return Some(addr as *mut u8);
+                    }
+                    Ok(request) => handle(uart, request),
+                    Err(status) => respond(uart, status, &[]),
+                }
+            }
+        }
+    }
+}
+
+/// Carries out `request`, other than a valid `Go`, and responds to it.
+fn handle(uart: &mut MiniUart, request: Request) {
+    match request {
+        Request::Hello => {
+            let info = Info {
+                protocol: PROTOCOL_VERSION,
+                load_start: BINARY_START_ADDR as u64,
+                load_end: load_end() as u64,
+                max_data: MAX_DATA as u32,
+            };
+            respond(uart, Status::Ok, &info.to_bytes());
+        }
+        Request::Upload { addr, data } if in_range(addr, data.len() as u64) => {
+            unsafe { core::ptr::copy_nonoverlapping(data.as_ptr(), addr as *mut u8, data.len()) };
+            respond(uart, Status::Ok, &[]);
+        }
+        Request::Crc { addr, len } if in_range(addr, len) => {
+            let memory = unsafe { core::slice::from_raw_parts(addr as *const u8, len as usize) };
+            respond(uart, Status::Ok, &crc32(memory).to_le_bytes());
+        }
+        Request::Peek { len, .. } if len as usize > MAX_DATA => respond(uart, Status::BadLength, &[]),
+        Request::Peek { addr, len } if in_range(addr, len as u64) => {
+            let memory = unsafe { core::slice::from_raw_parts(addr as *const u8, len as usize) };
+            respond(uart, Status::Ok, memory);
+        }
+        Request::Upload { .. } | Request::Crc { .. } | Request::Peek { .. } | Request::Go { .. } => {
+            respond(uart, Status::OutOfRange, &[]);
+        }
+    }
+}
+
+/// Returns `true` if the `len` bytes at `addr` lie in the memory images are
+/// loaded into, the only memory requests may touch.
+fn in_range(addr: u64, len: u64) -> bool {
+    addr >= BINARY_START_ADDR as u64 && addr.checked_add(len).is_some_and(|end| end <= load_end() as u64)
+}
+
+/// Sends a response frame with `status` and `payload`.
+fn respond(uart: &mut MiniUart, status: Status, payload: &[u8]) {
+    let mut frame = [0u8; MAX_FRAME];
+    let len = encode_frame(status as u8, payload, &mut frame);
+    frame[..len].iter().for_each(|&b| uart.write_byte(b));
+}
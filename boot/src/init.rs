@@ -7,7 +7,12 @@ use crate::kmain;
 
 use core::arch::global_asm;
 
-global_asm!(include_str!("init/init.s"));
+global_asm!(
+    include_str!("init/init.s"),
+    MAILBOX = const pi::mailbox::MAILBOX_REG_BASE,
+    MEMORY_END = const bootimg::fallback::DEFAULT_MEMORY_END,
+    RESERVED = const bootimg::fallback::BOOT_RESERVED,
+);
 
 fn zeros_bss() {
     unsafe extern "C" {
@@ -26,8 +31,10 @@ fn zeros_bss() {
     }
 }
 
+/// Entered from `init.s` with the device tree address the firmware passed in
+/// `dtb`.
 #[unsafe(no_mangle)]
-unsafe fn kinit() -> ! {
+unsafe extern "C" fn kinit(dtb: usize) -> ! {
     zeros_bss();
-    kmain();
+    kmain(dtb);
 }
\ No newline at end of file
@@ -3,6 +3,8 @@
 .global _start
 
 _start:
+    // x0 holds the address of the firmware's device tree: keep it for kinit
+
     // read cpu affinity, start core 0, halt rest
     mrs     x1, mpidr_el1
     and     x1, x1, #3
@@ -14,10 +16,94 @@ _start:
     b       1b
 
 2:
-    // set the stack to start before our boot code
+    // set a temporary stack to start before our boot code
     adr     x1, _start
     mov     sp, x1
 
-    // jump to kinit, which shouldn't return. halt if it does
-    bl      kinit
+    // ask the VideoCore for the ARM's memory over the mailbox's property
+    // channel, with the message on the stack
+    sub     sp, sp, #32
+    mov     x2, sp
+    mov     w3, #32
+    stp     w3, wzr, [x2]           // message size, request
+    mov     w3, #0x0005
+    movk    w3, #0x0001, lsl #16
+    mov     w4, #8
+    stp     w3, w4, [x2, #8]        // tag ARM memory, value size
+    stp     wzr, wzr, [x2, #16]     // tag request, base
+    stp     wzr, wzr, [x2, #24]     // size, end tag
+    dsb     sy
+
+    ldr     x4, ={MAILBOX}
+    orr     w5, w2, #8              // property channel
+3:
+    ldr     w6, [x4, #0x18]         // wait while the mailbox is full
+    tbnz    w6, #31, 3b
+    str     w5, [x4, #0x20]
+4:
+    ldr     w6, [x4, #0x18]         // wait for the reply to our message
+    tbnz    w6, #30, 4b
+    ldr     w6, [x4]
+    cmp     w6, w5
+    b.ne    4b
+    dsb     sy
+
+    // x20 = end of memory, or the default if the VideoCore didn't answer
+    ldr     x20, ={MEMORY_END}
+    ldr     w6, [x2, #4]
+    tbz     w6, #31, 5f
+    ldp     w6, w7, [x2, #20]       // base, size
+    add     x20, x6, x7
+5:
+    // x21 = where we run, x22 = where we move to: the top of memory, less
+    // room for our stack
+    adr     x21, _start
+    ldr     x3, ={RESERVED}
+    sub     x22, x20, x3
+
+    // copy everything before BSS, 8 bytes at a time
+    ldr     x3, =__load_len
+    mov     x4, x21
+    mov     x5, x22
+6:
+    ldr     x6, [x4], #8
+    str     x6, [x5], #8
+    subs    x3, x3, #8
+    b.hi    6b
+
+    // apply the copy's R_AARCH64_RELATIVE relocations; we're linked at zero
+    adrp    x7, __rela_beg
+    add     x7, x7, :lo12:__rela_beg
+    adrp    x8, __rela_end
+    add     x8, x8, :lo12:__rela_end
+    sub     x7, x7, x21
+    add     x7, x7, x22
+    sub     x8, x8, x21
+    add     x8, x8, x22
+7:
+    cmp     x7, x8
+    b.hs    8f
+    ldp     x9, x10, [x7], #16      // offset, info
+    ldr     x11, [x7], #8           // addend
+    cmp     w10, #1027
+    b.ne    7b
+    add     x11, x11, x22
+    str     x11, [x22, x9]
+    b       7b
+
+8:
+    // don't run stale instructions from the copy
+    dsb     sy
+    ic      iallu
+    dsb     sy
+    isb
+
+    // set the stack to start at the end of memory and jump to kinit in the
+    // copy, which shouldn't return. halt if it does
+    mov     sp, x20
+    adrp    x1, kinit
+    add     x1, x1, :lo12:kinit
+    sub     x1, x1, x21
+    add     x1, x1, x22
+    blr     x1
     b       1b
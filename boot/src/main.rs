@@ -3,8 +3,11 @@
 
 #[cfg(not(test))]
 mod init;
+mod command;
+mod sink;
 
 use core::arch::asm;
+use core::fmt::{self, Write};
 use core::time::Duration;
 
 use pi::{self, timer};
@@ -12,27 +15,90 @@ use pi::gpio::PinOut;
 use pi::gpio::Output;
 use pi::uart::MiniUart;
 
-use xmodem::Xmodem;
+use bootimg::elf::{self, Elf};
+use bootimg::proto::{COMMAND_MODE, RESET};
+use bootimg::status::{Event, TRANSFER_ERROR_CODE, TRANSFER_ERROR_KIND};
+use bootimg::{fallback, Header, HEADER_SIZE, MAX_CMDLINE};
+use xmodem::{Error, Mode, Xmodem, XmodemConfig};
 use mutex::Mutex;
+use shim::io::Read;
+
+use sink::Sink;
 
 /// Global `PinOut` singleton.
 pub static PIN_16: Mutex<PinOut<Output>> = Mutex::new(PinOut::new(16));
 
-/// Start address of the binary to load and of the bootloader.
-const BINARY_START_ADDR:        usize = 0x0080000;
-const BOOTLOADER_START_ADDR:    usize = 0x4000000;
+/// The loaded kernel's command line, NUL-terminated. Kept here, in the memory
+/// reserved for the bootloader, so that loading the kernel doesn't overwrite
+/// it.
+static CMDLINE: Mutex<[u8; MAX_CMDLINE + 1]> = Mutex::new([0; MAX_CMDLINE + 1]);
+
+/// A loaded kernel.
+struct Kernel {
+    /// The entry point.
+    entry: *mut u8,
+    /// The command line in `CMDLINE`, or null if there's none.
+    cmdline: *const u8,
+}
+
+/// Start address of the binary to load.
+const BINARY_START_ADDR: usize = 0x0080000;
 
 /// Pointer to where the loaded binary expects to be laoded.
 const BINARY_START: *mut u8 = BINARY_START_ADDR as *mut u8;
 
-/// Free space between the bootloader and the loaded binary's start address.
-const MAX_BINARY_SIZE: usize = BOOTLOADER_START_ADDR - BINARY_START_ADDR;
+/// End of RAM. At startup the bootloader moved itself to the top of RAM,
+/// `fallback::BOOT_RESERVED` bytes below the end, with its stack above it.
+fn memory_end() -> usize {
+    unsafe extern "C" {
+        static __text_beg: u8;
+    }
+
+    (&raw const __text_beg) as usize + fallback::BOOT_RESERVED
+}
+
+/// End of the memory images are received into and loaded to: the fallback
+/// slot lies between it and the bootloader.
+fn load_end() -> usize {
+    fallback::slot_addr(memory_end())
+}
+
+/// Free space between the fallback slot and the loaded binary's start
+/// address.
+fn max_binary_size() -> usize {
+    load_end() - BINARY_START_ADDR
+}
+
+/// Public key images must be signed with, embedded at build time.
+#[cfg(feature = "signed")]
+const PUBLIC_KEY: &[u8; bootimg::ed25519::PUBLIC_KEY_LEN] = include_bytes!(concat!(env!("OUT_DIR"), "/public_key.bin"));
+
+/// Keep asking for the binary every second, a minute at a time, until a
+/// sender shows up.
+const XMODEM_CONFIG: XmodemConfig = XmodemConfig::new()
+    .with_nak_interval(Duration::from_secs(1))
+    .with_byte_timeout(Duration::from_secs(1))
+    .with_start_timeout(Duration::from_secs(60))
+    .with_clock(timer::current_time);
+
+/// Seconds to wait for an upload before booting the fallback image, from
+/// `BOOT_FALLBACK_WAIT` at build time.
+const FALLBACK_WAIT_SECS: u64 = match option_env!("BOOT_FALLBACK_WAIT") {
+    Some(secs) => match u64::from_str_radix(secs, 10) {
+        Ok(secs) => secs,
+        Err(_) => panic!("BOOT_FALLBACK_WAIT must be a number of seconds"),
+    },
+    None => 10,
+};
+
+/// Only wait `FALLBACK_WAIT_SECS` for a sender when there's a fallback image.
+const FALLBACK_XMODEM_CONFIG: XmodemConfig = XMODEM_CONFIG.with_start_timeout(Duration::from_secs(FALLBACK_WAIT_SECS));
 
-/// Branches to the address `addr` unconditionally.
-/// 
+/// Branches to the address `addr` unconditionally, with `cmdline` in `x0` and
+/// `dtb` in `x1`.
 #[inline(always)]
-fn jump_to(addr: *mut u8) -> ! {
-    unsafe { asm!("br {}", in(reg) addr, options(noreturn)) };
+fn jump_to(addr: *mut u8, cmdline: *const u8, dtb: usize) -> ! {
+    unsafe { asm!("br {}", in(reg) addr, in("x0") cmdline, in("x1") dtb, options(noreturn)) };
     
     loop {
         unsafe { asm!("wfe", options(nomem, nostack, preserves_flags)) };
@@ -51,24 +117,205 @@ fn flash_pin(num: u8, milli: u64) {
     }
 }
 
+/// Writes the status line for `event` on the UART.
+fn report<M: fmt::Display>(uart: &mut MiniUart, event: Event<M>) {
+    let _ = writeln!(uart, "{}", event);
+}
+
+/// Reports a rejected image: `error`'s code in slow flashes, after its status
+/// line on the UART.
+fn report_error(uart: &mut MiniUart, error: &bootimg::Error) {
+    report(uart, Event::Error { code: error.code(), kind: error.kind(), message: error });
+    flash_pin(error.code(), 500);
+    timer::spin_sleep(&Duration::from_secs(2));
+}
+
+/// Copies `cmdline` to `CMDLINE` and returns a pointer to it there, or null if
+/// it's empty.
+fn keep_cmdline(cmdline: &str) -> *const u8 {
+    if cmdline.is_empty() {
+        return core::ptr::null();
+    }
+
+    let mut kept = CMDLINE.lock();
+    kept[..cmdline.len()].copy_from_slice(cmdline.as_bytes());
+    kept[cmdline.len()] = 0;
+    kept.as_ptr()
+}
+
+/// Loads the `received` bytes at `BINARY_START`, decompressed already if they
+/// came as an LZ4 frame: an ELF executable or an
+/// image with a header. `checked` is called with the bytes once they've passed
+/// every check, before they're moved.
+fn load(received: usize, checked: impl FnOnce(&[u8])) -> Result<Kernel, bootimg::Error> {
+    let image = unsafe { core::slice::from_raw_parts(BINARY_START, received) };
+    match elf::is_elf(image) {
+        // ELF executables carry no signature.
+        true if cfg!(feature = "signed") => Err(bootimg::Error::Unsigned),
+        true => load_elf(received, checked),
+        false => load_image(received, checked),
+    }
+}
+
+/// Checks the `received` bytes at `BINARY_START` against their header, and
+/// their signature if the `signed` feature is on, keeps their command line and
+/// moves the payload to its load address, maybe over the command line.
+fn load_image(received: usize, checked: impl FnOnce(&[u8])) -> Result<Kernel, bootimg::Error> {
+    let (header, cmdline) = {
+        let image = unsafe { core::slice::from_raw_parts(BINARY_START, received) };
+        #[cfg(feature = "signed")]
+        let verified = Header::verify_signed(image, PUBLIC_KEY);
+        #[cfg(not(feature = "signed"))]
+        let verified = Header::verify(image);
+        let header = verified?.0;
+        header.check_bounds(BINARY_START_ADDR as u64, load_end() as u64)?;
+        checked(image);
+        (header, keep_cmdline(header.cmdline(image)))
+    };
+
+    // The payload and its destination may overlap.
+    let load_addr = header.load_addr as *mut u8;
+    unsafe { core::ptr::copy(BINARY_START.add(HEADER_SIZE), load_addr, header.length as usize) };
+    Ok(Kernel { entry: header.entry as *mut u8, cmdline })
+}
+
+/// Copies the segments of the ELF executable in the `received` bytes at
+/// `BINARY_START` to their physical addresses, zeroing their BSS. ELF
+/// executables have no command line.
+fn load_elf(received: usize, checked: impl FnOnce(&[u8])) -> Result<Kernel, bootimg::Error> {
+    // Segments are usually linked to where the file was received: move the
+    // file to the top of free memory first, and keep segments below it.
+    let staging = (load_end() - received) & !0xF;
+    unsafe { core::ptr::copy(BINARY_START, staging as *mut u8, received) };
+
+    let image = unsafe { core::slice::from_raw_parts(staging as *const u8, received) };
+    let elf = Elf::parse(image)?;
+    for segment in elf.segments() {
+        segment.check_bounds(BINARY_START_ADDR as u64, staging as u64)?;
+    }
+    checked(image);
+
+    for segment in elf.segments() {
+        let data = elf.data(&segment);
+        let dest = segment.paddr as *mut u8;
+        unsafe {
+            core::ptr::copy_nonoverlapping(data.as_ptr(), dest, data.len());
+            core::ptr::write_bytes(dest.add(data.len()), 0, (segment.memsz - segment.filesz) as usize);
+        }
+    }
+
+    Ok(Kernel { entry: elf.entry as *mut u8, cmdline: core::ptr::null() })
+}
+
+/// The fallback slot.
+fn slot() -> &'static mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(load_end() as *mut u8, fallback::SLOT_SIZE) }
+}
+
+/// Loads the `received` bytes at `BINARY_START`, keeping them in the fallback
+/// slot once they've passed the checks. An image that fails them leaves the
+/// slot as it was.
+fn store_and_load(uart: &mut MiniUart, received: usize) -> Result<Kernel, bootimg::Error> {
+    load(received, |image| {
+        if let Err(e) = fallback::store(slot(), image) {
+            report(uart, Event::NotKept { message: e });
+        }
+    })
+}
+
+/// Copies the `len`-byte image in the fallback slot to `BINARY_START` and
+/// loads it.
+fn load_fallback(len: usize) -> Result<Kernel, bootimg::Error> {
+    let image = &slot()[fallback::RECORD_SIZE..fallback::RECORD_SIZE + len];
+    unsafe { core::ptr::copy_nonoverlapping(image.as_ptr(), BINARY_START, len) };
+    load(len, |_| {}).inspect_err(|_| fallback::clear(slot()))
+}
+
+/// Jumps to `kernel` with its command line and the firmware's device tree at
+/// `dtb`.
+fn boot(uart: &mut MiniUart, kernel: Kernel, dtb: usize) -> ! {
+    report(uart, Event::<&str>::Jumping { addr: kernel.entry as u64 });
+    flash_pin(2, 100);
+    timer::spin_sleep(&Duration::from_secs(2));
+    jump_to(kernel.entry, kernel.cmdline, dtb)
+}
+
 #[unsafe(no_mangle)]
-fn kmain() -> ! {
+fn kmain(dtb: usize) -> ! {
     // let mut buf =
     flash_pin(5, 450);
 
     let mut uart = MiniUart::new();
-    uart.set_read_timeout(Duration::from_secs(1));
-    
-    let mut kernel = unsafe { core::slice::from_raw_parts_mut(BINARY_START, MAX_BINARY_SIZE) };
+    uart.set_read_timeout(Duration::from_millis(100));
+    report(&mut uart, Event::<&str>::Ready { load_start: BINARY_START_ADDR as u64, load_end: load_end() as u64 });
 
     loop {
-        match Xmodem::receive(&mut uart, &mut kernel) {
-            Ok(_) => {
-                flash_pin(2, 100);
-                timer::spin_sleep(&Duration::from_secs(2));
-                jump_to(BINARY_START)
+        // Boot the fallback image if no upload comes, unless it keeps failing.
+        let fallback = fallback::load(slot()).map(|(image, failures)| (image.len(), failures));
+        let bootable = fallback.filter(|&(_, failures)| failures < fallback::MAX_FAILURES);
+        if let (Some((_, failures)), None) = (fallback, bootable) {
+            report(&mut uart, Event::<&str>::FallbackFailed { failures });
+        }
+
+        let config = match bootable {
+            Some(_) => FALLBACK_XMODEM_CONFIG,
+            None => XMODEM_CONFIG,
+        };
+        let wait = config.start_timeout().unwrap_or_default();
+        report(&mut uart, Event::<&str>::Receiving { wait: wait.as_secs() });
+
+        let kernel = unsafe { core::slice::from_raw_parts_mut(BINARY_START, max_binary_size()) };
+        let mut sink = Sink::new(kernel);
+        let mut xmodem = Xmodem::new_with_mode(&mut uart, Mode::Crc, |_| {}).with_config(config);
+        match (xmodem.receive_all(&mut sink), sink.error(), bootable) {
+            (Ok(_), _, _) => match sink.finish().and_then(|received| {
+                report(&mut uart, Event::<&str>::Received { bytes: received as u64 });
+                store_and_load(&mut uart, received)
+            }) {
+                Ok(kernel) => boot(&mut uart, kernel, dtb),
+                Err(e) => {
+                    report_error(&mut uart, &e);
+                    continue
+                }
             },
-            Err(_) => {
+            // Commands could load and run anything: they'd defeat signing.
+            (Err(Error::UnexpectedByte(COMMAND_MODE)), _, _) if !cfg!(feature = "signed") => {
+                if let Some(addr) = command::serve(&mut uart) {
+                    report(&mut uart, Event::<&str>::Jumping { addr: addr as u64 });
+                    while !uart.is_idle() {}
+                    jump_to(addr, core::ptr::null(), dtb)
+                }
+                continue
+            }
+            (Err(Error::UnexpectedByte(byte)), _, _) if byte == RESET[0] => {
+                // A host restarting the board for an upload found it already
+                // waiting for one: wait again.
+                let mut rest = [0u8; RESET.len() - 1];
+                let _ = uart.read_exact(&mut rest);
+                continue
+            }
+            (Err(Error::Io(_)), Some(e), _) => {
+                // The image can't be loaded: don't let the sender go on.
+                let _ = xmodem.cancel();
+                report_error(&mut uart, &e);
+                continue
+            }
+            (Err(Error::Timeout), _, Some((len, failures))) => {
+                report(&mut uart, Event::<&str>::Fallback { failures });
+                match load_fallback(len) {
+                    Ok(kernel) => {
+                        // The kernel clears the count once it's up.
+                        fallback::set_failures(slot(), failures + 1);
+                        boot(&mut uart, kernel, dtb)
+                    }
+                    Err(e) => {
+                        report_error(&mut uart, &e);
+                        continue
+                    }
+                }
+            }
+            (Err(e), _, _) => {
+                report(&mut uart, Event::Error { code: TRANSFER_ERROR_CODE, kind: TRANSFER_ERROR_KIND, message: e });
                 flash_pin(10, 100);
                 timer::spin_sleep(&Duration::from_secs(2));
 
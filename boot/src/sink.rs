@@ -0,0 +1,82 @@
+use bootimg::lz4::{self, Decoder};
+use bootimg::Error;
+use shim::io;
+
+enum Output<'a> {
+    /// Nothing received yet.
+    Empty(&'a mut [u8]),
+    /// Bytes received as they are, and how many so far.
+    Raw(&'a mut [u8], usize),
+    /// An LZ4 frame, decompressed as it arrives.
+    Lz4(Decoder<'a>),
+}
+
+/// Where the XMODEM payload goes: copied into a buffer as it is, or, if it
+/// starts with an LZ4 frame, decompressed into it.
+///
+/// A write that fails leaves the reason in `error` and returns an I/O error,
+/// which ends the transfer.
+pub struct Sink<'a> {
+    output: Output<'a>,
+    error: Option<Error>,
+}
+
+impl<'a> Sink<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Sink<'a> {
+        Sink { output: Output::Empty(buf), error: None }
+    }
+
+    /// The reason the last write failed, if it did.
+    pub fn error(&self) -> Option<Error> {
+        self.error
+    }
+
+    /// Returns the number of bytes in the buffer once the transfer is done.
+    pub fn finish(&self) -> Result<usize, Error> {
+        match &self.output {
+            Output::Empty(_) => Ok(0),
+            Output::Raw(_, len) => Ok(*len),
+            Output::Lz4(decoder) => decoder.finish(),
+        }
+    }
+
+    fn write_output(&mut self, data: &[u8]) -> Result<(), Error> {
+        // Packets are at least 128 bytes: the first holds the whole magic.
+        if let Output::Empty(buf) = &mut self.output {
+            let buf = core::mem::take(buf);
+            self.output = match lz4::is_lz4(data) {
+                true => Output::Lz4(Decoder::new(buf)),
+                false => Output::Raw(buf, 0),
+            };
+        }
+
+        match &mut self.output {
+            Output::Lz4(decoder) => decoder.write(data),
+            Output::Raw(buf, len) => {
+                let end = *len + data.len();
+                let capacity = buf.len() as u64;
+                let dest = buf.get_mut(*len..end).ok_or(Error::TooLarge { size: end as u64, capacity })?;
+                dest.copy_from_slice(data);
+                *len = end;
+                Ok(())
+            }
+            Output::Empty(_) => unreachable!(),
+        }
+    }
+}
+
+impl io::Write for Sink<'_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self.write_output(data) {
+            Ok(()) => Ok(data.len()),
+            Err(e) => {
+                self.error = Some(e);
+                Err(io::Error::new(io::ErrorKind::InvalidData, "image rejected"))
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
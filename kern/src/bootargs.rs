@@ -0,0 +1,67 @@
+//! What the kernel was booted with: the command line sent along with the
+//! image, which the bootloader passes in `x0`, and the address of the
+//! firmware's device tree, which it passes in `x1`.
+
+use core::ffi::CStr;
+
+use bootimg::MAX_CMDLINE;
+use mutex::Mutex;
+
+/// The first word of a device tree, big-endian.
+const FDT_MAGIC: u32 = 0xD00D_FEED;
+
+#[derive(Copy, Clone)]
+struct BootArgs {
+    cmdline: &'static str,
+    dtb: Option<usize>,
+}
+
+static BOOT_ARGS: Mutex<BootArgs> = Mutex::new(BootArgs { cmdline: "", dtb: None });
+
+/// Records the arguments the kernel was entered with, `x0` and `x1`.
+///
+/// # Safety
+///
+/// Must be called once, at startup, with the registers as they were at entry.
+pub(crate) unsafe fn init(x0: usize, x1: usize) {
+    // Booted by the firmware directly, the device tree comes in x0.
+    let (cmdline, dtb) = match unsafe { is_fdt(x0) } {
+        true => (0, x0),
+        false => (x0, x1),
+    };
+
+    let cmdline = match cmdline {
+        0 => "",
+        addr => {
+            let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, MAX_CMDLINE + 1) };
+            CStr::from_bytes_until_nul(bytes).ok().and_then(|s| s.to_str().ok()).unwrap_or("")
+        }
+    };
+
+    *BOOT_ARGS.lock() = BootArgs { cmdline, dtb: Some(dtb).filter(|&dtb| dtb != 0) };
+}
+
+/// Returns `true` if a device tree starts at `addr`.
+unsafe fn is_fdt(addr: usize) -> bool {
+    addr != 0 && addr % 4 == 0 && u32::from_be(unsafe { *(addr as *const u32) }) == FDT_MAGIC
+}
+
+/// Returns the kernel's command line, empty if it has none.
+pub fn cmdline() -> &'static str {
+    BOOT_ARGS.lock().cmdline
+}
+
+/// Returns the value of `key` on the command line: `value` for a `key=value`
+/// word, empty for a bare `key`. Returns `None` if `key` isn't there.
+pub fn param(key: &str) -> Option<&'static str> {
+    cmdline().split_whitespace().find_map(|word| match word.split_once('=') {
+        Some((k, value)) if k == key => Some(value),
+        None if word == key => Some(""),
+        _ => None,
+    })
+}
+
+/// Returns the address of the firmware's device tree, if the kernel got one.
+pub fn dtb() -> Option<usize> {
+    BOOT_ARGS.lock().dtb
+}
@@ -45,6 +45,14 @@ impl Console {
     pub fn write_byte(&mut self, byte: u8) {
         self.inner().write_byte(byte);
     }
+
+    /// Reads and drops any bytes the UART device has already received.
+    pub fn discard_input(&mut self) {
+        let uart = self.inner();
+        while uart.has_byte() {
+            uart.read_byte();
+        }
+    }
 }
 
 impl io::Read for Console {
@@ -3,7 +3,7 @@ use core::ptr::write_volatile;
 
 mod panic;
 
-use crate::kmain;
+use crate::{bootargs, kmain};
 
 use core::arch::global_asm;
 
@@ -26,8 +26,10 @@ fn zeros_bss() {
     }
 }
 
+/// Entered from `init.s` with the bootloader's arguments, `x0` and `x1`.
 #[unsafe(no_mangle)]
-unsafe fn kinit() -> ! {
+unsafe extern "C" fn kinit(x0: usize, x1: usize) -> ! {
     zeros_bss();
+    unsafe { bootargs::init(x0, x1) };
     kmain();
 }
\ No newline at end of file
@@ -3,10 +3,12 @@
 .global _start
 
 _start:
+    // x0 and x1 hold the bootloader's arguments: keep them for kinit
+
     // read cpu affinity, start core 0, halt rest
-    mrs     x1, mpidr_el1
-    and     x1, x1, #3
-    cbz     x1, 2f
+    mrs     x2, mpidr_el1
+    and     x2, x2, #3
+    cbz     x2, 2f
 
 1:
     // core affinity != 0, halt it
@@ -15,8 +17,8 @@ _start:
 
 2:
     // set the stack to start before our boot code
-    adr     x1, _start
-    mov     sp, x1
+    adr     x2, _start
+    mov     sp, x2
 
     // jump to kinit, which shouldn't return. halt if it does
     bl      kinit
@@ -5,13 +5,26 @@
 #[cfg(not(test))]
 mod init;
 
+use bootimg::fallback;
 use pi::gpio::Gpio;
+use pi::mailbox;
 
+pub mod bootargs;
 pub mod console;
 pub mod shell;
 
+/// Tells the bootloader this kernel came up, so it keeps booting it when no
+/// upload arrives.
+fn mark_booted() {
+    let memory_end = mailbox::arm_memory_end().unwrap_or(fallback::DEFAULT_MEMORY_END);
+    let slot = fallback::slot_addr(memory_end) as *mut u8;
+    let record = unsafe { core::slice::from_raw_parts_mut(slot, fallback::RECORD_SIZE) };
+    fallback::clear_failures(record);
+}
+
 #[unsafe(no_mangle)]
 fn kmain() -> ! {
+    mark_booted();
     Gpio::new(16).into_output().set();
     shell::shell(&">")
 }
\ No newline at end of file
@@ -1,5 +1,11 @@
+use core::time::Duration;
+
+use bootimg::proto::RESET;
+use pi::{pm, timer};
 use stack_vec::StackVec;
+use xmodem::{Mode, Xmodem, XmodemConfig};
 
+use crate::bootargs;
 use crate::console::{kprint, kprintln, CONSOLE};
 
 /// Error type for `Command` parse failures.
@@ -41,9 +47,54 @@ impl<'a> Command<'a> {
     }
 }
 
+/// Wait up to a minute for the receiver to start an `xsend`, and a second for
+/// each byte once it has.
+const XSEND_CONFIG: XmodemConfig = XmodemConfig::new()
+    .with_start_timeout(Duration::from_secs(60))
+    .with_byte_timeout(Duration::from_secs(1))
+    .with_clock(timer::current_time);
+
+/// Parses a decimal or `0x`-prefixed hexadecimal number.
+fn parse_num(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Sends `len` bytes of memory at `addr` over the console with XMODEM, for
+/// `ttywrite --receive` to write to a file. `args` are `ADDR LEN`.
+fn xsend(args: &[&str]) {
+    let (addr, len) = match args {
+        [addr, len] => match (parse_num(addr), parse_num(len)) {
+            (Some(addr), Some(len)) => (addr, len),
+            _ => return kprintln!("xsend: bad address or length"),
+        },
+        _ => return kprintln!("usage: xsend ADDR LEN"),
+    };
+
+    kprintln!("xsend: sending {} bytes at {:#x}, start the XMODEM receiver", len, addr);
+    let data = unsafe { core::slice::from_raw_parts(addr as *const u8, len) };
+    let result = {
+        let mut console = CONSOLE.lock();
+        // Whatever followed the command line would end the transfer early.
+        console.discard_input();
+        Xmodem::new_with_mode(&mut *console, Mode::Crc1k, |_| {})
+            .with_config(XSEND_CONFIG)
+            .transmit_all(data)
+    };
+
+    match result {
+        Ok(sent) => kprintln!("xsend: sent {} bytes", sent),
+        Err(e) => kprintln!("xsend: failed: {}", e),
+    }
+}
+
 /// Starts a shell using `prefix` as the prefix for each line. This function
 /// returns if the `exit` command is called.
 pub fn shell(prefix: &str) -> ! {
+    // How much of `RESET` was just received.
+    let mut reset = 0;
     loop {
         kprint!("{} ", prefix);
         let mut idx: usize = 0;
@@ -51,6 +102,15 @@ pub fn shell(prefix: &str) -> ! {
         loop {
             let mut c = CONSOLE.lock();
             let b = c.read_byte();
+            reset = match b {
+                b if b == RESET[reset] => reset + 1,
+                b if b == RESET[0] => 1,
+                _ => 0,
+            };
+            if reset == RESET.len() {
+                // The host wants the bootloader back, for a new image.
+                pm::reset();
+            }
             if b == b'\r' || b == b'\n' {
                 buff[idx] = ' ' as u8;
                 break;
@@ -79,6 +139,23 @@ pub fn shell(prefix: &str) -> ! {
                         }
                         kprintln!();
                     },
+                    "bootargs" => {
+                        kprintln!();
+                        match cmd.args.get(1) {
+                            Some(key) => kprintln!("{}", bootargs::param(key).unwrap_or("(not set)")),
+                            None => {
+                                kprintln!("cmdline: {}", bootargs::cmdline());
+                                match bootargs::dtb() {
+                                    Some(addr) => kprintln!("dtb: {:#x}", addr),
+                                    None => kprintln!("dtb: none"),
+                                }
+                            }
+                        }
+                    },
+                    "xsend" => {
+                        kprintln!();
+                        xsend(&cmd.args[1..]);
+                    },
                     "exit" => {
                         panic!();
                     },
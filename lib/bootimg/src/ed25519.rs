@@ -0,0 +1,498 @@
+//! Ed25519 signatures (RFC 8032) and the SHA-512 they're built on.
+//!
+//! Field and group arithmetic follow TweetNaCl: field elements are sixteen
+//! 16-bit limbs and scalar multiplication is a constant-time ladder. That is
+//! slow next to optimized implementations, but verifying one image at boot
+//! takes a fraction of a second.
+
+/// Length of a public key in bytes.
+pub const PUBLIC_KEY_LEN: usize = 32;
+
+/// Length of a secret key (the seed keys are derived from) in bytes.
+pub const SECRET_KEY_LEN: usize = 32;
+
+/// Length of a signature in bytes.
+pub const SIGNATURE_LEN: usize = 64;
+
+/// Returns the public key for `secret`.
+pub fn public_key(secret: &[u8; SECRET_KEY_LEN]) -> [u8; PUBLIC_KEY_LEN] {
+    let (scalar, _) = expand(secret);
+    let mut point = [GF0; 4];
+    scalarbase(&mut point, &scalar);
+    pack(&point)
+}
+
+/// Signs `message` with `secret`.
+pub fn sign(secret: &[u8; SECRET_KEY_LEN], message: &[u8]) -> [u8; SIGNATURE_LEN] {
+    let (scalar, prefix) = expand(secret);
+    let public = public_key(secret);
+
+    let mut r = Sha512::new().update(&prefix).update(message).finish();
+    reduce(&mut r);
+    let mut point = [GF0; 4];
+    scalarbase(&mut point, &r[..32]);
+
+    let mut signature = [0u8; SIGNATURE_LEN];
+    signature[..32].copy_from_slice(&pack(&point));
+
+    let mut h = Sha512::new().update(&signature[..32]).update(&public).update(message).finish();
+    reduce(&mut h);
+
+    // s = r + h * a (mod L)
+    let mut x = [0i64; 64];
+    for (x, &r) in x.iter_mut().zip(&r[..32]) {
+        *x = r as i64;
+    }
+    for i in 0..32 {
+        for j in 0..32 {
+            x[i + j] += h[i] as i64 * scalar[j] as i64;
+        }
+    }
+    mod_l(&mut signature[32..], &mut x);
+    signature
+}
+
+/// Returns `true` if `signature` is `public`'s signature of `message`.
+pub fn verify(public: &[u8; PUBLIC_KEY_LEN], message: &[u8], signature: &[u8; SIGNATURE_LEN]) -> bool {
+    let (r, s) = signature.split_at(32);
+    if !scalar_is_canonical(s) {
+        return false;
+    }
+
+    let mut a = [GF0; 4];
+    if !unpack_neg(&mut a, public) {
+        return false;
+    }
+
+    let mut h = Sha512::new().update(r).update(public).update(message).finish();
+    reduce(&mut h);
+
+    // Check that [s]B - [h]A = R.
+    let mut p = [GF0; 4];
+    scalarmult(&mut p, &mut a, &h[..32]);
+    let mut q = [GF0; 4];
+    scalarbase(&mut q, s);
+    add(&mut p, &q);
+    pack(&p) == r
+}
+
+/// Hashes `secret` into the clamped scalar and the nonce prefix.
+fn expand(secret: &[u8; SECRET_KEY_LEN]) -> ([u8; 32], [u8; 32]) {
+    let d = Sha512::new().update(secret).finish();
+    let mut scalar = [0u8; 32];
+    let mut prefix = [0u8; 32];
+    scalar.copy_from_slice(&d[..32]);
+    prefix.copy_from_slice(&d[32..]);
+    scalar[0] &= 248;
+    scalar[31] &= 127;
+    scalar[31] |= 64;
+    (scalar, prefix)
+}
+
+/// An element of GF(2^255 - 19).
+type Gf = [i64; 16];
+
+const GF0: Gf = [0; 16];
+const GF1: Gf = [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+/// The curve constant d = -121665/121666, and 2d.
+const D: Gf = [
+    0x78a3, 0x1359, 0x4dca, 0x75eb, 0xd8ab, 0x4141, 0x0a4d, 0x0070,
+    0xe898, 0x7779, 0x4079, 0x8cc7, 0xfe73, 0x2b6f, 0x6cee, 0x5203,
+];
+const D2: Gf = [
+    0xf159, 0x26b2, 0x9b94, 0xebd6, 0xb156, 0x8283, 0x149a, 0x00e0,
+    0xd130, 0xeef3, 0x80f2, 0x198e, 0xfce7, 0x56df, 0xd9dc, 0x2406,
+];
+
+/// Coordinates of the base point.
+const X: Gf = [
+    0xd51a, 0x8f25, 0x2d60, 0xc956, 0xa7b2, 0x9525, 0xc760, 0x692c,
+    0xdc5c, 0xfdd6, 0xe231, 0xc0a4, 0x53fe, 0xcd6e, 0x36d3, 0x2169,
+];
+const Y: Gf = [
+    0x6658, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666,
+    0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666,
+];
+
+/// A square root of -1.
+const I: Gf = [
+    0xa0b0, 0x4a0e, 0x1b27, 0xc4ee, 0xe478, 0xad2f, 0x1806, 0x2f43,
+    0xd7a7, 0x3dfb, 0x0099, 0x2b4d, 0xdf0b, 0x4fc1, 0x2480, 0x2b83,
+];
+
+/// The group order L = 2^252 + 27742317777372353535851937790883648493.
+const L: [i64; 32] = [
+    0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9, 0xde, 0x14,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10,
+];
+
+fn carry(o: &mut Gf) {
+    for i in 0..16 {
+        o[i] += 1 << 16;
+        let c = o[i] >> 16;
+        match i {
+            15 => o[0] += 38 * (c - 1),
+            _ => o[i + 1] += c - 1,
+        }
+        o[i] -= c << 16;
+    }
+}
+
+/// Swaps `p` and `q` if `b` is 1, in constant time.
+fn select(p: &mut Gf, q: &mut Gf, b: i64) {
+    let c = !(b - 1);
+    for i in 0..16 {
+        let t = c & (p[i] ^ q[i]);
+        p[i] ^= t;
+        q[i] ^= t;
+    }
+}
+
+fn pack25519(n: &Gf) -> [u8; 32] {
+    let mut t = *n;
+    carry(&mut t);
+    carry(&mut t);
+    carry(&mut t);
+    for _ in 0..2 {
+        let mut m = GF0;
+        m[0] = t[0] - 0xffed;
+        for i in 1..15 {
+            m[i] = t[i] - 0xffff - ((m[i - 1] >> 16) & 1);
+            m[i - 1] &= 0xffff;
+        }
+        m[15] = t[15] - 0x7fff - ((m[14] >> 16) & 1);
+        let b = (m[15] >> 16) & 1;
+        m[14] &= 0xffff;
+        select(&mut t, &mut m, 1 - b);
+    }
+
+    let mut o = [0u8; 32];
+    for i in 0..16 {
+        o[2 * i] = t[i] as u8;
+        o[2 * i + 1] = (t[i] >> 8) as u8;
+    }
+    o
+}
+
+fn unpack25519(n: &[u8; 32]) -> Gf {
+    let mut o = GF0;
+    for i in 0..16 {
+        o[i] = n[2 * i] as i64 + ((n[2 * i + 1] as i64) << 8);
+    }
+    o[15] &= 0x7fff;
+    o
+}
+
+fn parity(a: &Gf) -> u8 {
+    pack25519(a)[0] & 1
+}
+
+fn add_gf(a: &Gf, b: &Gf) -> Gf {
+    core::array::from_fn(|i| a[i] + b[i])
+}
+
+fn sub_gf(a: &Gf, b: &Gf) -> Gf {
+    core::array::from_fn(|i| a[i] - b[i])
+}
+
+fn mul_gf(a: &Gf, b: &Gf) -> Gf {
+    let mut t = [0i64; 31];
+    for i in 0..16 {
+        for j in 0..16 {
+            t[i + j] += a[i] * b[j];
+        }
+    }
+    for i in 0..15 {
+        t[i] += 38 * t[i + 16];
+    }
+
+    let mut o = GF0;
+    o.copy_from_slice(&t[..16]);
+    carry(&mut o);
+    carry(&mut o);
+    o
+}
+
+fn square(a: &Gf) -> Gf {
+    mul_gf(a, a)
+}
+
+fn invert(i: &Gf) -> Gf {
+    let mut c = *i;
+    for a in (0..=253).rev() {
+        c = square(&c);
+        if a != 2 && a != 4 {
+            c = mul_gf(&c, i);
+        }
+    }
+    c
+}
+
+/// Raises `i` to (p - 5) / 8.
+fn pow2523(i: &Gf) -> Gf {
+    let mut c = *i;
+    for a in (0..=250).rev() {
+        c = square(&c);
+        if a != 1 {
+            c = mul_gf(&c, i);
+        }
+    }
+    c
+}
+
+/// Adds `q` to `p`, both in extended coordinates.
+fn add(p: &mut [Gf; 4], q: &[Gf; 4]) {
+    let a = mul_gf(&sub_gf(&p[1], &p[0]), &sub_gf(&q[1], &q[0]));
+    let b = mul_gf(&add_gf(&p[0], &p[1]), &add_gf(&q[0], &q[1]));
+    let c = mul_gf(&mul_gf(&p[3], &q[3]), &D2);
+    let d = mul_gf(&p[2], &q[2]);
+    let d = add_gf(&d, &d);
+    let e = sub_gf(&b, &a);
+    let f = sub_gf(&d, &c);
+    let g = add_gf(&d, &c);
+    let h = add_gf(&b, &a);
+
+    p[0] = mul_gf(&e, &f);
+    p[1] = mul_gf(&h, &g);
+    p[2] = mul_gf(&g, &f);
+    p[3] = mul_gf(&e, &h);
+}
+
+fn cswap(p: &mut [Gf; 4], q: &mut [Gf; 4], b: u8) {
+    for (p, q) in p.iter_mut().zip(q.iter_mut()) {
+        select(p, q, b as i64);
+    }
+}
+
+fn pack(p: &[Gf; 4]) -> [u8; 32] {
+    let zi = invert(&p[2]);
+    let tx = mul_gf(&p[0], &zi);
+    let ty = mul_gf(&p[1], &zi);
+    let mut r = pack25519(&ty);
+    r[31] ^= parity(&tx) << 7;
+    r
+}
+
+/// Sets `p` to `[s]q`, clobbering `q`.
+fn scalarmult(p: &mut [Gf; 4], q: &mut [Gf; 4], s: &[u8]) {
+    *p = [GF0, GF1, GF1, GF0];
+    for i in (0..256).rev() {
+        let b = (s[i / 8] >> (i & 7)) & 1;
+        cswap(p, q, b);
+        let p_copy = *p;
+        add(q, &p_copy);
+        add(p, &p_copy);
+        cswap(p, q, b);
+    }
+}
+
+/// Sets `p` to `[s]B` for the base point B.
+fn scalarbase(p: &mut [Gf; 4], s: &[u8]) {
+    let mut q = [X, Y, GF1, mul_gf(&X, &Y)];
+    scalarmult(p, &mut q, s);
+}
+
+/// Decodes the point `p` and negates it. Returns `false` if `p` isn't on the
+/// curve.
+fn unpack_neg(r: &mut [Gf; 4], p: &[u8; 32]) -> bool {
+    r[2] = GF1;
+    r[1] = unpack25519(p);
+    let num = square(&r[1]);
+    let den = mul_gf(&num, &D);
+    let num = sub_gf(&num, &r[2]);
+    let den = add_gf(&r[2], &den);
+
+    let den2 = square(&den);
+    let den4 = square(&den2);
+    let den6 = mul_gf(&den4, &den2);
+    let mut t = mul_gf(&mul_gf(&den6, &num), &den);
+    t = pow2523(&t);
+    t = mul_gf(&mul_gf(&mul_gf(&t, &num), &den), &den);
+    r[0] = mul_gf(&t, &den);
+
+    if pack25519(&mul_gf(&square(&r[0]), &den)) != pack25519(&num) {
+        r[0] = mul_gf(&r[0], &I);
+    }
+    if pack25519(&mul_gf(&square(&r[0]), &den)) != pack25519(&num) {
+        return false;
+    }
+
+    if parity(&r[0]) == p[31] >> 7 {
+        r[0] = sub_gf(&GF0, &r[0]);
+    }
+    r[3] = mul_gf(&r[0], &r[1]);
+    true
+}
+
+/// Reduces `x` modulo L into the 32 bytes of `r`.
+fn mod_l(r: &mut [u8], x: &mut [i64; 64]) {
+    for i in (32..64).rev() {
+        let mut carry = 0;
+        let mut j = i - 32;
+        while j < i - 12 {
+            x[j] += carry - 16 * x[i] * L[j - (i - 32)];
+            carry = (x[j] + 128) >> 8;
+            x[j] -= carry << 8;
+            j += 1;
+        }
+        x[j] += carry;
+        x[i] = 0;
+    }
+
+    let mut carry = 0;
+    for j in 0..32 {
+        x[j] += carry - (x[31] >> 4) * L[j];
+        carry = x[j] >> 8;
+        x[j] &= 255;
+    }
+    for j in 0..32 {
+        x[j] -= carry * L[j];
+    }
+    for i in 0..32 {
+        x[i + 1] += x[i] >> 8;
+        r[i] = x[i] as u8;
+    }
+}
+
+/// Reduces the 64-byte `r` modulo L in place, leaving the result in its first
+/// 32 bytes.
+fn reduce(r: &mut [u8; 64]) {
+    let mut x: [i64; 64] = core::array::from_fn(|i| r[i] as i64);
+    *r = [0; 64];
+    mod_l(&mut r[..32], &mut x);
+}
+
+/// Returns `true` if the scalar `s` is less than L, as RFC 8032 requires.
+fn scalar_is_canonical(s: &[u8]) -> bool {
+    for i in (0..32).rev() {
+        match (s[i] as i64).cmp(&L[i]) {
+            core::cmp::Ordering::Less => return true,
+            core::cmp::Ordering::Greater => return false,
+            core::cmp::Ordering::Equal => {}
+        }
+    }
+    false
+}
+
+/// Streaming SHA-512.
+pub struct Sha512 {
+    state: [u64; 8],
+    block: [u8; 128],
+    block_len: usize,
+    len: u128,
+}
+
+impl Sha512 {
+    pub fn new() -> Sha512 {
+        Sha512 {
+            state: [
+                0x6a09e667f3bcc908, 0xbb67ae8584caa73b, 0x3c6ef372fe94f82b, 0xa54ff53a5f1d36f1,
+                0x510e527fade682d1, 0x9b05688c2b3e6c1f, 0x1f83d9abfb41bd6b, 0x5be0cd19137e2179,
+            ],
+            block: [0; 128],
+            block_len: 0,
+            len: 0,
+        }
+    }
+
+    /// Hashes `data` after everything so far.
+    pub fn update(mut self, mut data: &[u8]) -> Sha512 {
+        self.len += data.len() as u128;
+        while !data.is_empty() {
+            let n = (128 - self.block_len).min(data.len());
+            self.block[self.block_len..self.block_len + n].copy_from_slice(&data[..n]);
+            self.block_len += n;
+            data = &data[n..];
+            if self.block_len == 128 {
+                self.compress();
+            }
+        }
+        self
+    }
+
+    /// Returns the hash of everything passed to `update`.
+    pub fn finish(mut self) -> [u8; 64] {
+        let bits = self.len * 8;
+        self.block[self.block_len] = 0x80;
+        self.block_len += 1;
+        if self.block_len > 112 {
+            self.block[self.block_len..].fill(0);
+            self.compress();
+        }
+        self.block[self.block_len..112].fill(0);
+        self.block[112..].copy_from_slice(&bits.to_be_bytes());
+        self.compress();
+
+        let mut hash = [0u8; 64];
+        for (out, word) in hash.chunks_exact_mut(8).zip(&self.state) {
+            out.copy_from_slice(&word.to_be_bytes());
+        }
+        hash
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u64; 80];
+        for (w, word) in w.iter_mut().zip(self.block.chunks_exact(8)) {
+            *w = u64::from_be_bytes([word[0], word[1], word[2], word[3], word[4], word[5], word[6], word[7]]);
+        }
+        for i in 16..80 {
+            let s0 = w[i - 15].rotate_right(1) ^ w[i - 15].rotate_right(8) ^ (w[i - 15] >> 7);
+            let s1 = w[i - 2].rotate_right(19) ^ w[i - 2].rotate_right(61) ^ (w[i - 2] >> 6);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..80 {
+            let s1 = e.rotate_right(14) ^ e.rotate_right(18) ^ e.rotate_right(41);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(28) ^ a.rotate_right(34) ^ a.rotate_right(39);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (state, v) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(v);
+        }
+        self.block_len = 0;
+    }
+}
+
+impl Default for Sha512 {
+    fn default() -> Sha512 {
+        Sha512::new()
+    }
+}
+
+const K: [u64; 80] = [
+    0x428a2f98d728ae22, 0x7137449123ef65cd, 0xb5c0fbcfec4d3b2f, 0xe9b5dba58189dbbc,
+    0x3956c25bf348b538, 0x59f111f1b605d019, 0x923f82a4af194f9b, 0xab1c5ed5da6d8118,
+    0xd807aa98a3030242, 0x12835b0145706fbe, 0x243185be4ee4b28c, 0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f, 0x80deb1fe3b1696b1, 0x9bdc06a725c71235, 0xc19bf174cf692694,
+    0xe49b69c19ef14ad2, 0xefbe4786384f25e3, 0x0fc19dc68b8cd5b5, 0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275, 0x4a7484aa6ea6e483, 0x5cb0a9dcbd41fbd4, 0x76f988da831153b5,
+    0x983e5152ee66dfab, 0xa831c66d2db43210, 0xb00327c898fb213f, 0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2, 0xd5a79147930aa725, 0x06ca6351e003826f, 0x142929670a0e6e70,
+    0x27b70a8546d22ffc, 0x2e1b21385c26c926, 0x4d2c6dfc5ac42aed, 0x53380d139d95b3df,
+    0x650a73548baf63de, 0x766a0abb3c77b2a8, 0x81c2c92e47edaee6, 0x92722c851482353b,
+    0xa2bfe8a14cf10364, 0xa81a664bbc423001, 0xc24b8b70d0f89791, 0xc76c51a30654be30,
+    0xd192e819d6ef5218, 0xd69906245565a910, 0xf40e35855771202a, 0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8, 0x1e376c085141ab53, 0x2748774cdf8eeb99, 0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63, 0x4ed8aa4ae3418acb, 0x5b9cca4f7763e373, 0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc, 0x78a5636f43172f60, 0x84c87814a1f0ab72, 0x8cc702081a6439ec,
+    0x90befffa23631e28, 0xa4506cebde82bde9, 0xbef9a3f7b2c67915, 0xc67178f2e372532b,
+    0xca273eceea26619c, 0xd186b8c721c0c207, 0xeada7dd6cde0eb1e, 0xf57d4f7fee6ed178,
+    0x06f067aa72176fba, 0x0a637dc5a2c898a6, 0x113f9804bef90dae, 0x1b710b35131c471b,
+    0x28db77f523047d84, 0x32caab7b40c72493, 0x3c9ebe0a15c9bebc, 0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6, 0x597f299cfc657e2a, 0x5fcb6fab3ad6faec, 0x6c44198c4a475817,
+];
@@ -0,0 +1,142 @@
+//! Just enough ELF64 to load a statically linked AArch64 executable.
+
+use crate::{le_u16, le_u32, le_u64, Error};
+
+/// `e_ident[EI_MAG0..EI_MAG3]`.
+const MAGIC: &[u8; 4] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_AARCH64: u16 = 183;
+const PT_LOAD: u32 = 1;
+
+/// Size of the ELF64 file header.
+const EHDR_SIZE: usize = 64;
+/// Size of an ELF64 program header.
+const PHDR_SIZE: usize = 56;
+
+/// Returns `true` if `image` starts with the ELF magic number.
+pub fn is_elf(image: &[u8]) -> bool {
+    image.starts_with(MAGIC)
+}
+
+/// A `PT_LOAD` segment: `filesz` bytes at `offset` in the file, copied to
+/// `paddr` and followed by zeroes up to `memsz` bytes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub offset: u64,
+    pub paddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+}
+
+impl Segment {
+    /// Checks that the whole segment, including its zeroed tail, fits in
+    /// `start..end`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::BadSegment` otherwise.
+    pub fn check_bounds(&self, start: u64, end: u64) -> Result<(), Error> {
+        match self.paddr.checked_add(self.memsz) {
+            Some(seg_end) if self.paddr >= start && seg_end <= end => Ok(()),
+            _ => Err(Error::BadSegment(self.paddr)),
+        }
+    }
+
+    /// Returns `true` if `addr` lies within the segment in memory.
+    fn contains(&self, addr: u64) -> bool {
+        addr >= self.paddr && addr - self.paddr < self.memsz
+    }
+}
+
+/// A validated ELF64 executable.
+#[derive(Debug, Copy, Clone)]
+pub struct Elf<'a> {
+    image: &'a [u8],
+    /// The entry point, `e_entry`.
+    pub entry: u64,
+    phoff: usize,
+    phentsize: usize,
+    phnum: usize,
+}
+
+impl<'a> Elf<'a> {
+    /// Parses the ELF64 executable in `image`, checking that every `PT_LOAD`
+    /// segment lies within the file and that the entry point lies within one
+    /// of them.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::BadElf` if `image` isn't a well-formed little-endian
+    /// AArch64 executable and `Error::BadEntryPoint` if the entry point isn't
+    /// loaded.
+    pub fn parse(image: &'a [u8]) -> Result<Elf<'a>, Error> {
+        if image.len() < EHDR_SIZE || !is_elf(image) {
+            return Err(Error::BadElf("not an ELF file"));
+        }
+
+        if image[4] != ELFCLASS64 || image[5] != ELFDATA2LSB {
+            return Err(Error::BadElf("not little-endian ELF64"));
+        }
+
+        if le_u16(image, 16) != ET_EXEC {
+            return Err(Error::BadElf("not an executable"));
+        }
+
+        if le_u16(image, 18) != EM_AARCH64 {
+            return Err(Error::BadElf("not AArch64"));
+        }
+
+        let phentsize = le_u16(image, 54) as usize;
+        if phentsize < PHDR_SIZE {
+            return Err(Error::BadElf("program headers too small"));
+        }
+
+        let phoff = le_u64(image, 32);
+        let phnum = le_u16(image, 56) as usize;
+        let table_len = (phentsize * phnum) as u64;
+        if phoff.checked_add(table_len).is_none_or(|end| end > image.len() as u64) {
+            return Err(Error::BadElf("program headers out of bounds"));
+        }
+
+        let elf = Elf { image, entry: le_u64(image, 24), phoff: phoff as usize, phentsize, phnum };
+        let mut entry_loaded = false;
+        for segment in elf.segments() {
+            if segment.filesz > segment.memsz {
+                return Err(Error::BadElf("segment larger in file than in memory"));
+            }
+
+            if segment.offset.checked_add(segment.filesz).is_none_or(|end| end > image.len() as u64) {
+                return Err(Error::BadElf("segment out of bounds"));
+            }
+
+            entry_loaded |= segment.contains(elf.entry);
+        }
+
+        match entry_loaded {
+            true => Ok(elf),
+            false => Err(Error::BadEntryPoint(elf.entry)),
+        }
+    }
+
+    /// The `PT_LOAD` segments, in program header order.
+    pub fn segments(&self) -> impl Iterator<Item = Segment> + 'a {
+        let Elf { image, phoff, phentsize, phnum, .. } = *self;
+        (0..phnum)
+            .map(move |i| phoff + i * phentsize)
+            .filter(move |&ph| le_u32(image, ph) == PT_LOAD)
+            .map(move |ph| Segment {
+                offset: le_u64(image, ph + 8),
+                paddr: le_u64(image, ph + 24),
+                filesz: le_u64(image, ph + 32),
+                memsz: le_u64(image, ph + 40),
+            })
+    }
+
+    /// The bytes of `segment` in the file. `segment` must be one of this
+    /// file's segments.
+    pub fn data(&self, segment: &Segment) -> &'a [u8] {
+        &self.image[segment.offset as usize..(segment.offset + segment.filesz) as usize]
+    }
+}
@@ -0,0 +1,102 @@
+//! The fallback slot: a copy of the last image the bootloader accepted, kept
+//! in reserved RAM across warm resets so it can be booted again when no
+//! upload arrives.
+//!
+//! The slot starts with a record, all fields little-endian:
+//!
+//! | offset | size | field                                  |
+//! |--------|------|----------------------------------------|
+//! | 0      | 4    | magic, `MAGIC`                         |
+//! | 4      | 4    | image length in bytes                  |
+//! | 8      | 4    | CRC-32 of the image                    |
+//! | 12     | 4    | boots since the kernel last cleared it |
+//!
+//! The image follows. The bootloader counts every boot of the stored image,
+//! for want of an upload, and a kernel that comes up clears the count with
+//! `clear_failures`, so a count of `MAX_FAILURES` means the kernel keeps
+//! crashing and shouldn't be booted again without a fresh upload. Storing a
+//! fresh upload starts the count at zero.
+
+use crate::{crc32, le_u32, Error};
+
+/// Memory at the top of RAM the bootloader moves itself to at startup: its
+/// code, data and stack.
+pub const BOOT_RESERVED: usize = 0x10_0000;
+
+/// End of RAM to assume if the VideoCore doesn't say: that of a 1 GiB board
+/// with the GPU's default share.
+pub const DEFAULT_MEMORY_END: usize = 0x3B40_0000;
+
+/// Size of the slot in bytes, record included.
+pub const SLOT_SIZE: usize = 0x80_0000;
+
+/// Magic number at the start of a valid record: `"RPIF"` in memory.
+pub const MAGIC: u32 = u32::from_le_bytes(*b"RPIF");
+
+/// Size of the record before the image.
+pub const RECORD_SIZE: usize = 16;
+
+/// Returns the address of the slot when RAM ends at `memory_end`: just below
+/// the bootloader.
+pub const fn slot_addr(memory_end: usize) -> usize {
+    memory_end - BOOT_RESERVED - SLOT_SIZE
+}
+
+/// Boots of the stored image without the kernel clearing the count after
+/// which it's no longer booted.
+pub const MAX_FAILURES: u32 = 3;
+
+/// Returns the stored image in `slot` and its failure count, or `None` if the
+/// slot holds no intact image.
+pub fn load(slot: &[u8]) -> Option<(&[u8], u32)> {
+    if slot.len() < RECORD_SIZE || le_u32(slot, 0) != MAGIC {
+        return None;
+    }
+
+    let image = slot[RECORD_SIZE..].get(..le_u32(slot, 4) as usize)?;
+    match crc32(image) == le_u32(slot, 8) {
+        true => Some((image, le_u32(slot, 12))),
+        false => None,
+    }
+}
+
+/// Stores `image` in `slot` with a failure count of zero.
+///
+/// # Errors
+///
+/// Returns `Error::TooLarge` if `image` doesn't fit, leaving the slot as it
+/// was.
+pub fn store(slot: &mut [u8], image: &[u8]) -> Result<(), Error> {
+    let capacity = slot.len().saturating_sub(RECORD_SIZE);
+    if image.len() > capacity {
+        return Err(Error::TooLarge { size: image.len() as u64, capacity: capacity as u64 });
+    }
+
+    clear(slot);
+    slot[RECORD_SIZE..RECORD_SIZE + image.len()].copy_from_slice(image);
+    slot[4..8].copy_from_slice(&(image.len() as u32).to_le_bytes());
+    slot[8..12].copy_from_slice(&crc32(image).to_le_bytes());
+    slot[12..16].copy_from_slice(&0u32.to_le_bytes());
+    slot[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+    Ok(())
+}
+
+/// Sets the failure count of the record in `slot`, if there is one.
+pub fn set_failures(slot: &mut [u8], failures: u32) {
+    if slot.len() >= RECORD_SIZE && le_u32(slot, 0) == MAGIC {
+        slot[12..16].copy_from_slice(&failures.to_le_bytes());
+    }
+}
+
+/// Tells the bootloader the kernel it booted came up. Only the record is
+/// needed: `slot` may be just its first `RECORD_SIZE` bytes.
+pub fn clear_failures(slot: &mut [u8]) {
+    set_failures(slot, 0)
+}
+
+/// Empties `slot`.
+pub fn clear(slot: &mut [u8]) {
+    if let Some(magic) = slot.get_mut(..4) {
+        magic.fill(0);
+    }
+}
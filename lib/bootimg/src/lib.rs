@@ -0,0 +1,361 @@
+#![cfg_attr(not(test), no_std)]
+
+//! What the bootloader and `ttywrite` agree on: the header `ttywrite` puts in
+//! front of a kernel image and the bootloader checks before jumping to it, the
+//! ELF executables the bootloader also loads (`elf`), the LZ4 frames either
+//! may be compressed into (`lz4`), the Ed25519 signatures images may carry
+//! (`ed25519`), the command protocol between the two (`proto`) and the status
+//! lines the bootloader reports on (`status`). The bootloader also shares the
+//! slot it keeps the last image in (`fallback`) with the kernel.
+//!
+//! A header is `HEADER_SIZE` bytes, all fields little-endian:
+//!
+//! | offset | size | field                               |
+//! |--------|------|-------------------------------------|
+//! | 0      | 4    | magic, `MAGIC`                      |
+//! | 4      | 2    | format version, `VERSION`           |
+//! | 6      | 2    | command line length in bytes        |
+//! | 8      | 4    | payload length in bytes             |
+//! | 12     | 4    | CRC-32 of payload and command line  |
+//! | 16     | 8    | load address                        |
+//! | 24     | 8    | entry point                         |
+//!
+//! The payload follows immediately, then the command line for the kernel: at
+//! most `MAX_CMDLINE` bytes of UTF-8 without NULs, usually none. The CRC-32 is
+//! IEEE 802.3's. A signed image then has the Ed25519 signature of the header,
+//! payload and command line, `ed25519::SIGNATURE_LEN` bytes. Anything after
+//! that, such as XMODEM padding, is ignored.
+
+#[cfg(test)]
+mod tests;
+
+pub mod ed25519;
+pub mod elf;
+pub mod fallback;
+pub mod lz4;
+pub mod proto;
+pub mod status;
+
+use core::fmt;
+
+/// Magic number at the start of every image: `"RPIB"` on the wire.
+pub const MAGIC: u32 = u32::from_le_bytes(*b"RPIB");
+
+/// The header format version written by this crate.
+pub const VERSION: u16 = 1;
+
+/// Size of a header in bytes.
+pub const HEADER_SIZE: usize = 32;
+
+/// Maximum length of an image's command line in bytes.
+pub const MAX_CMDLINE: usize = 1024;
+
+/// Reasons an image fails validation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Fewer than `HEADER_SIZE` bytes were received.
+    TooShort,
+    /// The image doesn't start with `MAGIC`.
+    BadMagic(u32),
+    /// The header has a format version this crate doesn't know.
+    UnsupportedVersion(u16),
+    /// The header promises `expected` bytes of payload, but only `got` arrived.
+    Truncated { expected: u32, got: usize },
+    /// The payload's CRC-32 doesn't match the header's.
+    BadCrc { expected: u32, got: u32 },
+    /// The payload wouldn't fit where the header asks for it to be loaded.
+    BadLoadAddress(u64),
+    /// The entry point lies outside the loaded payload.
+    BadEntryPoint(u64),
+    /// An ELF image is malformed or not an AArch64 executable; `.0` says
+    /// what's wrong.
+    BadElf(&'static str),
+    /// An ELF segment to be loaded at `.0` wouldn't fit in memory.
+    BadSegment(u64),
+    /// The image, `size` bytes at least, doesn't fit in the `capacity` bytes
+    /// available for it.
+    TooLarge { size: u64, capacity: u64 },
+    /// An LZ4-compressed image is malformed; `.0` says what's wrong.
+    BadLz4(&'static str),
+    /// A signed image was required, but no signature follows the payload.
+    Unsigned,
+    /// The image's signature doesn't match its contents and the public key.
+    BadSignature,
+    /// The image's command line is too long or not a string; `.0` says
+    /// what's wrong.
+    BadCmdline(&'static str),
+}
+
+impl Error {
+    /// A small, stable number identifying the error, for reporting on devices
+    /// with nothing but an LED.
+    pub fn code(&self) -> u8 {
+        match self {
+            Error::TooShort => 1,
+            Error::BadMagic(_) => 2,
+            Error::UnsupportedVersion(_) => 3,
+            Error::Truncated { .. } => 4,
+            Error::BadCrc { .. } => 5,
+            Error::BadLoadAddress(_) => 6,
+            Error::BadEntryPoint(_) => 7,
+            Error::BadElf(_) => 8,
+            Error::BadSegment(_) => 9,
+            Error::TooLarge { .. } => 10,
+            Error::BadLz4(_) => 11,
+            Error::Unsigned => 12,
+            Error::BadSignature => 13,
+            Error::BadCmdline(_) => 14,
+        }
+    }
+
+    /// A short, stable name for the kind of error, for `status` lines.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::TooShort => "too-short",
+            Error::BadMagic(_) => "bad-magic",
+            Error::UnsupportedVersion(_) => "unsupported-version",
+            Error::Truncated { .. } => "truncated",
+            Error::BadCrc { .. } => "bad-crc",
+            Error::BadLoadAddress(_) => "bad-load-address",
+            Error::BadEntryPoint(_) => "bad-entry-point",
+            Error::BadElf(_) => "bad-elf",
+            Error::BadSegment(_) => "bad-segment",
+            Error::TooLarge { .. } => "too-large",
+            Error::BadLz4(_) => "bad-lz4",
+            Error::Unsigned => "unsigned",
+            Error::BadSignature => "bad-signature",
+            Error::BadCmdline(_) => "bad-cmdline",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::TooShort => write!(f, "image shorter than its header"),
+            Error::BadMagic(magic) => write!(f, "bad magic {:#010x}", magic),
+            Error::UnsupportedVersion(version) => write!(f, "unsupported header version {}", version),
+            Error::Truncated { expected, got } => {
+                write!(f, "truncated payload: expected {} bytes, got {}", expected, got)
+            }
+            Error::BadCrc { expected, got } => {
+                write!(f, "bad payload CRC-32: expected {:#010x}, got {:#010x}", expected, got)
+            }
+            Error::BadLoadAddress(addr) => write!(f, "bad load address {:#x}", addr),
+            Error::BadEntryPoint(addr) => write!(f, "bad entry point {:#x}", addr),
+            Error::BadElf(what) => write!(f, "bad ELF image: {}", what),
+            Error::BadSegment(addr) => write!(f, "bad ELF segment at {:#x}", addr),
+            Error::TooLarge { size, capacity } => {
+                write!(f, "image too large: at least {} bytes for {} available", size, capacity)
+            }
+            Error::BadLz4(what) => write!(f, "bad LZ4 frame: {}", what),
+            Error::Unsigned => write!(f, "image isn't signed"),
+            Error::BadSignature => write!(f, "bad image signature"),
+            Error::BadCmdline(what) => write!(f, "bad command line: {}", what),
+        }
+    }
+}
+
+/// A boot image header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Header {
+    /// Length of the payload in bytes.
+    pub length: u32,
+    /// Length of the command line after the payload in bytes.
+    pub cmdline_len: u16,
+    /// CRC-32 of the payload and command line.
+    pub crc32: u32,
+    /// Address the payload is to be copied to.
+    pub load_addr: u64,
+    /// Address to jump to once the payload is loaded.
+    pub entry: u64,
+}
+
+impl Header {
+    /// Returns the header for `payload`, to be loaded at `load_addr` and
+    /// entered at `entry`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `payload` is 4 GiB or larger.
+    pub fn new(payload: &[u8], load_addr: u64, entry: u64) -> Header {
+        Header::new_with_cmdline(payload, "", load_addr, entry)
+    }
+
+    /// Like `new`, but with `cmdline` following the payload.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `payload` is 4 GiB or larger, or if `cmdline` is longer than
+    /// `MAX_CMDLINE` or has a NUL.
+    pub fn new_with_cmdline(payload: &[u8], cmdline: &str, load_addr: u64, entry: u64) -> Header {
+        let length = u32::try_from(payload.len()).expect("payload smaller than 4 GiB");
+        assert!(check_cmdline(cmdline.as_bytes()).is_ok(), "command line too long or with a NUL");
+        let crc32 = crc32_update(crc32(payload), cmdline.as_bytes());
+        Header { length, cmdline_len: cmdline.len() as u16, crc32, load_addr, entry }
+    }
+
+    /// Encodes this header.
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut buf = [0u8; HEADER_SIZE];
+        buf[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        buf[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        buf[6..8].copy_from_slice(&self.cmdline_len.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.length.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.crc32.to_le_bytes());
+        buf[16..24].copy_from_slice(&self.load_addr.to_le_bytes());
+        buf[24..32].copy_from_slice(&self.entry.to_le_bytes());
+        buf
+    }
+
+    /// Decodes the header at the start of `image`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::TooShort`, `Error::BadMagic` or
+    /// `Error::UnsupportedVersion` if `image` doesn't start with a header this
+    /// crate understands.
+    pub fn parse(image: &[u8]) -> Result<Header, Error> {
+        if image.len() < HEADER_SIZE {
+            return Err(Error::TooShort);
+        }
+
+        let magic = le_u32(image, 0);
+        if magic != MAGIC {
+            return Err(Error::BadMagic(magic));
+        }
+
+        let version = le_u16(image, 4);
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        Ok(Header {
+            length: le_u32(image, 8),
+            cmdline_len: le_u16(image, 6),
+            crc32: le_u32(image, 12),
+            load_addr: le_u64(image, 16),
+            entry: le_u64(image, 24),
+        })
+    }
+
+    /// Decodes and checks the header at the start of `image` against the
+    /// payload and command line that follow it, and returns the header and
+    /// the payload; see `cmdline` for the command line.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from `parse`, `Error::Truncated` if the payload and
+    /// command line are shorter than the header says, `Error::BadCrc` if they
+    /// are corrupt and `Error::BadCmdline` if the command line isn't one.
+    pub fn verify(image: &[u8]) -> Result<(Header, &[u8]), Error> {
+        let header = Header::parse(image)?;
+        let rest = &image[HEADER_SIZE..];
+        let expected = header.length as usize + header.cmdline_len as usize;
+        let contents = rest.get(..expected).ok_or(Error::Truncated {
+            expected: expected as u32,
+            got: rest.len(),
+        })?;
+
+        let crc = crc32(contents);
+        if crc != header.crc32 {
+            return Err(Error::BadCrc { expected: header.crc32, got: crc });
+        }
+
+        let (payload, cmdline) = contents.split_at(header.length as usize);
+        check_cmdline(cmdline)?;
+        Ok((header, payload))
+    }
+
+    /// Returns the command line of `image`, which `verify` accepted with this
+    /// header.
+    pub fn cmdline<'a>(&self, image: &'a [u8]) -> &'a str {
+        let start = HEADER_SIZE + self.length as usize;
+        let cmdline = image.get(start..start + self.cmdline_len as usize).unwrap_or_default();
+        core::str::from_utf8(cmdline).unwrap_or_default()
+    }
+
+    /// Like `verify`, but also checks the signature after the payload against
+    /// `public_key`.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from `verify`, `Error::Unsigned` if the signature is
+    /// missing and `Error::BadSignature` if it doesn't verify.
+    pub fn verify_signed<'a>(
+        image: &'a [u8],
+        public_key: &[u8; ed25519::PUBLIC_KEY_LEN],
+    ) -> Result<(Header, &'a [u8]), Error> {
+        let (header, payload) = Header::verify(image)?;
+        let (signed, rest) = image.split_at(HEADER_SIZE + payload.len() + header.cmdline_len as usize);
+        let signature = rest.first_chunk::<{ ed25519::SIGNATURE_LEN }>().ok_or(Error::Unsigned)?;
+        match ed25519::verify(public_key, signed, signature) {
+            true => Ok((header, payload)),
+            false => Err(Error::BadSignature),
+        }
+    }
+
+    /// Checks that the payload fits in `start..end` at its load address and
+    /// that the entry point lies within it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::BadLoadAddress` or `Error::BadEntryPoint` otherwise.
+    pub fn check_bounds(&self, start: u64, end: u64) -> Result<(), Error> {
+        let load_end = self.load_addr.checked_add(self.length as u64);
+        if self.load_addr < start || load_end.is_none_or(|load_end| load_end > end) {
+            return Err(Error::BadLoadAddress(self.load_addr));
+        }
+
+        if self.entry < self.load_addr || self.entry >= self.load_addr + self.length as u64 {
+            return Err(Error::BadEntryPoint(self.entry));
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that `cmdline` can be passed to a kernel: that it isn't longer than
+/// `MAX_CMDLINE` and is UTF-8 without NULs, so it can be NUL-terminated.
+fn check_cmdline(cmdline: &[u8]) -> Result<(), Error> {
+    if cmdline.len() > MAX_CMDLINE {
+        return Err(Error::BadCmdline("too long"));
+    }
+
+    match core::str::from_utf8(cmdline) {
+        Ok(s) if s.contains('\0') => Err(Error::BadCmdline("contains a NUL")),
+        Ok(_) => Ok(()),
+        Err(_) => Err(Error::BadCmdline("not UTF-8")),
+    }
+}
+
+/// Reads a little-endian `u16` at `at` in `buf`.
+pub(crate) fn le_u16(buf: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([buf[at], buf[at + 1]])
+}
+
+/// Reads a little-endian `u32` at `at` in `buf`.
+pub(crate) fn le_u32(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]])
+}
+
+/// Reads a little-endian `u64` at `at` in `buf`.
+pub(crate) fn le_u64(buf: &[u8], at: usize) -> u64 {
+    le_u32(buf, at) as u64 | (le_u32(buf, at + 4) as u64) << 32
+}
+
+/// Computes the CRC-32 (IEEE 802.3) of `buf`.
+pub fn crc32(buf: &[u8]) -> u32 {
+    crc32_update(0, buf)
+}
+
+/// Continues the CRC-32 `crc` of some bytes over `buf`, which follow them.
+pub fn crc32_update(crc: u32, buf: &[u8]) -> u32 {
+    !buf.iter().fold(!crc, |crc, &b| {
+        let mut crc = crc ^ b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+        crc
+    })
+}
@@ -0,0 +1,530 @@
+//! LZ4 frames: a compressor for `ttywrite` and a streaming decoder that
+//! writes straight into the bootloader's load area.
+//!
+//! Frames use the standard format, so images can also be compressed with the
+//! `lz4` tool. The decoder checks the header and content checksums but skips
+//! block checksums, and doesn't support dictionaries.
+
+use crate::{le_u32, Error};
+
+/// Magic number at the start of every LZ4 frame.
+pub const MAGIC: u32 = 0x184D_2204;
+
+/// Maximum size of a block this module's compressor writes: 4 MiB.
+const BLOCK_SIZE: usize = 4 << 20;
+
+/// Frame descriptor flags.
+const FLG_VERSION: u8 = 0b0100_0000;
+const FLG_VERSION_MASK: u8 = 0b1100_0000;
+const FLG_INDEPENDENT: u8 = 0b0010_0000;
+const FLG_BLOCK_CHECKSUM: u8 = 0b0001_0000;
+const FLG_CONTENT_SIZE: u8 = 0b0000_1000;
+const FLG_CONTENT_CHECKSUM: u8 = 0b0000_0100;
+const FLG_DICT_ID: u8 = 0b0000_0001;
+
+/// Block descriptor for 4 MiB blocks.
+const BD_4M: u8 = 7 << 4;
+
+/// High bit of a block size: the block is stored uncompressed.
+const UNCOMPRESSED: u32 = 1 << 31;
+
+/// Matches are at least this long.
+const MIN_MATCH: usize = 4;
+
+/// The last match must start at least this far from the end of a block...
+const MF_LIMIT: usize = 12;
+
+/// ...and the last this many bytes of a block are always literals.
+const LAST_LITERALS: usize = 5;
+
+const HASH_LOG: u32 = 12;
+
+/// Returns `true` if `data` starts with an LZ4 frame.
+pub fn is_lz4(data: &[u8]) -> bool {
+    data.len() >= 4 && le_u32(data, 0) == MAGIC
+}
+
+/// Returns the most bytes compressing `len` bytes with `compress` can take.
+pub const fn max_compressed_len(len: usize) -> usize {
+    let blocks = len.div_ceil(BLOCK_SIZE);
+    // Header, end mark and content checksum, then each block's size and data.
+    4 + 2 + 8 + 1 + 4 + 4 + 4 * blocks + len
+}
+
+/// Compresses `input` into an LZ4 frame in `out` and returns its length. The
+/// frame records the content size and checksum.
+///
+/// # Panics
+///
+/// Panics if `out` is shorter than `max_compressed_len(input.len())`.
+pub fn compress(input: &[u8], out: &mut [u8]) -> usize {
+    assert!(out.len() >= max_compressed_len(input.len()), "output buffer too small");
+
+    out[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+    out[4] = FLG_VERSION | FLG_INDEPENDENT | FLG_CONTENT_SIZE | FLG_CONTENT_CHECKSUM;
+    out[5] = BD_4M;
+    out[6..14].copy_from_slice(&(input.len() as u64).to_le_bytes());
+    out[14] = (xxh32(&out[4..14], 0) >> 8) as u8;
+
+    let mut len = 15;
+    for block in input.chunks(BLOCK_SIZE) {
+        // Blocks that don't shrink are stored as they are.
+        let data = &mut out[len + 4..len + 4 + block.len()];
+        let size = match compress_block(block, data) {
+            Some(n) => n as u32,
+            None => {
+                data.copy_from_slice(block);
+                block.len() as u32 | UNCOMPRESSED
+            }
+        };
+
+        out[len..len + 4].copy_from_slice(&size.to_le_bytes());
+        len += 4 + (size & !UNCOMPRESSED) as usize;
+    }
+
+    out[len..len + 4].copy_from_slice(&0u32.to_le_bytes());
+    out[len + 4..len + 8].copy_from_slice(&xxh32(input, 0).to_le_bytes());
+    len + 8
+}
+
+/// Compresses `block` into `out` with greedy matching, returning the length of
+/// the result, or `None` if it wouldn't be shorter than `block`.
+fn compress_block(block: &[u8], out: &mut [u8]) -> Option<usize> {
+    let mut table = [u32::MAX; 1 << HASH_LOG];
+    let hash = |seq: u32| (seq.wrapping_mul(2_654_435_761) >> (32 - HASH_LOG)) as usize;
+
+    let mut writer = BlockWriter { out, len: 0 };
+    let mut anchor = 0;
+    let mut i = 0;
+    while i + MF_LIMIT < block.len() {
+        let seq = le_u32(block, i);
+        let candidate = core::mem::replace(&mut table[hash(seq)], i as u32) as usize;
+        if candidate == u32::MAX as usize || i - candidate > u16::MAX as usize || le_u32(block, candidate) != seq {
+            i += 1;
+            continue;
+        }
+
+        let mut match_len = MIN_MATCH;
+        while i + match_len < block.len() - LAST_LITERALS && block[candidate + match_len] == block[i + match_len] {
+            match_len += 1;
+        }
+
+        writer.sequence(&block[anchor..i], Some(((i - candidate) as u16, match_len)))?;
+        i += match_len;
+        anchor = i;
+    }
+
+    writer.sequence(&block[anchor..], None)?;
+    Some(writer.len)
+}
+
+/// Writes sequences to a compressed block, giving up once the block is as
+/// long as `out`.
+struct BlockWriter<'a> {
+    out: &'a mut [u8],
+    len: usize,
+}
+
+impl BlockWriter<'_> {
+    /// Writes `literals` followed by a match at `offset` of `len` bytes, if
+    /// any.
+    fn sequence(&mut self, literals: &[u8], matched: Option<(u16, usize)>) -> Option<()> {
+        let match_len = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+        self.byte((literals.len().min(15) << 4 | match_len.min(15)) as u8)?;
+        self.length(literals.len())?;
+        for &b in literals {
+            self.byte(b)?;
+        }
+
+        if let Some((offset, _)) = matched {
+            offset.to_le_bytes().iter().try_for_each(|&b| self.byte(b))?;
+            self.length(match_len)?;
+        }
+
+        Some(())
+    }
+
+    /// Writes the extra bytes of a length that doesn't fit in a token.
+    fn length(&mut self, len: usize) -> Option<()> {
+        if len < 15 {
+            return Some(());
+        }
+
+        let mut rest = len - 15;
+        while rest >= 255 {
+            self.byte(255)?;
+            rest -= 255;
+        }
+        self.byte(rest as u8)
+    }
+
+    fn byte(&mut self, b: u8) -> Option<()> {
+        *self.out.get_mut(self.len)? = b;
+        self.len += 1;
+        match self.len < self.out.len() {
+            true => Some(()),
+            false => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Magic,
+    Descriptor,
+    BlockSize,
+    Stored,
+    Token,
+    LiteralLength,
+    Literals,
+    Offset,
+    MatchLength,
+    BlockChecksum,
+    ContentChecksum,
+    Done,
+}
+
+/// Streaming LZ4 frame decoder writing into a fixed buffer.
+///
+/// Matches may reach back anywhere in the output, so frames with linked
+/// blocks decode too. Anything after the end of the frame, such as XMODEM
+/// padding, is ignored.
+pub struct Decoder<'a> {
+    out: &'a mut [u8],
+    pos: usize,
+    state: State,
+    /// The frame descriptor, for its checksum.
+    descriptor: [u8; 15],
+    descriptor_len: usize,
+    content_size: Option<u64>,
+    /// A multi-byte field being read, and how many of its bytes have been.
+    field: u64,
+    field_len: usize,
+    /// Bytes left in the current block.
+    block_left: usize,
+    literals: usize,
+    match_len: usize,
+    offset: usize,
+}
+
+impl<'a> Decoder<'a> {
+    /// Returns a decoder writing into `out`.
+    pub fn new(out: &'a mut [u8]) -> Decoder<'a> {
+        Decoder {
+            out,
+            pos: 0,
+            state: State::Magic,
+            descriptor: [0; 15],
+            descriptor_len: 0,
+            content_size: None,
+            field: 0,
+            field_len: 0,
+            block_left: 0,
+            literals: 0,
+            match_len: 0,
+            offset: 0,
+        }
+    }
+
+    /// The number of bytes decoded so far.
+    pub fn len(&self) -> usize {
+        self.pos
+    }
+
+    /// Returns `true` if nothing has been decoded yet.
+    pub fn is_empty(&self) -> bool {
+        self.pos == 0
+    }
+
+    /// Decodes `input`, the next part of the frame.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::TooLarge` if the decoded data doesn't fit in the
+    /// output buffer and `Error::BadLz4` if the frame is malformed. The
+    /// decoder is of no further use after an error.
+    pub fn write(&mut self, input: &[u8]) -> Result<(), Error> {
+        for &byte in input {
+            if self.state == State::Done {
+                break;
+            }
+            self.push(byte)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the whole frame has been decoded and returns the length of
+    /// the decoded data.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::BadLz4` if the frame is incomplete or its content size
+    /// doesn't match.
+    pub fn finish(&self) -> Result<usize, Error> {
+        if self.state != State::Done {
+            return Err(Error::BadLz4("truncated frame"));
+        }
+
+        match self.content_size {
+            Some(size) if size != self.pos as u64 => Err(Error::BadLz4("wrong content size")),
+            _ => Ok(self.pos),
+        }
+    }
+
+    /// Accumulates `byte` into a little-endian field of `n` bytes, returning
+    /// the field once complete.
+    fn field(&mut self, byte: u8, n: usize) -> Option<u64> {
+        self.field |= (byte as u64) << (8 * self.field_len);
+        self.field_len += 1;
+        if self.field_len < n {
+            return None;
+        }
+
+        let field = self.field;
+        self.field = 0;
+        self.field_len = 0;
+        Some(field)
+    }
+
+    fn flags(&self) -> u8 {
+        self.descriptor[0]
+    }
+
+    fn push(&mut self, byte: u8) -> Result<(), Error> {
+        if matches!(self.state, State::Stored | State::Token | State::LiteralLength | State::Literals
+            | State::Offset | State::MatchLength)
+        {
+            if self.block_left == 0 {
+                return Err(Error::BadLz4("truncated block"));
+            }
+            self.block_left -= 1;
+        }
+
+        match self.state {
+            State::Magic => {
+                if let Some(magic) = self.field(byte, 4) {
+                    if magic != MAGIC as u64 {
+                        return Err(Error::BadLz4("bad magic"));
+                    }
+                    self.state = State::Descriptor;
+                }
+            }
+            State::Descriptor => self.descriptor_byte(byte)?,
+            State::BlockSize => {
+                if let Some(size) = self.field(byte, 4) {
+                    let size = size as u32;
+                    self.block_left = (size & !UNCOMPRESSED) as usize;
+                    self.state = match (size, size & UNCOMPRESSED != 0) {
+                        (0, _) if self.flags() & FLG_CONTENT_CHECKSUM != 0 => State::ContentChecksum,
+                        (0, _) => State::Done,
+                        (_, true) => State::Stored,
+                        (_, false) => State::Token,
+                    };
+                }
+            }
+            State::Stored => {
+                self.output(byte)?;
+                if self.block_left == 0 {
+                    self.end_block();
+                }
+            }
+            State::Token => {
+                self.literals = (byte >> 4) as usize;
+                self.match_len = (byte & 0xF) as usize + MIN_MATCH;
+                self.state = match self.literals {
+                    15 => State::LiteralLength,
+                    _ => State::Literals,
+                };
+                if self.state == State::Literals && self.literals == 0 {
+                    self.end_literals();
+                }
+            }
+            State::LiteralLength => {
+                self.literals += byte as usize;
+                if byte != 255 {
+                    self.state = State::Literals;
+                    if self.literals == 0 {
+                        self.end_literals();
+                    }
+                }
+            }
+            State::Literals => {
+                self.output(byte)?;
+                self.literals -= 1;
+                if self.literals == 0 {
+                    self.end_literals();
+                }
+            }
+            State::Offset => {
+                if let Some(offset) = self.field(byte, 2) {
+                    self.offset = offset as usize;
+                    if self.offset == 0 || self.offset > self.pos {
+                        return Err(Error::BadLz4("bad match offset"));
+                    }
+
+                    match self.match_len {
+                        19 => self.state = State::MatchLength,
+                        _ => self.copy_match()?,
+                    }
+                }
+            }
+            State::MatchLength => {
+                self.match_len += byte as usize;
+                if byte != 255 {
+                    self.copy_match()?;
+                }
+            }
+            State::BlockChecksum => {
+                if self.field(byte, 4).is_some() {
+                    self.state = State::BlockSize;
+                }
+            }
+            State::ContentChecksum => {
+                if let Some(checksum) = self.field(byte, 4) {
+                    if xxh32(&self.out[..self.pos], 0) as u64 != checksum {
+                        return Err(Error::BadLz4("bad content checksum"));
+                    }
+                    self.state = State::Done;
+                }
+            }
+            State::Done => {}
+        }
+
+        Ok(())
+    }
+
+    /// Reads the frame descriptor: flags, block descriptor, content size if
+    /// any, and its checksum.
+    fn descriptor_byte(&mut self, byte: u8) -> Result<(), Error> {
+        if self.descriptor_len == 1 && self.flags() & FLG_VERSION_MASK != FLG_VERSION {
+            return Err(Error::BadLz4("unsupported version"));
+        }
+
+        if self.descriptor_len == 1 && self.flags() & FLG_DICT_ID != 0 {
+            return Err(Error::BadLz4("dictionaries unsupported"));
+        }
+
+        let len = match self.descriptor_len > 0 && self.flags() & FLG_CONTENT_SIZE != 0 {
+            true => 10,
+            false => 2,
+        };
+
+        if self.descriptor_len < len {
+            self.descriptor[self.descriptor_len] = byte;
+            self.descriptor_len += 1;
+            return Ok(());
+        }
+
+        if (xxh32(&self.descriptor[..len], 0) >> 8) as u8 != byte {
+            return Err(Error::BadLz4("bad header checksum"));
+        }
+
+        if len == 10 {
+            let mut size = [0u8; 8];
+            size.copy_from_slice(&self.descriptor[2..10]);
+            let size = u64::from_le_bytes(size);
+            if size > self.out.len() as u64 {
+                return Err(Error::TooLarge { size, capacity: self.out.len() as u64 });
+            }
+            self.content_size = Some(size);
+        }
+
+        self.state = State::BlockSize;
+        Ok(())
+    }
+
+    /// Moves on from a sequence's literals to its match, or to the next block
+    /// if this was the block's last sequence.
+    fn end_literals(&mut self) {
+        match self.block_left {
+            0 => self.end_block(),
+            _ => self.state = State::Offset,
+        }
+    }
+
+    fn end_block(&mut self) {
+        self.state = match self.flags() & FLG_BLOCK_CHECKSUM != 0 {
+            true => State::BlockChecksum,
+            false => State::BlockSize,
+        };
+    }
+
+    fn output(&mut self, byte: u8) -> Result<(), Error> {
+        self.reserve(1)?;
+        self.out[self.pos] = byte;
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Copies the current match, which may overlap its own output.
+    fn copy_match(&mut self) -> Result<(), Error> {
+        self.reserve(self.match_len)?;
+        for _ in 0..self.match_len {
+            self.out[self.pos] = self.out[self.pos - self.offset];
+            self.pos += 1;
+        }
+
+        match self.block_left {
+            0 => self.end_block(),
+            _ => self.state = State::Token,
+        }
+        Ok(())
+    }
+
+    fn reserve(&self, n: usize) -> Result<(), Error> {
+        match self.pos + n <= self.out.len() {
+            true => Ok(()),
+            false => Err(Error::TooLarge { size: (self.pos + n) as u64, capacity: self.out.len() as u64 }),
+        }
+    }
+}
+
+/// Computes the XXH32 hash of `data` with `seed`.
+pub fn xxh32(data: &[u8], seed: u32) -> u32 {
+    const P1: u32 = 2_654_435_761;
+    const P2: u32 = 2_246_822_519;
+    const P3: u32 = 3_266_489_917;
+    const P4: u32 = 668_265_263;
+    const P5: u32 = 374_761_393;
+
+    let round = |acc: u32, lane: u32| acc.wrapping_add(lane.wrapping_mul(P2)).rotate_left(13).wrapping_mul(P1);
+
+    let mut stripes = data.chunks_exact(16);
+    let mut h = match data.len() >= 16 {
+        true => {
+            let mut v = [
+                seed.wrapping_add(P1).wrapping_add(P2),
+                seed.wrapping_add(P2),
+                seed,
+                seed.wrapping_sub(P1),
+            ];
+            for stripe in &mut stripes {
+                for (i, v) in v.iter_mut().enumerate() {
+                    *v = round(*v, le_u32(stripe, 4 * i));
+                }
+            }
+            v[0].rotate_left(1)
+                .wrapping_add(v[1].rotate_left(7))
+                .wrapping_add(v[2].rotate_left(12))
+                .wrapping_add(v[3].rotate_left(18))
+        }
+        false => seed.wrapping_add(P5),
+    };
+
+    h = h.wrapping_add(data.len() as u32);
+    let rest = stripes.remainder();
+    let mut words = rest.chunks_exact(4);
+    for word in &mut words {
+        h = h.wrapping_add(le_u32(word, 0).wrapping_mul(P3)).rotate_left(17).wrapping_mul(P4);
+    }
+    for &b in words.remainder() {
+        h = h.wrapping_add((b as u32).wrapping_mul(P5)).rotate_left(11).wrapping_mul(P1);
+    }
+
+    h ^= h >> 15;
+    h = h.wrapping_mul(P2);
+    h ^= h >> 13;
+    h = h.wrapping_mul(P3);
+    h ^ (h >> 16)
+}
@@ -0,0 +1,294 @@
+//! The bootloader's command protocol.
+//!
+//! While the bootloader waits for an XMODEM upload, sending `COMMAND_MODE` in
+//! place of the first packet switches it to taking requests instead. Every
+//! request gets exactly one response. Both travel in frames:
+//!
+//! | size | field                                          |
+//! |------|------------------------------------------------|
+//! | 1    | `SYNC`                                         |
+//! | 1    | kind: a request code, or a response `Status`   |
+//! | 2    | payload length, at most `MAX_PAYLOAD`          |
+//! | n    | payload                                        |
+//! | 4    | CRC-32 of the kind, length and payload         |
+//!
+//! Multi-byte fields are little-endian. Bytes before `SYNC` are skipped, so
+//! the `C`s of the XMODEM handshake don't get in the way.
+
+use crate::{crc32, le_u16, le_u32, le_u64};
+
+/// Byte that switches a waiting bootloader from XMODEM to command mode.
+pub const COMMAND_MODE: u8 = 0xB0;
+
+/// Sequence a host sends to restart the board for a new upload: a kernel
+/// that watches for it resets the board, and a bootloader already waiting
+/// for an upload skips it and waits again. It starts with a byte that is
+/// neither an XMODEM packet's first byte nor `COMMAND_MODE`.
+pub const RESET: [u8; 6] = [0xB1, b'R', b'E', b'S', b'E', b'T'];
+
+/// First byte of every frame.
+pub const SYNC: u8 = 0xA5;
+
+/// Version of this protocol.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Maximum number of data bytes in an upload or peek.
+pub const MAX_DATA: usize = 1024;
+
+/// Maximum payload length of a frame: an address and `MAX_DATA` bytes.
+pub const MAX_PAYLOAD: usize = 8 + MAX_DATA;
+
+/// Maximum length of a whole frame.
+pub const MAX_FRAME: usize = 4 + MAX_PAYLOAD + 4;
+
+const HELLO: u8 = 0x01;
+const UPLOAD: u8 = 0x02;
+const CRC: u8 = 0x03;
+const PEEK: u8 = 0x04;
+const GO: u8 = 0x05;
+
+/// A request from the host.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Request<'a> {
+    /// Asks for the bootloader's `Info`.
+    Hello,
+    /// Writes `data` to memory at `addr`.
+    Upload { addr: u64, data: &'a [u8] },
+    /// Asks for the CRC-32 of `len` bytes of memory at `addr`.
+    Crc { addr: u64, len: u64 },
+    /// Reads `len` bytes, at most `MAX_DATA`, of memory at `addr`.
+    Peek { addr: u64, len: u32 },
+    /// Jumps to `addr`, after responding.
+    Go { addr: u64 },
+}
+
+impl<'a> Request<'a> {
+    /// Encodes this request as a frame into `out` and returns its length.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an upload has more than `MAX_DATA` bytes.
+    pub fn encode(&self, out: &mut [u8; MAX_FRAME]) -> usize {
+        let mut payload = [0u8; MAX_PAYLOAD];
+        let (kind, len) = match *self {
+            Request::Hello => (HELLO, 0),
+            Request::Upload { addr, data } => {
+                assert!(data.len() <= MAX_DATA, "upload longer than MAX_DATA");
+                payload[..8].copy_from_slice(&addr.to_le_bytes());
+                payload[8..8 + data.len()].copy_from_slice(data);
+                (UPLOAD, 8 + data.len())
+            }
+            Request::Crc { addr, len } => {
+                payload[..8].copy_from_slice(&addr.to_le_bytes());
+                payload[8..16].copy_from_slice(&len.to_le_bytes());
+                (CRC, 16)
+            }
+            Request::Peek { addr, len } => {
+                payload[..8].copy_from_slice(&addr.to_le_bytes());
+                payload[8..12].copy_from_slice(&len.to_le_bytes());
+                (PEEK, 12)
+            }
+            Request::Go { addr } => {
+                payload[..8].copy_from_slice(&addr.to_le_bytes());
+                (GO, 8)
+            }
+        };
+
+        encode_frame(kind, &payload[..len], out)
+    }
+
+    /// Decodes a request from the kind and payload of a frame.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Status::UnknownCommand` for an unknown kind and
+    /// `Status::BadLength` if the payload doesn't fit the kind.
+    pub fn decode(kind: u8, payload: &'a [u8]) -> Result<Request<'a>, Status> {
+        let expect = |len: usize| match payload.len() == len {
+            true => Ok(()),
+            false => Err(Status::BadLength),
+        };
+
+        match kind {
+            HELLO => expect(0).map(|_| Request::Hello),
+            UPLOAD if payload.len() >= 8 => {
+                Ok(Request::Upload { addr: le_u64(payload, 0), data: &payload[8..] })
+            }
+            UPLOAD => Err(Status::BadLength),
+            CRC => expect(16).map(|_| Request::Crc { addr: le_u64(payload, 0), len: le_u64(payload, 8) }),
+            PEEK => expect(12).map(|_| Request::Peek { addr: le_u64(payload, 0), len: le_u32(payload, 8) }),
+            GO => expect(8).map(|_| Request::Go { addr: le_u64(payload, 0) }),
+            _ => Err(Status::UnknownCommand),
+        }
+    }
+}
+
+/// The outcome of a request, sent as the kind of its response.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// The request succeeded; the payload holds its result, if any.
+    Ok = 0,
+    /// The request frame failed its CRC.
+    BadFrame = 1,
+    /// The request kind is unknown.
+    UnknownCommand = 2,
+    /// The request touches memory outside the allowed range.
+    OutOfRange = 3,
+    /// The request's payload or length is wrong for its kind.
+    BadLength = 4,
+}
+
+impl Status {
+    /// Returns the status with code `code`, if any.
+    pub fn from_code(code: u8) -> Option<Status> {
+        match code {
+            0 => Some(Status::Ok),
+            1 => Some(Status::BadFrame),
+            2 => Some(Status::UnknownCommand),
+            3 => Some(Status::OutOfRange),
+            4 => Some(Status::BadLength),
+            _ => None,
+        }
+    }
+}
+
+/// The bootloader's response to `Request::Hello`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Info {
+    /// The bootloader's `PROTOCOL_VERSION`.
+    pub protocol: u16,
+    /// Start of the memory uploads may write to and `Go` may jump into.
+    pub load_start: u64,
+    /// End of that memory.
+    pub load_end: u64,
+    /// Maximum data length of an upload or peek.
+    pub max_data: u32,
+}
+
+impl Info {
+    /// Length of an encoded `Info`.
+    pub const SIZE: usize = 22;
+
+    /// Encodes this `Info` as a response payload.
+    pub fn to_bytes(&self) -> [u8; Info::SIZE] {
+        let mut buf = [0u8; Info::SIZE];
+        buf[0..2].copy_from_slice(&self.protocol.to_le_bytes());
+        buf[2..10].copy_from_slice(&self.load_start.to_le_bytes());
+        buf[10..18].copy_from_slice(&self.load_end.to_le_bytes());
+        buf[18..22].copy_from_slice(&self.max_data.to_le_bytes());
+        buf
+    }
+
+    /// Decodes an `Info` from a response payload.
+    pub fn parse(payload: &[u8]) -> Option<Info> {
+        if payload.len() < Info::SIZE {
+            return None;
+        }
+
+        Some(Info {
+            protocol: le_u16(payload, 0),
+            load_start: le_u64(payload, 2),
+            load_end: le_u64(payload, 10),
+            max_data: le_u32(payload, 18),
+        })
+    }
+}
+
+/// Encodes a frame of `kind` carrying `payload` into `out` and returns its
+/// length.
+///
+/// # Panics
+///
+/// Panics if `payload` is longer than `MAX_PAYLOAD`.
+pub fn encode_frame(kind: u8, payload: &[u8], out: &mut [u8; MAX_FRAME]) -> usize {
+    assert!(payload.len() <= MAX_PAYLOAD, "payload longer than MAX_PAYLOAD");
+
+    let end = 4 + payload.len();
+    out[0] = SYNC;
+    out[1] = kind;
+    out[2..4].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+    out[4..end].copy_from_slice(payload);
+    let crc = crc32(&out[1..end]);
+    out[end..end + 4].copy_from_slice(&crc.to_le_bytes());
+    end + 4
+}
+
+/// Result of feeding a byte to a `Decoder`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Decoded {
+    /// More bytes are needed.
+    Pending,
+    /// A frame is complete; see `Decoder::frame`.
+    Frame,
+    /// A frame was complete but failed its CRC, or announced a payload longer
+    /// than `MAX_PAYLOAD`.
+    BadFrame,
+}
+
+/// Sans-I/O frame decoder.
+pub struct Decoder {
+    buf: [u8; MAX_FRAME],
+    len: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Decoder {
+        Decoder::new()
+    }
+}
+
+impl Decoder {
+    /// Returns a decoder waiting for `SYNC`.
+    pub const fn new() -> Decoder {
+        Decoder { buf: [0; MAX_FRAME], len: 0 }
+    }
+
+    /// Feeds the next received byte. Once a frame is complete, the next byte
+    /// starts looking for another.
+    pub fn push(&mut self, byte: u8) -> Decoded {
+        if self.len == 0 || self.is_complete() {
+            self.len = 0;
+            if byte != SYNC {
+                return Decoded::Pending;
+            }
+        }
+
+        self.buf[self.len] = byte;
+        self.len += 1;
+        if self.len == 4 && self.payload_len() > MAX_PAYLOAD {
+            self.len = 0;
+            return Decoded::BadFrame;
+        }
+
+        if !self.is_complete() {
+            return Decoded::Pending;
+        }
+
+        let end = 4 + self.payload_len();
+        match crc32(&self.buf[1..end]) == le_u32(&self.buf, end) {
+            true => Decoded::Frame,
+            false => {
+                self.len = 0;
+                Decoded::BadFrame
+            }
+        }
+    }
+
+    /// The kind and payload of the frame just completed.
+    ///
+    /// # Panics
+    ///
+    /// Panics unless the last call to `push` returned `Decoded::Frame`.
+    pub fn frame(&self) -> (u8, &[u8]) {
+        assert!(self.is_complete(), "no complete frame");
+        (self.buf[1], &self.buf[4..4 + self.payload_len()])
+    }
+
+    fn payload_len(&self) -> usize {
+        le_u16(&self.buf, 2) as usize
+    }
+
+    fn is_complete(&self) -> bool {
+        self.len >= 4 && self.len == 4 + self.payload_len() + 4
+    }
+}
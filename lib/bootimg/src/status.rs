@@ -0,0 +1,116 @@
+//! Status lines the bootloader writes on its UART around transfers.
+//!
+//! Each line is `PREFIX`, an event name and the event's `key=value` fields,
+//! separated by single spaces; an error's message takes the rest of the line.
+//! Lines end in `\r\n`:
+//!
+//! ```text
+//! boot: ready load=0x80000..0x3a300000
+//! boot: receiving wait=60
+//! boot: received bytes=4096
+//! boot: error code=5 kind=bad-crc bad payload CRC-32: expected 0x12345678, got 0x9abcdef0
+//! boot: jumping addr=0x80000
+//! ```
+//!
+//! A line only ever starts with `PREFIX`, which holds none of the bytes an
+//! XMODEM receiver answers with, so a sender can tell the two apart before
+//! the handshake and after the transfer; nothing is written during one.
+
+use core::fmt;
+
+/// Start of every status line.
+pub const PREFIX: &str = "boot: ";
+
+/// `Event::Error` code and kind of a failed transfer. A rejected image's are
+/// its `Error::code` and `Error::kind`.
+pub const TRANSFER_ERROR_CODE: u8 = 0;
+pub const TRANSFER_ERROR_KIND: &str = "transfer";
+
+/// Something the bootloader reports. `M` is an error's message: anything
+/// that displays when writing, the rest of the line when parsed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Event<M> {
+    /// The bootloader started; images may be loaded to `load_start..load_end`.
+    Ready { load_start: u64, load_end: u64 },
+    /// Waiting up to `wait` seconds for an upload.
+    Receiving { wait: u64 },
+    /// A transfer completed with `bytes` bytes, after any decompression.
+    Received { bytes: u64 },
+    /// The transfer failed or the image was rejected. `kind` names the error;
+    /// `code` is also flashed on the LED.
+    Error { code: u8, kind: &'static str, message: M },
+    /// The received image couldn't be kept for fallback for the reason in
+    /// `message`.
+    NotKept { message: M },
+    /// No upload came: booting the fallback image, which failed `failures`
+    /// boots so far.
+    Fallback { failures: u32 },
+    /// The fallback image failed `failures` boots and won't be booted again
+    /// without an upload.
+    FallbackFailed { failures: u32 },
+    /// Jumping to the loaded image's entry point `addr`.
+    Jumping { addr: u64 },
+}
+
+impl<M: fmt::Display> fmt::Display for Event<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(PREFIX)?;
+        match self {
+            Event::Ready { load_start, load_end } => write!(f, "ready load={:#x}..{:#x}", load_start, load_end),
+            Event::Receiving { wait } => write!(f, "receiving wait={}", wait),
+            Event::Received { bytes } => write!(f, "received bytes={}", bytes),
+            Event::Error { code, kind, message } => write!(f, "error code={} kind={} {}", code, kind, message),
+            Event::NotKept { message } => write!(f, "not-kept {}", message),
+            Event::Fallback { failures } => write!(f, "fallback failures={}", failures),
+            Event::FallbackFailed { failures } => write!(f, "fallback-failed failures={}", failures),
+            Event::Jumping { addr } => write!(f, "jumping addr={:#x}", addr),
+        }
+    }
+}
+
+/// Parses a status line, with or without its line ending. Returns `None` if
+/// `line` isn't one. The `kind` of a parsed error is one this crate knows, or
+/// `"unknown"`.
+pub fn parse(line: &str) -> Option<Event<&str>> {
+    let line = line.trim_end_matches(['\r', '\n']).strip_prefix(PREFIX)?;
+    let (event, rest) = line.split_once(' ').unwrap_or((line, ""));
+    match event {
+        "ready" => {
+            let (start, end) = field(rest, "load")?.0.split_once("..")?;
+            Some(Event::Ready { load_start: hex(start)?, load_end: hex(end)? })
+        }
+        "receiving" => Some(Event::Receiving { wait: field(rest, "wait")?.0.parse().ok()? }),
+        "received" => Some(Event::Received { bytes: field(rest, "bytes")?.0.parse().ok()? }),
+        "error" => {
+            let (code, rest) = field(rest, "code")?;
+            let (kind, message) = field(rest, "kind")?;
+            let kind = KINDS.iter().find(|&&known| known == kind).copied().unwrap_or("unknown");
+            Some(Event::Error { code: code.parse().ok()?, kind, message })
+        }
+        "not-kept" => Some(Event::NotKept { message: rest }),
+        "fallback" => Some(Event::Fallback { failures: field(rest, "failures")?.0.parse().ok()? }),
+        "fallback-failed" => Some(Event::FallbackFailed { failures: field(rest, "failures")?.0.parse().ok()? }),
+        "jumping" => Some(Event::Jumping { addr: hex(field(rest, "addr")?.0)? }),
+        _ => None,
+    }
+}
+
+/// Error kinds the bootloader reports: those of `Error::kind` and
+/// `TRANSFER_ERROR_KIND`.
+const KINDS: &[&str] = &[
+    TRANSFER_ERROR_KIND, "too-short", "bad-magic", "unsupported-version", "truncated", "bad-crc",
+    "bad-load-address", "bad-entry-point", "bad-elf", "bad-segment", "too-large", "bad-lz4",
+    "unsigned", "bad-signature", "bad-cmdline",
+];
+
+/// Splits the `key=value` field at the start of `fields` into its value and
+/// the fields after it.
+fn field<'a>(fields: &'a str, key: &str) -> Option<(&'a str, &'a str)> {
+    let (field, rest) = fields.split_once(' ').unwrap_or((fields, ""));
+    Some((field.strip_prefix(key)?.strip_prefix('=')?, rest))
+}
+
+/// Parses a `0x`-prefixed hexadecimal number.
+fn hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s.strip_prefix("0x")?, 16).ok()
+}
@@ -0,0 +1,404 @@
+use crate::*;
+
+fn image(payload: &[u8], load_addr: u64, entry: u64) -> [u8; 64] {
+    let mut image = [0u8; 64];
+    image[..HEADER_SIZE].copy_from_slice(&Header::new(payload, load_addr, entry).to_bytes());
+    image[HEADER_SIZE..HEADER_SIZE + payload.len()].copy_from_slice(payload);
+    image
+}
+
+#[test]
+fn crc32_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    assert_eq!(crc32(&[]), 0);
+}
+
+#[test]
+fn header_round_trip() {
+    let header = Header::new(b"kernel", 0x80000, 0x80004);
+    let bytes = header.to_bytes();
+    assert_eq!(&bytes[..4], b"RPIB");
+    assert_eq!(&bytes[4..8], &[1, 0, 0, 0]);
+    assert_eq!(Header::parse(&bytes), Ok(header));
+}
+
+#[test]
+fn verify_ignores_padding() {
+    let image = image(b"kernel", 0x80000, 0x80000);
+    let (header, payload) = Header::verify(&image).expect("valid image");
+    assert_eq!(header.length, 6);
+    assert_eq!(payload, b"kernel");
+}
+
+#[test]
+fn verify_errors() {
+    assert_eq!(Header::verify(&[0; 16]), Err(Error::TooShort));
+    assert_eq!(Header::verify(&[0; 64]), Err(Error::BadMagic(0)));
+
+    let mut bad = image(b"kernel", 0x80000, 0x80000);
+    bad[4] = 2;
+    assert_eq!(Header::verify(&bad), Err(Error::UnsupportedVersion(2)));
+
+    let good = image(b"kernel", 0x80000, 0x80000);
+    assert_eq!(Header::verify(&good[..HEADER_SIZE + 3]), Err(Error::Truncated { expected: 6, got: 3 }));
+
+    let mut bad = good;
+    bad[HEADER_SIZE] ^= 1;
+    assert!(matches!(Header::verify(&bad), Err(Error::BadCrc { .. })));
+}
+
+/// Builds an image of `payload` followed by the command line `cmdline`, which
+/// needn't be valid.
+fn image_with_cmdline(payload: &[u8], cmdline: &[u8]) -> Vec<u8> {
+    let header = Header {
+        length: payload.len() as u32,
+        cmdline_len: cmdline.len() as u16,
+        crc32: crc32_update(crc32(payload), cmdline),
+        load_addr: 0x80000,
+        entry: 0x80000,
+    };
+    [&header.to_bytes()[..], payload, cmdline].concat()
+}
+
+#[test]
+fn cmdline() {
+    let header = Header::new_with_cmdline(b"kernel", "root=sd quiet", 0x80000, 0x80000);
+    assert_eq!(header.cmdline_len, 13);
+    assert_eq!(header.crc32, crc32(b"kernelroot=sd quiet"));
+
+    let image = image_with_cmdline(b"kernel", b"root=sd quiet");
+    assert_eq!(Header::parse(&image), Ok(header));
+    let (header, payload) = Header::verify(&image).expect("valid image");
+    assert_eq!(payload, b"kernel");
+    assert_eq!(header.cmdline(&image), "root=sd quiet");
+    assert_eq!(Header::verify(&image[..image.len() - 1]), Err(Error::Truncated { expected: 19, got: 18 }));
+
+    let mut bad = image.clone();
+    *bad.last_mut().unwrap() ^= 1;
+    assert!(matches!(Header::verify(&bad), Err(Error::BadCrc { .. })));
+
+    assert_eq!(Header::new(b"kernel", 0x80000, 0x80000).cmdline(&image), "");
+
+    let long = vec![b'x'; MAX_CMDLINE + 1];
+    assert_eq!(Header::verify(&image_with_cmdline(b"kernel", &long)), Err(Error::BadCmdline("too long")));
+    assert_eq!(Header::verify(&image_with_cmdline(b"kernel", b"a\0b")), Err(Error::BadCmdline("contains a NUL")));
+    assert_eq!(Header::verify(&image_with_cmdline(b"kernel", b"\xff")), Err(Error::BadCmdline("not UTF-8")));
+}
+
+#[test]
+fn bounds() {
+    let header = Header::new(&[0; 16], 0x80000, 0x80000);
+    assert_eq!(header.check_bounds(0x80000, 0x80010), Ok(()));
+    assert_eq!(header.check_bounds(0x80000, 0x8000F), Err(Error::BadLoadAddress(0x80000)));
+    assert_eq!(header.check_bounds(0x80001, 0x90000), Err(Error::BadLoadAddress(0x80000)));
+
+    let header = Header::new(&[0; 16], 0x80000, 0x80010);
+    assert_eq!(header.check_bounds(0x80000, 0x90000), Err(Error::BadEntryPoint(0x80010)));
+
+    let header = Header::new(&[0; 16], u64::MAX - 4, u64::MAX - 4);
+    assert_eq!(header.check_bounds(0, u64::MAX), Err(Error::BadLoadAddress(u64::MAX - 4)));
+}
+
+/// Builds an AArch64 executable entered at `entry` with a single `PT_LOAD`
+/// segment of `data` at `paddr`, followed by `bss` zeroes in memory.
+fn elf_image(entry: u64, paddr: u64, data: &[u8], bss: u64) -> Vec<u8> {
+    let mut image = vec![0u8; 64 + 56];
+    image[..4].copy_from_slice(b"\x7fELF");
+    image[4] = 2;
+    image[5] = 1;
+    image[6] = 1;
+    image[16..18].copy_from_slice(&2u16.to_le_bytes());
+    image[18..20].copy_from_slice(&183u16.to_le_bytes());
+    image[24..32].copy_from_slice(&entry.to_le_bytes());
+    image[32..40].copy_from_slice(&64u64.to_le_bytes());
+    image[52..54].copy_from_slice(&64u16.to_le_bytes());
+    image[54..56].copy_from_slice(&56u16.to_le_bytes());
+    image[56..58].copy_from_slice(&1u16.to_le_bytes());
+
+    let ph = &mut image[64..];
+    ph[..4].copy_from_slice(&1u32.to_le_bytes());
+    ph[8..16].copy_from_slice(&120u64.to_le_bytes());
+    ph[16..24].copy_from_slice(&paddr.to_le_bytes());
+    ph[24..32].copy_from_slice(&paddr.to_le_bytes());
+    ph[32..40].copy_from_slice(&(data.len() as u64).to_le_bytes());
+    ph[40..48].copy_from_slice(&(data.len() as u64 + bss).to_le_bytes());
+    image.extend_from_slice(data);
+    image
+}
+
+#[test]
+fn elf_segments() {
+    let image = elf_image(0x80000, 0x80000, b"code", 12);
+    assert!(elf::is_elf(&image));
+
+    let elf = elf::Elf::parse(&image).expect("valid ELF");
+    assert_eq!(elf.entry, 0x80000);
+    let segments: Vec<_> = elf.segments().collect();
+    assert_eq!(segments, [elf::Segment { offset: 120, paddr: 0x80000, filesz: 4, memsz: 16 }]);
+    assert_eq!(elf.data(&segments[0]), b"code");
+    assert_eq!(segments[0].check_bounds(0x80000, 0x80010), Ok(()));
+    assert_eq!(segments[0].check_bounds(0x80000, 0x8000F), Err(Error::BadSegment(0x80000)));
+}
+
+#[test]
+fn elf_errors() {
+    assert!(!elf::is_elf(&[0; 64]));
+    assert_eq!(elf::Elf::parse(&[0; 64]).err(), Some(Error::BadElf("not an ELF file")));
+
+    let image = elf_image(0x90000, 0x80000, b"code", 0);
+    assert_eq!(elf::Elf::parse(&image).err(), Some(Error::BadEntryPoint(0x90000)));
+
+    let mut image = elf_image(0x80000, 0x80000, b"code", 0);
+    image[18] = 62;
+    assert_eq!(elf::Elf::parse(&image).err(), Some(Error::BadElf("not AArch64")));
+
+    let image = elf_image(0x80000, 0x80000, b"code", 0);
+    assert_eq!(elf::Elf::parse(&image[..122]).err(), Some(Error::BadElf("segment out of bounds")));
+    assert_eq!(elf::Elf::parse(&image[..100]).err(), Some(Error::BadElf("program headers out of bounds")));
+}
+
+fn decode_all(decoder: &mut proto::Decoder, bytes: &[u8]) -> Vec<proto::Decoded> {
+    bytes.iter().map(|&b| decoder.push(b)).filter(|&d| d != proto::Decoded::Pending).collect()
+}
+
+#[test]
+fn proto_round_trip() {
+    let requests = [
+        proto::Request::Hello,
+        proto::Request::Upload { addr: 0x80000, data: &[1, 2, 3] },
+        proto::Request::Crc { addr: 0x80000, len: 3 },
+        proto::Request::Peek { addr: 0x80000, len: 16 },
+        proto::Request::Go { addr: 0x80000 },
+    ];
+
+    let mut decoder = proto::Decoder::new();
+    for request in requests {
+        let mut frame = [0u8; proto::MAX_FRAME];
+        let len = request.encode(&mut frame);
+
+        // the XMODEM handshake may come first
+        assert_eq!(decode_all(&mut decoder, b"CC"), []);
+        assert_eq!(decode_all(&mut decoder, &frame[..len]), [proto::Decoded::Frame]);
+        let (kind, payload) = decoder.frame();
+        assert_eq!(proto::Request::decode(kind, payload), Ok(request));
+    }
+}
+
+#[test]
+fn proto_errors() {
+    let mut frame = [0u8; proto::MAX_FRAME];
+    let len = proto::Request::Go { addr: 0x80000 }.encode(&mut frame);
+    frame[5] ^= 1;
+
+    let mut decoder = proto::Decoder::new();
+    assert_eq!(decode_all(&mut decoder, &frame[..len]), [proto::Decoded::BadFrame]);
+
+    let mut out = [0u8; proto::MAX_FRAME];
+    let len = proto::encode_frame(0x7F, &[], &mut out);
+    assert_eq!(decode_all(&mut decoder, &out[..len]), [proto::Decoded::Frame]);
+    let (kind, payload) = decoder.frame();
+    assert_eq!(proto::Request::decode(kind, payload), Err(proto::Status::UnknownCommand));
+    assert_eq!(proto::Request::decode(0x05, &[0; 4]), Err(proto::Status::BadLength));
+
+    assert_eq!(decode_all(&mut decoder, &[proto::SYNC, 0, 0xFF, 0xFF]), [proto::Decoded::BadFrame]);
+}
+
+#[test]
+fn proto_info() {
+    let info = proto::Info { protocol: 1, load_start: 0x80000, load_end: 0x3FF0000, max_data: 1024 };
+    assert_eq!(proto::Info::parse(&info.to_bytes()), Some(info));
+    assert_eq!(proto::Info::parse(&[0; 4]), None);
+}
+
+fn lz4_compress(data: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0; lz4::max_compressed_len(data.len())];
+    let len = lz4::compress(data, &mut frame);
+    frame.truncate(len);
+    frame
+}
+
+#[test]
+fn xxh32_check_values() {
+    assert_eq!(lz4::xxh32(b"", 0), 0x02CC5D05);
+    assert_eq!(lz4::xxh32(b"abc", 0), 0x32D153FF);
+    assert_eq!(lz4::xxh32(b"Nobody inspects the spammish repetition", 0), 0xE2293B2F);
+}
+
+#[test]
+fn lz4_round_trip() {
+    let text: Vec<u8> = b"kernel ".iter().cycle().take(10_000).copied().collect();
+    let noise: Vec<u8> = (0..5000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+    for data in [&b""[..], b"k", &text, &noise] {
+        let frame = lz4_compress(data);
+        assert!(lz4::is_lz4(&frame));
+
+        // One byte at a time, with trailing padding.
+        let mut out = vec![0; data.len()];
+        let mut decoder = lz4::Decoder::new(&mut out);
+        for &b in frame.iter().chain(&[0x1A; 16]) {
+            decoder.write(&[b]).expect("valid frame");
+        }
+        assert_eq!(decoder.finish(), Ok(data.len()));
+        assert_eq!(out, data);
+    }
+
+    assert!(lz4_compress(&text).len() < 100);
+}
+
+#[test]
+fn lz4_errors() {
+    let data: Vec<u8> = b"0123456789".iter().cycle().take(1000).copied().collect();
+    let frame = lz4_compress(&data);
+
+    let mut out = vec![0; 999];
+    let mut decoder = lz4::Decoder::new(&mut out);
+    assert_eq!(decoder.write(&frame), Err(Error::TooLarge { size: 1000, capacity: 999 }));
+
+    let mut out = vec![0; 1000];
+    let mut decoder = lz4::Decoder::new(&mut out);
+    decoder.write(&frame[..frame.len() - 1]).expect("valid prefix");
+    assert_eq!(decoder.finish(), Err(Error::BadLz4("truncated frame")));
+
+    let mut corrupt = frame.clone();
+    corrupt[6] ^= 1;
+    let mut decoder = lz4::Decoder::new(&mut out);
+    assert_eq!(decoder.write(&corrupt), Err(Error::BadLz4("bad header checksum")));
+
+    let mut corrupt = frame.clone();
+    let n = corrupt.len();
+    corrupt[n - 1] ^= 1;
+    let mut decoder = lz4::Decoder::new(&mut out);
+    assert_eq!(decoder.write(&corrupt), Err(Error::BadLz4("bad content checksum")));
+}
+
+fn unhex<const N: usize>(hex: &str) -> [u8; N] {
+    let bytes: Vec<u8> = (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect();
+    bytes.try_into().expect("right length")
+}
+
+#[test]
+fn sha512_check_value() {
+    let expected: [u8; 64] = unhex(
+        "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+         2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+    );
+    assert_eq!(ed25519::Sha512::new().update(b"ab").update(b"c").finish(), expected);
+}
+
+#[test]
+fn ed25519_rfc8032_vector() {
+    // RFC 8032, section 7.1, test 2.
+    let secret = unhex("4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb");
+    let public = unhex("3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c");
+    let signature = unhex(
+        "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da\
+         085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+    );
+
+    assert_eq!(ed25519::public_key(&secret), public);
+    assert_eq!(ed25519::sign(&secret, &[0x72]), signature);
+    assert!(ed25519::verify(&public, &[0x72], &signature));
+    assert!(!ed25519::verify(&public, &[0x73], &signature));
+}
+
+#[test]
+fn verify_signed() {
+    let secret = [7u8; 32];
+    let public = ed25519::public_key(&secret);
+
+    let mut signed = image(b"kernel", 0x80000, 0x80000)[..HEADER_SIZE + 6].to_vec();
+    signed.extend_from_slice(&ed25519::sign(&secret, &signed));
+    signed.extend_from_slice(&[0; 40]);
+    let (header, payload) = Header::verify_signed(&signed, &public).expect("signed image");
+    assert_eq!((header.length, payload), (6, &b"kernel"[..]));
+
+    assert_eq!(Header::verify_signed(&signed, &ed25519::public_key(&[8; 32])), Err(Error::BadSignature));
+    assert_eq!(Header::verify_signed(&signed[..HEADER_SIZE + 6 + 63], &public), Err(Error::Unsigned));
+
+    signed[HEADER_SIZE + 6 + 10] ^= 1;
+    assert_eq!(Header::verify_signed(&signed, &public), Err(Error::BadSignature));
+
+    // The signature covers the command line too.
+    let mut signed = image_with_cmdline(b"kernel", b"quiet");
+    signed.extend_from_slice(&ed25519::sign(&secret, &signed));
+    let (header, _) = Header::verify_signed(&signed, &public).expect("signed image");
+    assert_eq!(header.cmdline(&signed), "quiet");
+    signed[HEADER_SIZE + 6 + 64] ^= 1;
+    assert_eq!(Header::verify_signed(&signed, &public), Err(Error::BadSignature));
+}
+
+#[test]
+fn fallback_slot() {
+    let mut slot = vec![0xFF; fallback::RECORD_SIZE + 8];
+    assert_eq!(fallback::load(&slot), None);
+
+    fallback::store(&mut slot, b"kernel").expect("fits");
+    assert_eq!(fallback::load(&slot), Some((&b"kernel"[..], 0)));
+
+    fallback::set_failures(&mut slot, 2);
+    assert_eq!(fallback::load(&slot), Some((&b"kernel"[..], 2)));
+    fallback::clear_failures(&mut slot[..fallback::RECORD_SIZE]);
+    assert_eq!(fallback::load(&slot), Some((&b"kernel"[..], 0)));
+
+    slot[fallback::RECORD_SIZE] ^= 1;
+    assert_eq!(fallback::load(&slot), None);
+
+    assert_eq!(fallback::store(&mut slot, b"kernel..."), Err(Error::TooLarge { size: 9, capacity: 8 }));
+    assert_eq!(fallback::load(&slot), None);
+}
+
+#[test]
+fn fallback_store_too_large() {
+    let mut slot = vec![0; fallback::RECORD_SIZE + 8];
+    fallback::store(&mut slot, b"kernel").expect("fits");
+
+    assert_eq!(fallback::store(&mut slot, b"kernel..."), Err(Error::TooLarge { size: 9, capacity: 8 }));
+    assert_eq!(fallback::load(&slot), Some((&b"kernel"[..], 0)));
+}
+
+#[test]
+fn status_round_trip() {
+    use status::Event;
+
+    let bad_crc = Error::BadCrc { expected: 1, got: 2 };
+    let events = [
+        Event::Ready { load_start: 0x80000, load_end: 0x3A30_0000 },
+        Event::Receiving { wait: 60 },
+        Event::Received { bytes: 4096 },
+        Event::Error { code: bad_crc.code(), kind: bad_crc.kind(), message: "bad payload CRC-32: expected 0x00000001, got 0x00000002" },
+        Event::Error { code: status::TRANSFER_ERROR_CODE, kind: status::TRANSFER_ERROR_KIND, message: "timed out" },
+        Event::NotKept { message: "image too large" },
+        Event::Fallback { failures: 2 },
+        Event::FallbackFailed { failures: 3 },
+        Event::Jumping { addr: 0x80000 },
+    ];
+    for event in events {
+        let line = format!("{}\r\n", event);
+        assert_eq!(status::parse(&line), Some(event), "{:?}", line);
+    }
+
+    let line = Event::Error { code: bad_crc.code(), kind: bad_crc.kind(), message: bad_crc }.to_string();
+    assert_eq!(line, "boot: error code=5 kind=bad-crc bad payload CRC-32: expected 0x00000001, got 0x00000002");
+
+    let errors = [
+        Error::TooShort, Error::BadMagic(0), Error::UnsupportedVersion(2),
+        Error::Truncated { expected: 1, got: 0 }, bad_crc, Error::BadLoadAddress(0),
+        Error::BadEntryPoint(0), Error::BadElf(""), Error::BadSegment(0),
+        Error::TooLarge { size: 1, capacity: 0 }, Error::BadLz4(""), Error::Unsigned, Error::BadSignature,
+        Error::BadCmdline(""),
+    ];
+    for e in errors {
+        let line = Event::Error { code: e.code(), kind: e.kind(), message: e }.to_string();
+        assert!(matches!(status::parse(&line), Some(Event::Error { kind, .. }) if kind == e.kind()), "{}", line);
+    }
+}
+
+#[test]
+fn status_not_a_line() {
+    assert_eq!(status::parse("hello"), None);
+    assert_eq!(status::parse("boot: "), None);
+    assert_eq!(status::parse("boot: dancing"), None);
+    assert_eq!(status::parse("boot: received bytes=many"), None);
+    assert_eq!(status::parse("boot: jumping addr=80000"), None);
+    assert!(matches!(status::parse("boot: error code=99 kind=new thing"), Some(status::Event::Error { kind: "unknown", .. })));
+}
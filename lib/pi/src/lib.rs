@@ -4,5 +4,7 @@
 
 pub mod common;
 pub mod gpio;
+pub mod mailbox;
+pub mod pm;
 pub mod timer;
 pub mod uart;
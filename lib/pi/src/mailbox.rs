@@ -0,0 +1,86 @@
+use core::sync::atomic::{fence, Ordering};
+
+use crate::common::IO_BASE;
+
+use volatile::prelude::*;
+use volatile::{Volatile, ReadVolatile, Reserved};
+
+/// The base address for the mailbox registers.
+pub const MAILBOX_REG_BASE: usize = IO_BASE + 0xB880;
+
+/// The channel for property tags from the ARM to the VideoCore.
+const PROPERTY_CHANNEL: u32 = 8;
+
+/// Status bits: no room to write, nothing to read.
+const FULL: u32 = 1 << 31;
+const EMPTY: u32 = 1 << 30;
+
+/// Code of a processed request in a property message.
+const RESPONSE_OK: u32 = 0x8000_0000;
+
+/// Property tag asking for the ARM's share of memory.
+const TAG_ARM_MEMORY: u32 = 0x0001_0005;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    READ: ReadVolatile<u32>,
+    __r0: [Reserved<u32>; 5],
+    STATUS: ReadVolatile<u32>,
+    __r1: Reserved<u32>,
+    WRITE: Volatile<u32>,
+}
+
+/// A property message; the VideoCore wants it 16-byte aligned.
+#[repr(C, align(16))]
+struct Message([u32; 8]);
+
+/// The mailbox used to talk to the VideoCore.
+pub struct Mailbox {
+    registers: &'static mut Registers
+}
+
+impl Mailbox {
+    /// Returns a new instance of `Mailbox`.
+    pub fn new() -> Mailbox {
+        Mailbox {
+            registers: unsafe { &mut *(MAILBOX_REG_BASE as *mut Registers) },
+        }
+    }
+
+    /// Returns the base and size of the memory the ARM has, or `None` if the
+    /// VideoCore doesn't answer.
+    pub fn arm_memory(&mut self) -> Option<(usize, usize)> {
+        let mut message = Message([32, 0, TAG_ARM_MEMORY, 8, 0, 0, 0, 0]);
+        self.call(&mut message);
+        match message.0[1] == RESPONSE_OK {
+            true => Some((message.0[5] as usize, message.0[6] as usize)),
+            false => None,
+        }
+    }
+
+    /// Sends `message` on the property channel and waits for the reply, which
+    /// overwrites it.
+    fn call(&mut self, message: &mut Message) {
+        let request = (message as *mut Message as usize as u32) | PROPERTY_CHANNEL;
+
+        // The VideoCore reads the message from memory behind our back.
+        fence(Ordering::SeqCst);
+        while self.registers.STATUS.has_mask(FULL) {}
+        self.registers.WRITE.write(request);
+
+        loop {
+            while self.registers.STATUS.has_mask(EMPTY) {}
+            if self.registers.READ.read() == request {
+                break;
+            }
+        }
+        fence(Ordering::SeqCst);
+    }
+}
+
+/// Returns the end of the ARM's memory, or `None` if the VideoCore doesn't
+/// say.
+pub fn arm_memory_end() -> Option<usize> {
+    Mailbox::new().arm_memory().map(|(base, size)| base + size)
+}
@@ -0,0 +1,35 @@
+use crate::common::IO_BASE;
+
+use volatile::prelude::*;
+use volatile::{Volatile, Reserved};
+
+/// The base address for the power management registers.
+const PM_REG_BASE: usize = IO_BASE + 0x100000;
+
+/// Every write to a power management register must carry this.
+const PM_PASSWORD: u32 = 0x5A00_0000;
+/// `RSTC` bits configuring what the watchdog does when it expires.
+const PM_RSTC_WRCFG_MASK: u32 = 0x30;
+const PM_RSTC_WRCFG_FULL_RESET: u32 = 0x20;
+
+/// Watchdog ticks, of about 16µs, before the reset.
+const RESET_TICKS: u32 = 10;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    __r0: [Reserved<u32>; 7],
+    RSTC: Volatile<u32>,
+    RSTS: Volatile<u32>,
+    WDOG: Volatile<u32>,
+}
+
+/// Resets the whole board with the watchdog, as a power cycle does, back to
+/// the firmware and then the bootloader.
+pub fn reset() -> ! {
+    let registers = unsafe { &mut *(PM_REG_BASE as *mut Registers) };
+    registers.WDOG.write(PM_PASSWORD | RESET_TICKS);
+    let rstc = registers.RSTC.read() & !PM_RSTC_WRCFG_MASK;
+    registers.RSTC.write(PM_PASSWORD | rstc | PM_RSTC_WRCFG_FULL_RESET);
+    loop {}
+}
@@ -0,0 +1,80 @@
+//! Drives `read_packet` and `write_packet` with arbitrary bytes from the other
+//! side. The first byte of the input picks the mode; the rest is everything
+//! the other side sends, after which reads time out.
+//!
+//! Run with `cargo +nightly fuzz run packets` from `lib/xmodem`.
+
+#![no_main]
+
+use std::io::{self, Read, Write};
+
+use libfuzzer_sys::fuzz_target;
+use xmodem::{Error, Mode, Xmodem, XmodemConfig};
+
+/// Serves `input` to reads, then times out. Writes are discarded.
+struct Replay<'a> {
+    input: &'a [u8],
+}
+
+impl Read for Replay<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.input.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "end of input"));
+        }
+
+        let n = buf.len().min(self.input.len());
+        buf[..n].copy_from_slice(&self.input[..n]);
+        self.input = &self.input[n..];
+        Ok(n)
+    }
+}
+
+impl Write for Replay<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&selector, input)) = data.split_first() else { return };
+    let mode = match selector % 3 {
+        0 => Mode::Checksum,
+        1 => Mode::Crc,
+        _ => Mode::Crc1k,
+    };
+    let config = XmodemConfig::new().with_max_retries(3);
+
+    // Every byte consumed or timeout takes a step towards the end, so a
+    // receiver that loops longer than this has hung.
+    let limit = input.len() + 4 * config.max_retries() + 4;
+
+    let mut receiver = Xmodem::new_with_mode(Replay { input }, mode, |_| {}).with_config(config);
+    let mut packet = [0u8; 1024];
+    let mut steps = 0;
+    loop {
+        steps += 1;
+        assert!(steps <= limit, "receiver hung");
+        match receiver.read_packet(&mut packet) {
+            // A packet handed out must have arrived verbatim.
+            Ok(n) if n > 0 => assert!(input.windows(n).any(|w| w == &packet[..n])),
+            Err(Error::BadChecksum) => continue,
+            _ => break,
+        }
+    }
+
+    let payload = [0x5A; 1024];
+    let mut transmitter = Xmodem::new_with_mode(Replay { input }, mode, |_| {}).with_config(config);
+    let mut steps = 0;
+    loop {
+        steps += 1;
+        assert!(steps <= limit, "transmitter hung");
+        match transmitter.write_packet(&payload[..selector as usize % 2 * 896 + 128]) {
+            Ok(_) | Err(Error::BadChecksum) => continue,
+            Err(_) => break,
+        }
+    }
+});
@@ -0,0 +1,161 @@
+use core::time::Duration;
+
+/// Maximum number of `CAN` bytes sent to abort a transfer.
+pub const MAX_CANCEL_COUNT: usize = 8;
+
+/// Default number of times a receiver in `Mode::Crc` or `Mode::Crc1k` sends
+/// `C` without a response before falling back to `Mode::Checksum` and sending
+/// `NAK`, so that senders which only know the checksum are still answered.
+///
+/// The fallback is silent: a CRC-capable sender that starts after this many
+/// NAK intervals is answered in checksum mode. Receivers that wait on a sender
+/// started by hand should raise it with `XmodemConfig::with_crc_attempts()`.
+pub const CRC_HANDSHAKE_ATTEMPTS: usize = 3;
+
+/// Retry, timeout and cancel policy for a transfer.
+///
+/// Timeouts are measured with the configured clock, if any. Without a clock,
+/// and for any interval that isn't set, every read on the inner stream that
+/// times out counts as a whole interval, so the inner stream's own read
+/// timeout sets the pace.
+#[derive(Debug, Copy, Clone)]
+pub struct XmodemConfig {
+    max_retries: usize,
+    crc_attempts: usize,
+    nak_interval: Option<Duration>,
+    byte_timeout: Option<Duration>,
+    start_timeout: Option<Duration>,
+    cancel_count: usize,
+    clock: Option<fn() -> Duration>,
+}
+
+impl Default for XmodemConfig {
+    fn default() -> XmodemConfig {
+        XmodemConfig::new()
+    }
+}
+
+impl XmodemConfig {
+    /// Returns the default configuration: 10 retries, `CRC_HANDSHAKE_ATTEMPTS`
+    /// requests for CRC, a handshake resent on every read timeout, transfers
+    /// failing on the first read timeout after the handshake, and `CAN CAN` to
+    /// abort.
+    pub const fn new() -> XmodemConfig {
+        XmodemConfig {
+            max_retries: 10,
+            crc_attempts: CRC_HANDSHAKE_ATTEMPTS,
+            nak_interval: None,
+            byte_timeout: None,
+            start_timeout: None,
+            cancel_count: 2,
+            clock: None,
+        }
+    }
+
+    /// Sets the number of consecutive times a packet may be rejected, and,
+    /// without a start timeout, the number of times a receiver resends its
+    /// initial `NAK` or `C`, before a transfer fails.
+    pub const fn with_max_retries(mut self, retries: usize) -> XmodemConfig {
+        self.max_retries = retries;
+        self
+    }
+
+    /// Sets the number of times a receiver in a CRC mode sends `C` before
+    /// falling back to `Mode::Checksum`; see `CRC_HANDSHAKE_ATTEMPTS`.
+    pub const fn with_crc_attempts(mut self, attempts: usize) -> XmodemConfig {
+        self.crc_attempts = attempts;
+        self
+    }
+
+    /// Sets how long a receiver waits for the sender to respond before
+    /// resending its initial `NAK` or `C`.
+    pub const fn with_nak_interval(mut self, interval: Duration) -> XmodemConfig {
+        self.nak_interval = Some(interval);
+        self
+    }
+
+    /// Sets how long to wait for each byte from the other side once the
+    /// transfer has started, and for a sender without a start timeout, for the
+    /// receiver to start it.
+    pub const fn with_byte_timeout(mut self, timeout: Duration) -> XmodemConfig {
+        self.byte_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how long to wait for the other side to start the transfer, such as
+    /// while someone starts it by hand: for a sender, for the receiver's
+    /// initial `NAK` or `C`, and for a receiver, for the sender to answer it.
+    /// A receiver resends its `NAK` or `C` every NAK interval until then,
+    /// however many times that takes.
+    pub const fn with_start_timeout(mut self, timeout: Duration) -> XmodemConfig {
+        self.start_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the number of `CAN` bytes sent to abort a transfer, at most
+    /// `MAX_CANCEL_COUNT`.
+    pub const fn with_cancel_count(mut self, count: usize) -> XmodemConfig {
+        self.cancel_count = if count > MAX_CANCEL_COUNT { MAX_CANCEL_COUNT } else { count };
+        self
+    }
+
+    /// Sets the clock used to measure timeouts: a function returning the time
+    /// elapsed since some fixed point, such as boot.
+    pub const fn with_clock(mut self, clock: fn() -> Duration) -> XmodemConfig {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// The number of retries allowed.
+    pub fn max_retries(&self) -> usize {
+        self.max_retries
+    }
+
+    /// The number of times a receiver requests CRC before falling back.
+    pub fn crc_attempts(&self) -> usize {
+        self.crc_attempts
+    }
+
+    /// The interval between a receiver's initial `NAK`s, if set.
+    pub fn nak_interval(&self) -> Option<Duration> {
+        self.nak_interval
+    }
+
+    /// The per-byte timeout, if set.
+    pub fn byte_timeout(&self) -> Option<Duration> {
+        self.byte_timeout
+    }
+
+    /// The time to wait for the other side to start the transfer, if set.
+    pub fn start_timeout(&self) -> Option<Duration> {
+        self.start_timeout
+    }
+
+    /// The number of `CAN` bytes sent to abort a transfer.
+    pub fn cancel_count(&self) -> usize {
+        self.cancel_count
+    }
+
+    /// The clock used to measure timeouts, if set.
+    pub fn clock(&self) -> Option<fn() -> Duration> {
+        self.clock
+    }
+}
+
+/// Adds `elapsed`, if known, to the time spent waiting in `idle` and returns
+/// `true` if that reaches `interval`. If either is unknown, the timeout is taken
+/// to span the whole interval.
+pub(crate) fn expired(idle: &mut Duration, elapsed: Option<Duration>, interval: Option<Duration>) -> bool {
+    match (elapsed, interval) {
+        (Some(elapsed), Some(interval)) => {
+            *idle += elapsed;
+            if *idle < interval {
+                return false;
+            }
+
+            *idle = Duration::ZERO;
+            true
+        }
+        _ => true,
+    }
+}
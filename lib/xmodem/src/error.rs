@@ -0,0 +1,94 @@
+use core::fmt;
+
+use shim::io;
+
+/// Errors that can occur during an XMODEM or YMODEM transfer.
+///
+/// Protocol failures are reported with a dedicated variant; failures of the
+/// underlying stream, the data source or the data sink are wrapped in
+/// `Error::Io`. An `Error` converts into an `io::Error` for callers that only
+/// deal in I/O errors.
+#[derive(Debug)]
+pub enum Error {
+    /// A received packet failed its checksum or CRC, or the receiver rejected
+    /// a sent packet with `NAK`.
+    BadChecksum,
+    /// A packet arrived with the wrong packet number.
+    PacketNumberMismatch { expected: u8, got: u8 },
+    /// A packet arrived with the wrong packet number complement.
+    ComplementMismatch { expected: u8, got: u8 },
+    /// The other side cancelled the transfer with `CAN`.
+    Cancelled,
+    /// Timed out waiting for the other side.
+    Timeout,
+    /// A byte `.0` arrived that isn't valid at this point in the protocol.
+    UnexpectedByte(u8),
+    /// A packet could not be transferred within the allowed number of retries.
+    RetriesExhausted,
+    /// The data sent didn't fit in the receiving buffer of `capacity` bytes;
+    /// the sender had sent `size` bytes, counting the packet that didn't fit,
+    /// when the transfer was cancelled.
+    TooLarge { size: u64, capacity: u64 },
+    /// Reading or writing the inner stream, the data source or the data sink
+    /// failed.
+    Io(io::Error),
+}
+
+/// Result type for XMODEM and YMODEM transfers.
+pub type Result<T> = core::result::Result<T, Error>;
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        use io::ErrorKind::*;
+
+        match e {
+            Error::BadChecksum => io::Error::new(InvalidData, "bad checksum"),
+            Error::PacketNumberMismatch { .. } => io::Error::new(InvalidData, "wrong packet number"),
+            Error::ComplementMismatch { .. } => io::Error::new(InvalidData, "wrong packet complement"),
+            Error::Cancelled => io::Error::new(ConnectionAborted, "received CAN"),
+            Error::Timeout => io::Error::new(TimedOut, "timed out"),
+            Error::UnexpectedByte(_) => io::Error::new(InvalidData, "unexpected byte"),
+            Error::RetriesExhausted => io::Error::new(BrokenPipe, "retries exhausted"),
+            Error::TooLarge { .. } => io::Error::new(WriteZero, "image too large"),
+            Error::Io(e) => e,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::BadChecksum => write!(f, "bad checksum"),
+            Error::PacketNumberMismatch { expected, got } => {
+                write!(f, "wrong packet number: expected {}, got {}", expected, got)
+            }
+            Error::ComplementMismatch { expected, got } => {
+                write!(f, "wrong packet complement: expected {}, got {}", expected, got)
+            }
+            Error::Cancelled => write!(f, "transfer cancelled"),
+            Error::Timeout => write!(f, "timed out"),
+            Error::UnexpectedByte(b) => write!(f, "unexpected byte {:#04x}", b),
+            Error::RetriesExhausted => write!(f, "retries exhausted"),
+            Error::TooLarge { size, capacity } => {
+                write!(f, "image too large: at least {} bytes for a {}-byte buffer", size, capacity)
+            }
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+#[cfg(not(feature = "no_std"))]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
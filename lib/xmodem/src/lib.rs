@@ -2,29 +2,84 @@
 
 #![feature(decl_macro)]
 
+use core::time::Duration;
+
 use shim::io;
-use shim::ioerr;
 
 #[cfg(test)] mod tests;
+mod config;
+mod error;
 mod read_ext;
+mod machine;
 mod progress;
+mod ymodem;
+mod zmodem;
 
+pub use config::{XmodemConfig, CRC_HANDSHAKE_ATTEMPTS, MAX_CANCEL_COUNT};
+pub use error::{Error, Result};
+pub use machine::{Event, Receiver, Transmitter};
 pub use progress::{Progress, ProgressFn};
+pub use ymodem::{FileInfo, Ymodem, MAX_NAME_LEN};
+pub use zmodem::Zmodem;
 
 use read_ext::ReadExt;
 
 const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
 const EOT: u8 = 0x04;
 const ACK: u8 = 0x06;
 const NAK: u8 = 0x15;
 const CAN: u8 = 0x18;
+const CRC: u8 = b'C';
+
+/// The error-detection scheme and block size used for each packet.
+///
+/// A receiver in `Mode::Crc` or `Mode::Crc1k` requests CRC-16 by sending `C`
+/// instead of `NAK` and falls back to the 8-bit checksum if the sender doesn't
+/// respond within `XmodemConfig::crc_attempts()` requests. A sender in either
+/// CRC mode honors whichever of `C` or `NAK` the receiver sends, while a sender
+/// in `Mode::Checksum` only responds to `NAK`.
+///
+/// Receivers accept both 128-byte (`SOH`) and 1024-byte (`STX`) packets in
+/// every mode; only senders in `Mode::Crc1k` send 1024-byte packets.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// 8-bit additive checksum with 128-byte packets.
+    Checksum,
+    /// CRC-16/XMODEM with 128-byte packets.
+    Crc,
+    /// CRC-16/XMODEM with 1024-byte packets (XMODEM-1K). The final packets of
+    /// a transfer are sent as 128-byte packets to limit padding.
+    Crc1k,
+}
 
 /// Implementation of the XMODEM protocol.
-pub struct Xmodem<R> {
-    packet: u8,
-    started: bool,
+///
+/// `Xmodem` drives a [`Receiver`] or [`Transmitter`] state machine with
+/// blocking reads and writes on the inner stream.
+///
+/// Every constructor and convenience function that doesn't take a [`Mode`]
+/// uses `Mode::Crc`: a receiver requests CRC-16 and falls back to the checksum
+/// for senders that don't answer, and a sender uses whichever the receiver
+/// asks for.
+pub struct Xmodem<R, F = ProgressFn> {
+    mode: Mode,
     inner: R,
-    progress: ProgressFn
+    progress: F,
+    machine: Machine,
+    block: u64,
+    bytes: u64,
+    total: Option<u64>,
+    attempt: usize,
+    config: XmodemConfig,
+}
+
+/// The state machine driven by an `Xmodem`, chosen by the first packet read
+/// or written.
+enum Machine {
+    Idle,
+    Receiving(Receiver),
+    Transmitting(Transmitter),
 }
 
 impl Xmodem<()> {
@@ -34,7 +89,7 @@ impl Xmodem<()> {
     ///
     /// Returns the number of bytes written to `to`, excluding padding zeroes.
     #[inline]
-    pub fn transmit<R, W>(data: R, to: W) -> io::Result<usize>
+    pub fn transmit<R, W>(data: R, to: W) -> Result<usize>
         where W: io::Read + io::Write, R: io::Read
     {
         Xmodem::transmit_with_progress(data, to, progress::noop)
@@ -48,40 +103,32 @@ impl Xmodem<()> {
     /// the transmission. See the [`Progress`] enum for more information.
     ///
     /// Returns the number of bytes written to `to`, excluding padding zeroes.
-    pub fn transmit_with_progress<R, W>(mut data: R, to: W, f: ProgressFn) -> io::Result<usize>
-        where W: io::Read + io::Write, R: io::Read
+    #[inline]
+    pub fn transmit_with_progress<R, W, F>(data: R, to: W, f: F) -> Result<usize>
+        where W: io::Read + io::Write, R: io::Read, F: FnMut(Progress)
     {
-        let mut transmitter = Xmodem::new_with_progress(to, f);
-        let mut packet = [0u8; 128];
-        let mut written = 0;
-        'next_packet: loop {
-            let n = data.read_max(&mut packet)?;
-            packet[n..].iter_mut().for_each(|b| *b = 0);
-
-            if n == 0 {
-                transmitter.write_packet(&[])?;
-                return Ok(written);
-            }
-
-            for _ in 0..10 {
-                match transmitter.write_packet(&packet) {
-                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
-                    Err(e) => return Err(e),
-                    Ok(_) => {
-                        written += n;
-                        continue 'next_packet;
-                    }
-                }
-            }
+        Xmodem::transmit_with_mode(data, to, Mode::Crc, f)
+    }
 
-            return ioerr!(BrokenPipe, "bad transmit");
-        }
+    /// Transmits `data` to the receiver `to` using the XMODEM protocol with the
+    /// error-detection `mode`. If the length of the total data yielded by
+    /// `data` is not a multiple of 128 bytes, the data is padded with zeroes
+    /// and sent to the receiver.
+    ///
+    /// The function `f` is used as a callback to indicate progress throughout
+    /// the transmission. See the [`Progress`] enum for more information.
+    ///
+    /// Returns the number of bytes written to `to`, excluding padding zeroes.
+    pub fn transmit_with_mode<R, W, F>(data: R, to: W, mode: Mode, f: F) -> Result<usize>
+        where W: io::Read + io::Write, R: io::Read, F: FnMut(Progress)
+    {
+        Xmodem::new_with_mode(to, mode, f).transmit_all(data)
     }
 
     /// Receives `data` from `from` using the XMODEM protocol and writes it into
     /// `into`. Returns the number of bytes read from `from`, a multiple of 128.
     #[inline]
-    pub fn receive<R, W>(from: R, into: W) -> io::Result<usize>
+    pub fn receive<R, W>(from: R, into: W) -> Result<usize>
        where R: io::Read + io::Write, W: io::Write
     {
         Xmodem::receive_with_progress(from, into, progress::noop)
@@ -92,30 +139,23 @@ impl Xmodem<()> {
     ///
     /// The function `f` is used as a callback to indicate progress throughout
     /// the reception. See the [`Progress`] enum for more information.
-    pub fn receive_with_progress<R, W>(from: R, mut into: W, f: ProgressFn) -> io::Result<usize>
-       where R: io::Read + io::Write, W: io::Write
+    #[inline]
+    pub fn receive_with_progress<R, W, F>(from: R, into: W, f: F) -> Result<usize>
+       where R: io::Read + io::Write, W: io::Write, F: FnMut(Progress)
     {
-        let mut receiver = Xmodem::new_with_progress(from, f);
-        let mut packet = [0u8; 128];
-        let mut received = 0;
-        'next_packet: loop {
-            for _ in 0..10 {
-                match receiver.read_packet(&mut packet) {
-                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
-                    Err(e) => return Err(e),
-                    Ok(0) => break 'next_packet,
-                    Ok(n) => {
-                        received += n;
-                        into.write_all(&packet)?;
-                        continue 'next_packet;
-                    }
-                }
-            }
-
-            return ioerr!(BrokenPipe, "bad receive");
-        }
+        Xmodem::receive_with_mode(from, into, Mode::Crc, f)
+    }
 
-        Ok(received)
+    /// Receives `data` from `from` using the XMODEM protocol, requesting the
+    /// error-detection `mode`, and writes it into `into`. Returns the number of
+    /// bytes read from `from`, a multiple of 128.
+    ///
+    /// The function `f` is used as a callback to indicate progress throughout
+    /// the reception. See the [`Progress`] enum for more information.
+    pub fn receive_with_mode<R, W, F>(from: R, into: W, mode: Mode, f: F) -> Result<usize>
+       where R: io::Read + io::Write, W: io::Write, F: FnMut(Progress)
+    {
+        Xmodem::new_with_mode(from, mode, f).receive_all(into)
     }
 }
 
@@ -123,238 +163,521 @@ fn get_checksum(buf: &[u8]) -> u8 {
     return buf.iter().fold(0, |a, b| a.wrapping_add(*b));
 }
 
+/// Computes the CRC-16/XMODEM (polynomial `0x1021`, initial value `0`) of `buf`.
+fn get_crc(buf: &[u8]) -> u16 {
+    update_crc(0, buf)
+}
+
+/// Continues the CRC-16/XMODEM `crc` over `buf`.
+fn update_crc(crc: u16, buf: &[u8]) -> u16 {
+    buf.iter().fold(crc, |crc, &b| {
+        let mut crc = crc ^ ((b as u16) << 8);
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
 impl<T: io::Read + io::Write> Xmodem<T> {
     /// Returns a new `Xmodem` instance with the internal reader/writer set to
     /// `inner`. The returned instance can be used for both receiving
     /// (downloading) and sending (uploading).
     pub fn new(inner: T) -> Self {
-        Xmodem { packet: 1, started: false, inner, progress: progress::noop}
+        Xmodem::new_with_mode(inner, Mode::Crc, progress::noop)
     }
+}
 
+impl<T: io::Read + io::Write, F: FnMut(Progress)> Xmodem<T, F> {
     /// Returns a new `Xmodem` instance with the internal reader/writer set to
     /// `inner`. The returned instance can be used for both receiving
     /// (downloading) and sending (uploading). The function `f` is used as a
     /// callback to indicate progress throughout the transfer. See the
     /// [`Progress`] enum for more information.
-    pub fn new_with_progress(inner: T, f: ProgressFn) -> Self {
-        Xmodem { packet: 1, started: false, inner, progress: f }
+    pub fn new_with_progress(inner: T, f: F) -> Self {
+        Xmodem::new_with_mode(inner, Mode::Crc, f)
+    }
+
+    /// Returns a new `Xmodem` instance with the internal reader/writer set to
+    /// `inner` using the error-detection `mode`. See [`Mode`] for how the mode
+    /// is negotiated when receiving and sending. The function `f` is used as a
+    /// callback to indicate progress throughout the transfer.
+    pub fn new_with_mode(inner: T, mode: Mode, f: F) -> Self {
+        Xmodem {
+            mode,
+            inner,
+            progress: f,
+            machine: Machine::Idle,
+            block: 1,
+            bytes: 0,
+            total: None,
+            attempt: 0,
+            config: XmodemConfig::new(),
+        }
+    }
+
+    /// Sets the retry, timeout and cancel policy for transfers with this
+    /// instance. See [`XmodemConfig`] for the defaults.
+    pub fn with_config(mut self, config: XmodemConfig) -> Self {
+        self.config = config;
+        self.machine = Machine::Idle;
+        self
+    }
+
+    /// Returns the error-detection mode in use. Once a transfer has started,
+    /// this is the mode negotiated with the other side.
+    pub fn mode(&self) -> Mode {
+        match &self.machine {
+            Machine::Idle => self.mode,
+            Machine::Receiving(receiver) => receiver.mode(),
+            Machine::Transmitting(transmitter) => transmitter.mode(),
+        }
+    }
+
+    /// Returns the receiver state machine, replacing any transmitter.
+    pub(crate) fn receiver(&mut self) -> &mut Receiver {
+        if !matches!(self.machine, Machine::Receiving(_)) {
+            self.machine = Machine::Receiving(Receiver::new_with_config(self.mode, self.config));
+        }
+
+        match &mut self.machine {
+            Machine::Receiving(receiver) => receiver,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the transmitter state machine, replacing any receiver.
+    pub(crate) fn transmitter(&mut self) -> &mut Transmitter {
+        if !matches!(self.machine, Machine::Transmitting(_)) {
+            self.machine = Machine::Transmitting(Transmitter::new_with_config(self.mode, self.config));
+        }
+
+        match &mut self.machine {
+            Machine::Transmitting(transmitter) => transmitter,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Resets the progress reported to block `block` of a transfer of `total`
+    /// bytes, if known.
+    pub(crate) fn reset_progress(&mut self, block: u64, total: Option<u64>) {
+        self.block = block;
+        self.bytes = 0;
+        self.total = total;
+        self.attempt = 0;
+    }
+
+    /// Calls the progress callback for `event`, reported by the state machine.
+    fn report(&mut self, event: Event) {
+        match event {
+            Event::Ready => (self.progress)(Progress::Started),
+            Event::Packet(n) => {
+                self.bytes += n as u64;
+                if let Some(total) = self.total {
+                    self.bytes = self.bytes.min(total);
+                }
+
+                (self.progress)(Progress::Packet { block: self.block, bytes: self.bytes, total: self.total });
+                self.block += 1;
+                self.attempt = 0;
+            }
+            Event::Rejected => {
+                self.attempt += 1;
+                let (block, attempt) = (self.block, self.attempt);
+                match self.machine {
+                    Machine::Transmitting(_) => (self.progress)(Progress::Retransmit { block, attempt }),
+                    _ => (self.progress)(Progress::Nak { block, attempt }),
+                }
+            }
+            Event::Pending | Event::Done => {}
+        }
+    }
+
+    /// Fills `buf` from the inner I/O stream.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Timeout` if the inner stream times out and `Error::Io`
+    /// if reading from it fails otherwise.
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        self.inner.read_exact(buf).map_err(|e| match e.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Error::Timeout,
+            _ => Error::Io(e),
+        })
     }
 
     /// Reads a single byte from the inner I/O stream. If `abort_on_can` is
-    /// `true`, an error of `ConnectionAborted` is returned if the read byte is
-    /// `CAN`.
+    /// `true`, `Error::Cancelled` is returned if the read byte is `CAN`.
     ///
     /// # Errors
     ///
     /// Returns an error if reading from the inner stream fails or if
     /// `abort_on_can` is `true` and the read byte is `CAN`.
-    fn read_byte(&mut self, abort_on_can: bool) -> io::Result<u8> {
+    fn read_byte(&mut self, abort_on_can: bool) -> Result<u8> {
         let mut buf = [0u8; 1];
-        self.inner.read_exact(&mut buf)?;
+        self.read_exact(&mut buf)?;
 
         let byte = buf[0];
         if abort_on_can && byte == CAN {
-            return ioerr!(ConnectionAborted, "received CAN");
+            return Err(Error::Cancelled);
         }
 
         Ok(byte)
     }
 
-    /// Writes a single byte to the inner I/O stream.
+    /// The current time according to the configured clock, if any.
+    fn now(&self) -> Option<Duration> {
+        self.config.clock().map(|clock| clock())
+    }
+
+    /// The time elapsed since `start`, if a clock is configured.
+    fn elapsed_since(&self, start: Option<Duration>) -> Option<Duration> {
+        Some(self.now()?.saturating_sub(start?))
+    }
+
+    /// Writes everything the state machine has queued to the inner stream.
     ///
     /// # Errors
     ///
     /// Returns an error if writing to the inner stream fails.
-    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
-        self.inner.write_all(&[byte])
+    fn write_output(&mut self) -> Result<()> {
+        let Xmodem { inner, machine, .. } = self;
+        match machine {
+            Machine::Idle => {}
+            Machine::Receiving(receiver) => {
+                let n = receiver.output().len();
+                inner.write_all(receiver.output())?;
+                receiver.consume_output(n);
+            }
+            Machine::Transmitting(transmitter) => {
+                let n = transmitter.output().len();
+                inner.write_all(transmitter.output())?;
+                transmitter.consume_output(n);
+            }
+        }
+
+        Ok(())
     }
 
-    /// Reads a single byte from the inner I/O stream and compares it to `byte`.
-    /// If the bytes match, the byte is returned as an `Ok`. If they differ and
-    /// the read byte is not `CAN`, an error of `InvalidData` with the message
-    /// `expected` is returned. If they differ and the read byte is `CAN`, an
-    /// error of `ConnectionAborted` is returned. In either case, if they bytes
-    /// differ, a `CAN` byte is written out to the inner stream.
+    /// Feeds bytes from the inner stream to the receiver, writing out its
+    /// responses, until it reports an event other than `Event::Pending`.
     ///
     /// # Errors
     ///
-    /// Returns an error if reading from the inner stream fails, if the read
-    /// byte was not `byte`, if the read byte was `CAN` and `byte` is not `CAN`,
-    /// or if writing the `CAN` byte failed on byte mismatch.
-    fn expect_byte_or_cancel(&mut self, byte: u8, expected: &'static str) -> io::Result<u8> {
-        let b = self.read_byte(false)?;
-        if b == byte {
-            return Ok(b);
-        } else{
-            self.write_byte(CAN)?;
-            if b != CAN {
-                return ioerr!(InvalidData, expected);
-            } else {
-                return ioerr!(ConnectionAborted, expected);
+    /// Returns an error if reading or writing to the inner stream fails or if
+    /// the receiver reports an error.
+    fn poll_receiver(&mut self) -> Result<Event> {
+        if !self.receiver().is_started() {
+            (self.progress)(Progress::Started);
+            self.receiver().start();
+        }
+
+        loop {
+            self.write_output()?;
+            let start = self.now();
+            let result = match self.read_byte(false) {
+                Ok(byte) => self.receiver().receive_byte(byte),
+                Err(Error::Timeout) => {
+                    let elapsed = self.elapsed_since(start);
+                    self.receiver().timeout(elapsed)
+                }
+                Err(e) => return Err(e),
+            };
+
+            // The receiver's error takes precedence over failing to send the
+            // `CAN` bytes it queued: the sender hangs up after the first.
+            let written = self.write_output();
+            let event = result?;
+            written?;
+            match event {
+                Event::Pending => continue,
+                event => {
+                    self.report(event);
+                    return Ok(event);
+                }
             }
         }
     }
 
-    /// Reads a single byte from the inner I/O stream and compares it to `byte`.
-    /// If they differ, an error of `InvalidData` with the message `expected` is
-    /// returned. Otherwise the byte is returned. If `byte` is not `CAN` and the
-    /// read byte is `CAN`, a `ConnectionAborted` error is returned.
+    /// Feeds bytes from the inner stream to the transmitter, writing out its
+    /// queued packets, until it reports an event other than `Event::Pending`.
     ///
     /// # Errors
     ///
-    /// Returns an error if reading from the inner stream fails, or if the read
-    /// byte was not `byte`. If the read byte differed and was `CAN`, an error
-    /// of `ConnectionAborted` is returned. Otherwise, the error kind is
-    /// `InvalidData`.
-    fn expect_byte(&mut self, byte: u8, expected: &'static str) -> io::Result<u8> {
-        let b = self.read_byte(false)?;
-        if b == byte {
-            return Ok(b);
-        } else{
-            if b != CAN {
-                return ioerr!(InvalidData, expected);
-            } else {
-                return ioerr!(ConnectionAborted, "received CAN");
+    /// Returns an error if reading or writing to the inner stream fails or if
+    /// the transmitter reports an error.
+    fn poll_transmitter(&mut self) -> Result<Event> {
+        if !self.transmitter().is_started() {
+            (self.progress)(Progress::Waiting);
+            self.transmitter().start();
+        }
+
+        loop {
+            self.write_output()?;
+            let start = self.now();
+            let result = match self.read_byte(false) {
+                Ok(byte) => self.transmitter().receive_byte(byte),
+                Err(Error::Timeout) => {
+                    let elapsed = self.elapsed_since(start);
+                    self.transmitter().timeout(elapsed).map(|_| Event::Pending)
+                }
+                Err(e) => return Err(e),
+            };
+
+            // The transmitter's error takes precedence over failing to send the
+            // `CAN` bytes it queued: the receiver hangs up after the first.
+            let written = self.write_output();
+            let event = result?;
+            written?;
+            match event {
+                Event::Pending => continue,
+                event => {
+                    self.report(event);
+                    return Ok(event);
+                }
             }
         }
     }
 
-    /// Reads (downloads) a single packet from the inner stream using the XMODEM
-    /// protocol. On success, returns the number of bytes read (always 128).
+    /// Waits for the receiver to start a transmission, settling on the mode it
+    /// requests. Does nothing if the transmission has already started.
+    fn start_transmit(&mut self) -> Result<()> {
+        while !self.transmitter().is_ready() {
+            self.poll_transmitter()?;
+        }
+
+        Ok(())
+    }
+
+    /// Transmits (uploads) all of `data` followed by end of transmission. If the
+    /// length of the total data yielded by `data` is not a multiple of 128
+    /// bytes, the data is padded with zeroes and sent to the receiver.
     ///
-    /// The progress callback is called with `Progress::Started` when reception
-    /// for the first packet has started and subsequently with
-    /// `Progress::Packet` when a packet is received successfully.
+    /// Returns the number of bytes read from `data`, excluding padding zeroes.
     ///
     /// # Errors
     ///
-    /// Returns an error if reading or writing to the inner stream fails at any
-    /// point. Also returns an error if the XMODEM protocol indicates an error.
-    /// In particular, an `InvalidData` error is returned when:
+    /// Returns an error if reading from `data` or the XMODEM transfer fails.
+    pub fn transmit_all<R: io::Read>(&mut self, data: R) -> Result<usize> {
+        self.write_all_packets(data)
+    }
+
+    /// Receives (downloads) packets until end of transmission and writes them
+    /// into `into`. Returns the number of bytes received, a multiple of 128.
     ///
-    ///   * The sender's first byte for a packet isn't `EOT` or `SOH`.
-    ///   * The sender doesn't send a second `EOT` after the first.
-    ///   * The received packet numbers don't match the expected values.
+    /// # Errors
     ///
-    /// An error of kind `Interrupted` is returned if a packet checksum fails.
+    /// Returns an error if writing to `into` or the XMODEM transfer fails.
+    pub fn receive_all<W: io::Write>(&mut self, into: W) -> Result<usize> {
+        self.read_all_packets(into, None, None)
+    }
+
+    /// Receives (downloads) packets until end of transmission into `buf`.
+    /// Returns the number of bytes received, a multiple of 128.
     ///
-    /// An error of kind `ConnectionAborted` is returned if a `CAN` byte is
-    /// received when not expected.
+    /// # Errors
+    ///
+    /// Returns `Error::TooLarge`, and cancels the transfer with `CAN` instead of
+    /// acknowledging it, as soon as a packet doesn't fit in `buf`. The error
+    /// counts the bytes of that packet along with those already received. Returns an error if the XMODEM transfer fails otherwise.
+    pub fn receive_into(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let capacity = buf.len() as u64;
+        self.read_all_packets(buf, None, Some(capacity))
+    }
+
+    /// Aborts the transfer in progress by writing the configured number of
+    /// `CAN` bytes, `CAN CAN` by default, to the inner stream.
+    ///
+    /// # Errors
     ///
-    /// An error of kind `UnexpectedEof` is returned if `buf.len() < 128`.
-    pub fn read_packet(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if buf.len() < 128 && buf.len() != 0 {
-            return ioerr!(UnexpectedEof, "too short");
+    /// Returns an error if writing to the inner stream fails.
+    pub fn cancel(&mut self) -> Result<()> {
+        match &mut self.machine {
+            Machine::Receiving(receiver) => receiver.cancel(),
+            Machine::Transmitting(transmitter) => transmitter.cancel(),
+            Machine::Idle => self.transmitter().cancel(),
         }
 
-        if !self.started {
-            self.write_byte(NAK)?;
-            (self.progress)(Progress::Started);
-            self.started = true;
+        self.write_output()?;
+        self.flush()
+    }
+
+    /// Sends all of `data` followed by end of transmission, padding the final
+    /// packet with zeroes. In `Mode::Crc1k`, data is sent in 1024-byte packets
+    /// and the tail in 128-byte packets.
+    ///
+    /// Returns the number of bytes read from `data`, excluding padding zeroes.
+    fn write_all_packets<R: io::Read>(&mut self, mut data: R) -> Result<usize> {
+        self.start_transmit()?;
+
+        let block_size = match self.mode() {
+            Mode::Crc1k => 1024,
+            _ => 128,
+        };
+
+        let mut buf = [0u8; 1024];
+        let mut written = 0;
+        loop {
+            let n = data.read_max(&mut buf[..block_size])?;
+            if n == 0 {
+                self.write_packet(&[])?;
+                return Ok(written);
+            }
+
+            // A short read only happens at the end of `data`: send the tail
+            // as 128-byte packets rather than padding a whole 1024-byte one.
+            let len = if n == block_size { n } else { n.div_ceil(128) * 128 };
+            buf[n..len].iter_mut().for_each(|b| *b = 0);
+            let packet_size = if len == 1024 { 1024 } else { 128 };
+            for packet in buf[..len].chunks(packet_size) {
+                self.write_packet_with_retries(packet)?;
+            }
+
+            written += n;
+        }
+    }
+
+    /// Reads packets until end of transmission, writing their contents into
+    /// `into`. If `limit` is set, at most `limit` bytes are written and the
+    /// remainder (padding) is discarded.
+    ///
+    /// Returns the number of bytes received, a multiple of 128.
+    ///
+    /// If `capacity` is set, a packet that would take the data received past
+    /// it is answered with `CAN` instead of `ACK` and `Error::TooLarge`
+    /// returned.
+    fn read_all_packets<W: io::Write>(&mut self, mut into: W, limit: Option<u64>, capacity: Option<u64>) -> Result<usize> {
+        let mut packet = [0u8; 1024];
+        let mut received = 0;
+        self.receiver().set_capacity(capacity);
+        loop {
+            let n = self.read_packet_with_retries(&mut packet)?;
+            if n == 0 {
+                return Ok(received);
+            }
+
+            let keep = match limit {
+                Some(limit) => (limit.saturating_sub(received as u64) as usize).min(n),
+                None => n,
+            };
+            into.write_all(&packet[..keep])?;
+            received += n;
         }
-        let b = self.read_byte(true)?;
-        if b == EOT {
-            self.write_byte(NAK)?;
-            self.expect_byte(EOT, "EOT")?;
-            self.write_byte(ACK)?;
-            return Ok(0);
-        } else if b == SOH {
-            self.expect_byte_or_cancel(self.packet, "wrong packet number")?;
-            self.expect_byte_or_cancel(255 - self.packet, "wrong packet complement")?;
-            for i in 0..128 {
-                let byte = self.read_byte(false)?;
-                buf[i] = byte;
+    }
+
+    /// Reads a single packet into `buf`, retrying while the packet fails its
+    /// checksum until the receiver gives up.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::RetriesExhausted` if the packet failed `max_retries`
+    /// times in a row, or any other error returned by `read_packet`.
+    fn read_packet_with_retries(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            match self.read_packet(buf) {
+                Err(Error::BadChecksum) => continue,
+                result => return result,
             }
-            let check_sum = get_checksum(buf);
-            let cs = self.read_byte(false)?;
-            if cs == check_sum {
-                self.write_byte(ACK)?;
-                (self.progress)(Progress::Packet(self.packet));
-                if self.packet == 255 {
-                    self.packet = 0;
-                } else {
-                    self.packet += 1;
-                }
-                return Ok(128);
-            } else {
-                self.write_byte(NAK)?;
-                return ioerr!(Interrupted, "wrong checksum");
+        }
+    }
+
+    /// Sends `buf` as a single packet, retrying while the receiver responds
+    /// with `NAK` until the transmitter gives up.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::RetriesExhausted` if the packet was rejected
+    /// `max_retries` times in a row, or any other error returned by
+    /// `write_packet`.
+    fn write_packet_with_retries(&mut self, buf: &[u8]) -> Result<usize> {
+        loop {
+            match self.write_packet(buf) {
+                Err(Error::BadChecksum) => continue,
+                result => return result,
             }
+        }
+    }
 
-        } else {
-            return ioerr!(InvalidData, "InvalidData");
+    /// Reads (downloads) a single packet from the inner stream using the XMODEM
+    /// protocol. On success, returns the number of bytes read: 128 for an `SOH`
+    /// packet or 1024 for an `STX` packet.
+    ///
+    /// The progress callback is called with `Progress::Started` when reception
+    /// for the first packet has started, with `Progress::Nak` when a packet is
+    /// rejected and with `Progress::Packet` when a packet is received
+    /// successfully.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading or writing to the inner stream fails at any
+    /// point. Also returns an error if the XMODEM protocol indicates an error;
+    /// see [`Receiver::receive_byte()`]. In addition:
+    ///
+    ///   * `Error::BadChecksum` is returned, and a `NAK` byte written out, if a
+    ///     packet checksum or CRC fails, or if a packet timed out or arrived
+    ///     garbled and is asked for again.
+    ///   * `Error::Timeout` is returned if the inner stream times out before
+    ///     the sender answers, or too many times in a row after.
+    ///
+    /// An `Error::Io` of kind `UnexpectedEof` is returned if `buf.len() < 128`,
+    /// or if an `STX` packet arrives and `buf.len() < 1024`. In the latter
+    /// case, a `CAN` byte is written out to the inner stream.
+    pub fn read_packet(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.len() < 128 && !buf.is_empty() {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "too short")));
+        }
+
+        self.receiver().accept_1k(buf.len() >= 1024);
+        match self.poll_receiver()? {
+            Event::Packet(n) => {
+                let packet = self.receiver().packet();
+                let len = n.min(buf.len());
+                buf[..len].copy_from_slice(&packet[..len]);
+                Ok(n)
+            }
+            Event::Done => Ok(0),
+            Event::Rejected | Event::Ready | Event::Pending => Err(Error::BadChecksum),
         }
     }
 
     /// Sends (uploads) a single packet to the inner stream using the XMODEM
     /// protocol. If `buf` is empty, end of transmissions is sent. Users of this
     /// interface should ensure that `write_packet(&[])` is called when data
-    /// transmission is complete. On success, returns the number of bytes
-    /// written.
+    /// transmission is complete. If `buf.len() >= 1024`, the first 1024 bytes
+    /// are sent as an `STX` packet; otherwise the first 128 bytes are sent as
+    /// an `SOH` packet. On success, returns the number of bytes written.
     ///
     /// The progress callback is called with `Progress::Waiting` before waiting
-    /// for the receiver's `NAK`, `Progress::Started` when transmission of the
-    /// first packet has started and subsequently with `Progress::Packet` when a
-    /// packet is sent successfully.
+    /// for the receiver's `NAK` or `C`, `Progress::Started` when transmission of
+    /// the first packet has started, `Progress::Retransmit` when the receiver
+    /// rejects a packet and `Progress::Packet` when a packet is sent
+    /// successfully.
     ///
     /// # Errors
     ///
     /// Returns an error if reading or writing to the inner stream fails at any
-    /// point. Also returns an error if the XMODEM protocol indicates an error.
-    /// In particular, an `InvalidData` error is returned when:
-    ///
-    ///   * The receiver's first byte isn't a `NAK`.
-    ///   * The receiver doesn't respond with a `NAK` to the first `EOT`.
-    ///   * The receiver doesn't respond with an `ACK` to the second `EOT`.
-    ///   * The receiver responds to a complete packet with something besides
-    ///     `ACK` or `NAK`.
-    ///
-    /// An error of kind `UnexpectedEof` is returned if `buf.len() < 128 &&
-    /// buf.len() != 0`.
+    /// point. Also returns an error if the XMODEM protocol indicates an error;
+    /// see [`Transmitter::receive_byte()`].
     ///
-    /// An error of kind `ConnectionAborted` is returned if a `CAN` byte is
-    /// received when not expected.
+    /// An `Error::Io` of kind `UnexpectedEof` is returned if `buf` is not empty
+    /// but shorter than 128 bytes.
     ///
-    /// An error of kind `Interrupted` is returned if a packet checksum fails.
-    pub fn write_packet(&mut self, buf: &[u8]) -> io::Result<usize> {
-        if buf.len() < 128 && buf.len() != 0 {
-            return ioerr!(UnexpectedEof, "too short");
-        }
-        if !self.started {
-            (self.progress)(Progress::Waiting);
-            self.expect_byte(NAK, "not NAK")?;
-            self.started = true;
-            (self.progress)(Progress::Started);
-        }
-        if buf.len() == 0 {
-            self.write_byte(EOT)?;
-            self.expect_byte(NAK, "not NAK")?;
-            self.write_byte(EOT)?;
-            self.expect_byte(ACK, "not NAK")?;
-            return Ok(0);
-        }
-        self.write_byte(SOH)?;
-        self.write_byte(self.packet)?;
-        self.write_byte(255 - self.packet)?;
-        let mut cnt = 0;
-        for b in buf {
-            self.write_byte(*b)?;
-            cnt += 1;
+    /// `Error::BadChecksum` is returned if the receiver rejects the packet with
+    /// a `NAK`.
+    pub fn write_packet(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.len() < 128 && !buf.is_empty() {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "too short")));
         }
-        self.write_byte(get_checksum(buf))?;
-        let b = self.read_byte(true)?;
-        if b == NAK {
-            return ioerr!(Interrupted, "Interrupted");
-        } else if b == ACK {
-            if self.packet == 255 {
-                self.packet = 0;
-            } else {
-                self.packet += 1;
+
+        self.start_transmit()?;
+        self.transmitter().send_packet(buf)?;
+        loop {
+            match self.poll_transmitter()? {
+                Event::Packet(n) => return Ok(n),
+                Event::Done => return Ok(0),
+                Event::Rejected => return Err(Error::BadChecksum),
+                Event::Ready | Event::Pending => continue,
             }
-            (self.progress)(Progress::Packet(self.packet));
-            return Ok(cnt);
-        } else {
-            return ioerr!(InvalidData, "InvalidData");
         }
     }
 
@@ -365,7 +688,7 @@ impl<T: io::Read + io::Write> Xmodem<T> {
     ///
     /// It is considered an error if not all bytes could be written due to I/O
     /// errors or EOF being reached.
-    pub fn flush(&mut self) -> io::Result<()> {
-        self.inner.flush()
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.inner.flush()?)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,753 @@
+use core::time::Duration;
+
+use shim::io;
+
+use crate::config::{expired, MAX_CANCEL_COUNT};
+use crate::{get_checksum, get_crc, Error, Mode, Result, XmodemConfig};
+use crate::{ACK, CAN, CRC, EOT, NAK, SOH, STX};
+
+/// Something that happened in response to input fed to a [`Receiver`] or a
+/// [`Transmitter`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// Nothing to report; keep feeding input.
+    Pending,
+    /// The receiver has started the transfer and the transmitter may send the
+    /// first packet. Only reported by a `Transmitter`.
+    Ready,
+    /// A packet of `.0` bytes was received and acknowledged, or a sent packet
+    /// of `.0` bytes was acknowledged by the receiver.
+    Packet(usize),
+    /// A received packet failed its checksum and was rejected with `NAK`, or a
+    /// sent packet was rejected by the receiver and must be sent again.
+    Rejected,
+    /// The transfer is complete.
+    Done,
+}
+
+/// Bytes queued to be sent to the other side.
+struct Output<const N: usize> {
+    buf: [u8; N],
+    start: usize,
+    end: usize,
+}
+
+impl<const N: usize> Output<N> {
+    const fn new() -> Self {
+        Output { buf: [0; N], start: 0, end: 0 }
+    }
+
+    /// Queues `bytes`, which must fit alongside the bytes not yet consumed.
+    fn push(&mut self, bytes: &[u8]) {
+        if self.end + bytes.len() > N {
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+
+        self.buf[self.end..self.end + bytes.len()].copy_from_slice(bytes);
+        self.end += bytes.len();
+    }
+
+    fn pending(&self) -> &[u8] {
+        &self.buf[self.start..self.end]
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.start = (self.start + n).min(self.end);
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum RxState {
+    Idle,
+    Header,
+    Number,
+    Complement,
+    Payload,
+    Purge,
+    SecondEot,
+    Done,
+}
+
+/// Sans-I/O XMODEM receiver.
+///
+/// A `Receiver` performs no I/O of its own. Bytes from the sender are fed in
+/// with [`receive_byte()`](Receiver::receive_byte), elapsed read timeouts are
+/// reported with [`timeout()`](Receiver::timeout), and the bytes to send back
+/// are taken from [`output()`](Receiver::output) and marked as sent with
+/// [`consume_output()`](Receiver::consume_output). The transfer starts on the
+/// first call to [`start()`](Receiver::start) or `receive_byte()`.
+///
+/// After an error, the receiver expects the start of a new packet. Once the
+/// transfer is under way, a packet that times out or is garbled is asked for
+/// again with `NAK`, and a packet sent again because its `ACK` was lost is
+/// acknowledged and discarded.
+pub struct Receiver {
+    config: XmodemConfig,
+    idle: Duration,
+    waited: Duration,
+    mode: Mode,
+    packet: u8,
+    number: u8,
+    previous: Option<u8>,
+    state: RxState,
+    handshaking: bool,
+    handshakes: usize,
+    errors: usize,
+    accept_1k: bool,
+    capacity: Option<u64>,
+    accepted: u64,
+    size: usize,
+    len: usize,
+    buf: [u8; 1024 + 2],
+    output: Output<{ 1 + MAX_CANCEL_COUNT }>,
+}
+
+impl Receiver {
+    /// Returns a new `Receiver` requesting the error-detection `mode`.
+    pub fn new(mode: Mode) -> Receiver {
+        Receiver::new_with_config(mode, XmodemConfig::new())
+    }
+
+    /// Returns a new `Receiver` requesting the error-detection `mode` with the
+    /// retry, timeout and cancel policy `config`.
+    pub fn new_with_config(mode: Mode, config: XmodemConfig) -> Receiver {
+        Receiver {
+            config,
+            idle: Duration::ZERO,
+            waited: Duration::ZERO,
+            mode,
+            packet: 1,
+            number: 1,
+            previous: None,
+            state: RxState::Idle,
+            handshaking: false,
+            handshakes: 0,
+            errors: 0,
+            accept_1k: true,
+            capacity: None,
+            accepted: 0,
+            size: 0,
+            len: 0,
+            buf: [0; 1024 + 2],
+            output: Output::new(),
+        }
+    }
+
+    /// Returns the error-detection mode in use. Once the sender has responded
+    /// to the handshake, this is the negotiated mode.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Sets whether 1024-byte `STX` packets are accepted. If they aren't, an
+    /// `STX` packet cancels the transfer. Defaults to `true`.
+    pub fn accept_1k(&mut self, accept: bool) {
+        self.accept_1k = accept;
+    }
+
+    /// Limits the packets accepted from now on to `capacity` bytes in total,
+    /// or lifts the limit if `capacity` is `None`. A packet that would take the
+    /// total past the limit is answered with `CAN` rather than `ACK`.
+    pub fn set_capacity(&mut self, capacity: Option<u64>) {
+        self.capacity = capacity;
+        self.accepted = 0;
+    }
+
+    /// Prepares the receiver for a new handshake expecting packet `packet`
+    /// next, keeping the current mode.
+    pub fn restart(&mut self, packet: u8) {
+        self.packet = packet;
+        self.previous = None;
+        self.state = RxState::Idle;
+        self.handshaking = false;
+        self.handshakes = 0;
+        self.errors = 0;
+        self.idle = Duration::ZERO;
+        self.waited = Duration::ZERO;
+    }
+
+    /// Returns `true` once the transfer has been started.
+    pub fn is_started(&self) -> bool {
+        self.state != RxState::Idle
+    }
+
+    /// Starts the transfer by queueing `C` (in a CRC mode) or `NAK`. Does
+    /// nothing if the transfer has already started.
+    pub fn start(&mut self) {
+        if self.state != RxState::Idle {
+            return;
+        }
+
+        if self.mode != Mode::Checksum {
+            self.output.push(&[CRC]);
+        } else {
+            self.output.push(&[NAK]);
+        }
+        self.handshakes = 1;
+        self.handshaking = true;
+        self.state = RxState::Header;
+    }
+
+    /// Bytes that should be sent to the sender.
+    pub fn output(&self) -> &[u8] {
+        self.output.pending()
+    }
+
+    /// Marks the first `n` bytes of [`output()`](Receiver::output) as sent.
+    pub fn consume_output(&mut self, n: usize) {
+        self.output.consume(n)
+    }
+
+    /// The contents of the last packet received, valid after
+    /// `Event::Packet` was returned.
+    pub fn packet(&self) -> &[u8] {
+        &self.buf[..self.size]
+    }
+
+    /// Reports that a read timed out after `elapsed` without a byte from the
+    /// sender. If `elapsed` is `None`, the whole interval is taken to have
+    /// passed; see [`XmodemConfig`].
+    ///
+    /// While waiting for the sender's first response, the initial `NAK` or `C`
+    /// is queued again every NAK interval, until the start timeout passes or,
+    /// without one, up to `max_retries` times. In a CRC
+    /// mode, `C` is sent up to `crc_attempts` times in total, after
+    /// which the receiver falls back to `Mode::Checksum` and sends `NAK`.
+    ///
+    /// After the handshake, once the byte timeout passes, the packet is asked
+    /// for again with `NAK` and `Event::Rejected` returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Timeout` if the start timeout has passed or the
+    /// handshake was resent `max_retries` times, or, after the handshake, if
+    /// the packet was asked for `max_retries` times in a row.
+    pub fn timeout(&mut self, elapsed: Option<Duration>) -> Result<Event> {
+        if self.state == RxState::Header && self.handshaking {
+            let start_timeout = self.config.start_timeout();
+            if start_timeout.is_some() && expired(&mut self.waited, elapsed, start_timeout) {
+                return Err(Error::Timeout);
+            }
+            if !expired(&mut self.idle, elapsed, self.config.nak_interval()) {
+                return Ok(Event::Pending);
+            }
+            if start_timeout.is_none() && self.handshakes > self.config.max_retries() {
+                return Err(Error::Timeout);
+            }
+
+            if self.mode != Mode::Checksum && self.handshakes < self.config.crc_attempts() {
+                self.output.push(&[CRC]);
+            } else {
+                self.mode = Mode::Checksum;
+                self.output.push(&[NAK]);
+            }
+            self.handshakes += 1;
+            return Ok(Event::Pending);
+        }
+
+        if !expired(&mut self.idle, elapsed, self.config.byte_timeout()) {
+            return Ok(Event::Pending);
+        }
+
+        match self.state {
+            RxState::Idle | RxState::Done => Err(Error::Timeout),
+            // the line went quiet after garbled input, already counted
+            RxState::Purge => {
+                self.output.push(&[NAK]);
+                self.state = RxState::Header;
+                Ok(Event::Rejected)
+            }
+            RxState::SecondEot => self.reject(Error::Timeout),
+            _ => {
+                self.state = RxState::Header;
+                self.reject(Error::Timeout)
+            }
+        }
+    }
+
+    /// Aborts the transfer by queueing the configured number of `CAN` bytes.
+    /// Any further input is ignored until the receiver is restarted.
+    pub fn cancel(&mut self) {
+        self.queue_cancel();
+        self.state = RxState::Done;
+    }
+
+    /// Queues the configured number of `CAN` bytes.
+    fn queue_cancel(&mut self) {
+        let cancel = [CAN; MAX_CANCEL_COUNT];
+        self.output.push(&cancel[..self.config.cancel_count()]);
+    }
+
+    /// Feeds a single byte received from the sender.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the XMODEM protocol indicates an error:
+    ///
+    ///   * `Error::UnexpectedByte` if the sender's first response to the
+    ///     handshake isn't `EOT`, `SOH` or `STX`. Later, if a packet doesn't
+    ///     start with one of those, or the sender doesn't send a second `EOT`
+    ///     after the first, `max_retries` times in a row; until then, the
+    ///     receiver asks again with `NAK` once any noise has passed.
+    ///   * `Error::ComplementMismatch` if a packet's number and its complement
+    ///     disagree `max_retries` times in a row. Until then, the packet is
+    ///     asked for again.
+    ///   * `Error::PacketNumberMismatch`, with a `CAN` byte queued, if a packet
+    ///     is neither the expected one nor the previous one sent again.
+    ///   * `Error::Cancelled` if a `CAN` byte is received when not expected.
+    ///   * `Error::RetriesExhausted` if a packet failed its checksum too many
+    ///     times in a row.
+    ///   * An `Error::Io` of kind `UnexpectedEof`, with a `CAN` byte queued, if
+    ///     an `STX` packet arrives and 1024-byte packets aren't accepted.
+    ///   * `Error::TooLarge`, with the transfer cancelled, if a packet doesn't
+    ///     fit in the capacity set with
+    ///     [`set_capacity()`](Receiver::set_capacity).
+    pub fn receive_byte(&mut self, byte: u8) -> Result<Event> {
+        self.start();
+        self.idle = Duration::ZERO;
+
+        match self.state {
+            RxState::Idle | RxState::Purge | RxState::Done => Ok(Event::Pending),
+            RxState::Header => {
+                match byte {
+                    EOT => {
+                        self.handshaking = false;
+                        self.output.push(&[NAK]);
+                        self.state = RxState::SecondEot;
+                        Ok(Event::Pending)
+                    }
+                    SOH | STX => {
+                        self.handshaking = false;
+                        self.size = if byte == STX { 1024 } else { 128 };
+                        if byte == STX && !self.accept_1k {
+                            self.queue_cancel();
+                            let e = io::Error::new(io::ErrorKind::UnexpectedEof, "too short");
+                            return self.fail(Error::Io(e));
+                        }
+
+                        self.state = RxState::Number;
+                        Ok(Event::Pending)
+                    }
+                    CAN => self.fail(Error::Cancelled),
+                    b if self.handshaking => self.fail(Error::UnexpectedByte(b)),
+                    // noise, or the tail of a packet we lost track of
+                    b => self.purge(Error::UnexpectedByte(b)),
+                }
+            }
+            RxState::Number => {
+                let expected = byte == self.packet || Some(byte) == self.previous;
+                if byte == CAN && !expected {
+                    self.queue_cancel();
+                    return self.fail(Error::Cancelled);
+                }
+
+                self.number = byte;
+                self.state = RxState::Complement;
+                Ok(Event::Pending)
+            }
+            RxState::Complement => {
+                if byte != 255 - self.number {
+                    // a garbled header: wait it out and ask again
+                    let e = Error::ComplementMismatch { expected: 255 - self.number, got: byte };
+                    return self.purge(e);
+                }
+
+                if self.number != self.packet && Some(self.number) != self.previous {
+                    self.queue_cancel();
+                    return self.fail(Error::PacketNumberMismatch { expected: self.packet, got: self.number });
+                }
+
+                self.len = 0;
+                self.state = RxState::Payload;
+                Ok(Event::Pending)
+            }
+            RxState::Payload => {
+                self.buf[self.len] = byte;
+                self.len += 1;
+
+                let check_len = if self.mode == Mode::Checksum { 1 } else { 2 };
+                if self.len < self.size + check_len {
+                    return Ok(Event::Pending);
+                }
+
+                self.state = RxState::Header;
+                let (payload, check) = self.buf[..self.len].split_at(self.size);
+                let valid = match self.mode {
+                    Mode::Checksum => check[0] == get_checksum(payload),
+                    Mode::Crc | Mode::Crc1k => u16::from_be_bytes([check[0], check[1]]) == get_crc(payload),
+                };
+
+                if valid && self.number != self.packet {
+                    // the sender missed our `ACK` for this packet
+                    self.output.push(&[ACK]);
+                    self.errors = 0;
+                    return Ok(Event::Pending);
+                }
+
+                if valid {
+                    let size = self.accepted + self.size as u64;
+                    if let Some(capacity) = self.capacity.filter(|&capacity| size > capacity) {
+                        self.cancel();
+                        return Err(Error::TooLarge { size, capacity });
+                    }
+
+                    self.accepted = size;
+                    self.output.push(&[ACK]);
+                    self.previous = Some(self.packet);
+                    self.packet = self.packet.wrapping_add(1);
+                    self.errors = 0;
+                    return Ok(Event::Packet(self.size));
+                }
+
+                self.reject(Error::RetriesExhausted)
+            }
+            RxState::SecondEot => match byte {
+                EOT => {
+                    self.output.push(&[ACK]);
+                    self.state = RxState::Done;
+                    Ok(Event::Done)
+                }
+                CAN => self.fail(Error::Cancelled),
+                b => self.reject(Error::UnexpectedByte(b)),
+            },
+        }
+    }
+
+    /// Ignores input until the line goes quiet, after which the packet is
+    /// asked for again, or returns `e` once input was garbled `max_retries`
+    /// times in a row.
+    fn purge(&mut self, e: Error) -> Result<Event> {
+        self.errors += 1;
+        if self.errors >= self.config.max_retries() {
+            return self.fail(e);
+        }
+
+        self.state = RxState::Purge;
+        Ok(Event::Pending)
+    }
+
+    /// Asks the sender for the packet, or the second `EOT`, again with `NAK`,
+    /// or returns `e` once that has happened `max_retries` times in a row.
+    fn reject(&mut self, e: Error) -> Result<Event> {
+        self.output.push(&[NAK]);
+        self.errors += 1;
+        if self.errors >= self.config.max_retries() {
+            return self.fail(e);
+        }
+
+        Ok(Event::Rejected)
+    }
+
+    /// Resets the receiver to expect the start of a new packet and returns `e`.
+    fn fail(&mut self, e: Error) -> Result<Event> {
+        self.state = RxState::Header;
+        self.errors = 0;
+        Err(e)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum TxState {
+    Idle,
+    Waiting,
+    Ready,
+    AwaitAck,
+    FirstEot,
+    SecondEot,
+    Done,
+}
+
+/// Sans-I/O XMODEM transmitter.
+///
+/// A `Transmitter` performs no I/O of its own. Bytes from the receiver are fed
+/// in with [`receive_byte()`](Transmitter::receive_byte), and the bytes to send
+/// are taken from [`output()`](Transmitter::output) and marked as sent with
+/// [`consume_output()`](Transmitter::consume_output). Once `Event::Ready` has
+/// been returned, packets are queued with
+/// [`send_packet()`](Transmitter::send_packet) and end of transmission with
+/// [`finish()`](Transmitter::finish), each time after the previous packet was
+/// acknowledged with `Event::Packet`. A rejected packet must be sent again.
+pub struct Transmitter {
+    config: XmodemConfig,
+    idle: Duration,
+    mode: Mode,
+    packet: u8,
+    state: TxState,
+    errors: usize,
+    timeouts: usize,
+    size: usize,
+    output: Output<{ 3 + 1024 + 2 }>,
+}
+
+impl Transmitter {
+    /// Returns a new `Transmitter` using the error-detection `mode`.
+    pub fn new(mode: Mode) -> Transmitter {
+        Transmitter::new_with_config(mode, XmodemConfig::new())
+    }
+
+    /// Returns a new `Transmitter` using the error-detection `mode` with the
+    /// retry, timeout and cancel policy `config`.
+    pub fn new_with_config(mode: Mode, config: XmodemConfig) -> Transmitter {
+        Transmitter {
+            config,
+            idle: Duration::ZERO,
+            mode,
+            packet: 1,
+            state: TxState::Idle,
+            errors: 0,
+            timeouts: 0,
+            size: 0,
+            output: Output::new(),
+        }
+    }
+
+    /// Returns the error-detection mode in use. Once the receiver has started
+    /// the transfer, this is the negotiated mode.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Returns `true` if the receiver has started the transfer and the next
+    /// packet may be sent.
+    pub fn is_ready(&self) -> bool {
+        self.state == TxState::Ready
+    }
+
+    /// Prepares the transmitter for a new handshake sending packet `packet`
+    /// next, keeping the current mode.
+    pub fn restart(&mut self, packet: u8) {
+        self.packet = packet;
+        self.state = TxState::Idle;
+        self.errors = 0;
+        self.timeouts = 0;
+        self.idle = Duration::ZERO;
+    }
+
+    /// Returns `true` once the transmitter has started waiting for the
+    /// receiver.
+    pub fn is_started(&self) -> bool {
+        self.state != TxState::Idle
+    }
+
+    /// Starts waiting for the receiver's `NAK` or `C`. Does nothing if the
+    /// transfer has already started.
+    pub fn start(&mut self) {
+        if self.state == TxState::Idle {
+            self.state = TxState::Waiting;
+        }
+    }
+
+    /// Bytes that should be sent to the receiver.
+    pub fn output(&self) -> &[u8] {
+        self.output.pending()
+    }
+
+    /// Marks the first `n` bytes of [`output()`](Transmitter::output) as sent.
+    pub fn consume_output(&mut self, n: usize) {
+        self.output.consume(n)
+    }
+
+    /// Reports that a read timed out after `elapsed` without a byte from the
+    /// receiver. If `elapsed` is `None`, the whole timeout is taken to have
+    /// passed; see [`XmodemConfig`].
+    ///
+    /// While waiting for the receiver to start the transfer, the start timeout
+    /// applies if it's set, and the byte timeout otherwise. Nothing is sent
+    /// again on a timeout: a receiver missing a packet or `EOT` asks for it
+    /// with `NAK`, and resending unasked would pair its `ACK` with the wrong
+    /// copy.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Timeout` if the receiver didn't start the transfer in
+    /// time, or sent nothing for `max_retries` byte timeouts in a row while a
+    /// packet or `EOT` was awaiting its answer.
+    pub fn timeout(&mut self, elapsed: Option<Duration>) -> Result<()> {
+        let timeout = match self.state {
+            TxState::Waiting => self.config.start_timeout().or(self.config.byte_timeout()),
+            _ => self.config.byte_timeout(),
+        };
+
+        if !expired(&mut self.idle, elapsed, timeout) {
+            return Ok(());
+        }
+
+        match self.state {
+            TxState::AwaitAck | TxState::FirstEot | TxState::SecondEot => {
+                self.timeouts += 1;
+                if self.timeouts >= self.config.max_retries() {
+                    self.fail(Error::Timeout)?;
+                }
+
+                Ok(())
+            }
+            _ => Err(Error::Timeout),
+        }
+    }
+
+    /// Aborts the transfer by queueing the configured number of `CAN` bytes
+    /// in place of any unsent output. Any further input is ignored until the
+    /// transmitter is restarted.
+    pub fn cancel(&mut self) {
+        let n = self.output.pending().len();
+        self.output.consume(n);
+
+        let cancel = [CAN; MAX_CANCEL_COUNT];
+        self.output.push(&cancel[..self.config.cancel_count()]);
+        self.state = TxState::Done;
+    }
+
+    /// Queues `buf` as a single packet. If `buf.len() >= 1024`, the first 1024
+    /// bytes are sent as an `STX` packet; otherwise the first 128 bytes are
+    /// sent as an `SOH` packet. If `buf` is empty, end of transmission is
+    /// queued instead. Returns the number of bytes queued.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Error::Io` of kind `UnexpectedEof` if `buf` is not empty but
+    /// shorter than 128 bytes, and of kind `InvalidInput` if the transmitter
+    /// isn't ready for a packet.
+    pub fn send_packet(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.len() < 128 && !buf.is_empty() {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "too short")));
+        }
+        if buf.is_empty() {
+            self.finish()?;
+            return Ok(0);
+        }
+        if self.state != TxState::Ready {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput, "not ready")));
+        }
+
+        let (header, size) = if buf.len() >= 1024 { (STX, 1024) } else { (SOH, 128) };
+        let buf = &buf[..size];
+        self.output.push(&[header, self.packet, 255 - self.packet]);
+        self.output.push(buf);
+        match self.mode {
+            Mode::Checksum => self.output.push(&[get_checksum(buf)]),
+            Mode::Crc | Mode::Crc1k => self.output.push(&get_crc(buf).to_be_bytes()),
+        }
+
+        self.size = size;
+        self.state = TxState::AwaitAck;
+        Ok(size)
+    }
+
+    /// Queues end of transmission.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Error::Io` of kind `InvalidInput` if the transmitter isn't
+    /// ready for a packet.
+    pub fn finish(&mut self) -> Result<()> {
+        if self.state != TxState::Ready {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput, "not ready")));
+        }
+
+        self.output.push(&[EOT]);
+        self.state = TxState::FirstEot;
+        Ok(())
+    }
+
+    /// Feeds a single byte received from the receiver.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the XMODEM protocol indicates an error:
+    ///
+    ///   * `Error::UnexpectedByte` if the receiver's first byte isn't a `NAK`
+    ///     (or a `C` in a CRC mode), or if the receiver doesn't respond with a
+    ///     `NAK` to the first `EOT` or an `ACK` to the second, `max_retries`
+    ///     times in a row. Before that, the `EOT` is sent again.
+    ///   * `Error::Cancelled` if a `CAN` byte is received when not expected.
+    ///   * `Error::RetriesExhausted` if a packet was rejected too many times in
+    ///     a row. Any response to a packet besides `ACK` or `CAN` counts as a
+    ///     rejection.
+    pub fn receive_byte(&mut self, byte: u8) -> Result<Event> {
+        self.start();
+        self.idle = Duration::ZERO;
+        self.timeouts = 0;
+
+        match self.state {
+            TxState::Idle | TxState::Ready | TxState::Done => Ok(Event::Pending),
+            TxState::Waiting => match byte {
+                NAK => {
+                    self.mode = Mode::Checksum;
+                    self.ready()
+                }
+                CRC if self.mode != Mode::Checksum => self.ready(),
+                CRC => Ok(Event::Pending),
+                CAN => Err(Error::Cancelled),
+                b => Err(Error::UnexpectedByte(b)),
+            },
+            TxState::AwaitAck => match byte {
+                ACK => {
+                    self.packet = self.packet.wrapping_add(1);
+                    self.errors = 0;
+                    self.state = TxState::Ready;
+                    Ok(Event::Packet(self.size))
+                }
+                CAN => self.fail(Error::Cancelled),
+                // a `NAK`, or an `ACK` or `NAK` garbled on the way
+                _ => self.reject(Error::RetriesExhausted),
+            },
+            TxState::FirstEot => match byte {
+                NAK => {
+                    self.output.push(&[EOT]);
+                    self.state = TxState::SecondEot;
+                    Ok(Event::Pending)
+                }
+                CAN => self.fail(Error::Cancelled),
+                b => self.resend_eot(Error::UnexpectedByte(b)),
+            },
+            TxState::SecondEot => match byte {
+                ACK => {
+                    self.state = TxState::Done;
+                    Ok(Event::Done)
+                }
+                CAN => self.fail(Error::Cancelled),
+                b => self.resend_eot(Error::UnexpectedByte(b)),
+            },
+        }
+    }
+
+    /// Marks the packet as needing to be sent again, or returns `e` once it
+    /// has been rejected `max_retries` times in a row.
+    fn reject(&mut self, e: Error) -> Result<Event> {
+        self.state = TxState::Ready;
+        self.errors += 1;
+        if self.errors >= self.config.max_retries() {
+            return self.fail(e);
+        }
+
+        Ok(Event::Rejected)
+    }
+
+    /// Queues `EOT` again, or returns `e` once that has happened `max_retries`
+    /// times in a row.
+    fn resend_eot(&mut self, e: Error) -> Result<Event> {
+        self.errors += 1;
+        if self.errors >= self.config.max_retries() {
+            return self.fail(e);
+        }
+
+        self.output.push(&[EOT]);
+        Ok(Event::Pending)
+    }
+
+    /// Marks the receiver as having started the transfer.
+    fn ready(&mut self) -> Result<Event> {
+        self.state = TxState::Ready;
+        Ok(Event::Ready)
+    }
+
+    /// Resets the transmitter to send its next packet again and returns `e`.
+    fn fail(&mut self, e: Error) -> Result<Event> {
+        self.state = TxState::Ready;
+        self.errors = 0;
+        self.timeouts = 0;
+        Err(e)
+    }
+}
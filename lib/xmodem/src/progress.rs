@@ -4,20 +4,30 @@
 /// methods like [`Xmodem::transmit_with_progress()`],
 /// [`Xmodem::receive_with_progress()`], and [`Xmodem::new_with_progress()`]. It
 /// is intended to be used by progress indicators or for debugging purposes.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Progress {
     /// Waiting for receiver to send NAK.
     Waiting,
     /// Download/upload has started.
     Started,
-    /// Packet `.0` was transmitted/received.
-    Packet(u8),
-    NAK,
-    Unknown,
+    /// Block `block` was transmitted/received, bringing the number of bytes
+    /// transferred so far to `bytes` out of `total`.
+    ///
+    /// Blocks are counted from 1 and, unlike packet numbers, don't wrap; in
+    /// YMODEM, block 0 carries the file's metadata. `total` is only known in
+    /// YMODEM, where `bytes` excludes the padding of the final packet.
+    Packet { block: u64, bytes: u64, total: Option<u64> },
+    /// The receiver rejected block `block` with NAK and it is being sent again
+    /// for the `attempt`th time in a row.
+    Retransmit { block: u64, attempt: usize },
+    /// Block `block` failed its checksum and was rejected with NAK for the
+    /// `attempt`th time in a row.
+    Nak { block: u64, attempt: usize },
 }
 
-/// Type for progress callbacks.
+/// Type of the default progress callback. Any `FnMut(Progress)`, including
+/// closures with state, can be used as a progress callback.
 pub type ProgressFn = fn(Progress);
 
 /// Noop progress callback.
-pub fn noop(_: Progress) {  }
\ No newline at end of file
+pub fn noop(_: Progress) {  }
@@ -1,19 +1,35 @@
 use super::*;
-use std::sync::mpsc::{Receiver, Sender, channel};
+use std::sync::mpsc::{self, channel};
+use std::sync::mpsc::RecvTimeoutError;
 use std::io::Cursor;
+use std::time::Duration;
 
-struct Pipe(Sender<u8>, Receiver<u8>, Vec<u8>);
+struct Pipe(mpsc::Sender<u8>, mpsc::Receiver<u8>, Vec<u8>, Option<Duration>);
 
 fn pipe() -> (Pipe, Pipe) {
     let ((tx1, rx1), (tx2, rx2)) = (channel(), channel());
-    (Pipe(tx1, rx2, vec![]), Pipe(tx2, rx1, vec![]))
+    (Pipe(tx1, rx2, vec![], None), Pipe(tx2, rx1, vec![], None))
+}
+
+impl Pipe {
+    /// Makes reads from this end fail with `TimedOut` after `t`.
+    fn timeout(mut self, t: Duration) -> Pipe {
+        self.3 = Some(t);
+        self
+    }
 }
 
 impl io::Read for Pipe {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         for i in 0..buf.len() {
-            match self.1.recv() {
+            let byte = match self.3 {
+                Some(t) => self.1.recv_timeout(t).map_err(|e| e == RecvTimeoutError::Timeout),
+                None => self.1.recv().map_err(|_| false),
+            };
+
+            match byte {
                 Ok(byte) => buf[i] = byte,
+                Err(true) if i == 0 => return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out")),
                 Err(_) => return Ok(i)
             }
         }
@@ -40,6 +56,74 @@ impl io::Write for Pipe {
     }
 }
 
+/// Faults a `lossy_pipe` injects into each byte, as probabilities in parts
+/// per thousand.
+#[derive(Debug, Copy, Clone, Default)]
+struct Faults {
+    drop: u32,
+    flip: u32,
+    duplicate: u32,
+    delay: u32,
+}
+
+/// A small xorshift64* generator, so a failing seed can be replayed.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn chance(&mut self, per_mille: u32) -> bool {
+        self.below(1000) < per_mille as u64
+    }
+}
+
+/// Forwards bytes from `from` to `to`, applying `faults` with `rng`, until
+/// either end hangs up.
+fn relay(from: mpsc::Receiver<u8>, to: mpsc::Sender<u8>, mut rng: Rng, faults: Faults) {
+    std::thread::spawn(move || {
+        for mut byte in from.iter() {
+            if rng.chance(faults.delay) {
+                std::thread::sleep(Duration::from_millis(rng.below(15)));
+            }
+            if rng.chance(faults.drop) {
+                continue;
+            }
+            if rng.chance(faults.flip) {
+                byte ^= 1 << rng.below(8);
+            }
+            let copies = if rng.chance(faults.duplicate) { 2 } else { 1 };
+            for _ in 0..copies {
+                if to.send(byte).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+/// Like `pipe`, but with `faults` injected in both directions by relay
+/// threads seeded from `seed`. Reads from either end time out after `t`.
+fn lossy_pipe(seed: u64, faults: Faults, t: Duration) -> (Pipe, Pipe) {
+    let ((tx1, rx1), (tx2, rx2)) = (channel(), channel());
+    let ((tx3, rx3), (tx4, rx4)) = (channel(), channel());
+    relay(rx1, tx3, Rng::new(seed), faults);
+    relay(rx2, tx4, Rng::new(!seed), faults);
+    (Pipe(tx1, rx4, vec![], Some(t)), Pipe(tx2, rx3, vec![], Some(t)))
+}
+
 #[test]
 fn test_loop() {
     let mut input = [0u8; 384];
@@ -71,59 +155,89 @@ fn read_byte() {
         .read_byte(true)
         .expect_err("abort on CAN");
 
-    assert_eq!(e.kind(), io::ErrorKind::ConnectionAborted);
+    assert!(matches!(e, Error::Cancelled));
+}
+
+/// Drains and returns everything `receiver` has queued to send.
+fn take_output(receiver: &mut Receiver) -> Vec<u8> {
+    let output = receiver.output().to_vec();
+    receiver.consume_output(output.len());
+    output
+}
+
+/// Returns a `Receiver` in `mode` that has already started the transfer.
+fn started_receiver(mode: Mode) -> Receiver {
+    let mut receiver = Receiver::new(mode);
+    receiver.start();
+    take_output(&mut receiver);
+    receiver
+}
+
+/// Feeds all of `bytes` to `transmitter`, returning the last event.
+fn feed(transmitter: &mut Transmitter, bytes: &[u8]) -> Result<Event> {
+    let mut event = Event::Pending;
+    for &b in bytes {
+        event = transmitter.receive_byte(b)?;
+    }
+    Ok(event)
 }
 
 #[test]
 fn test_expect_byte() {
-    let mut xmodem = Xmodem::new(Cursor::new(vec![1, 1]));
-    assert_eq!(xmodem.expect_byte(1, "1").expect("expected"), 1);
-    let e = xmodem.expect_byte(2, "1, please").expect_err("expect the unexpected");
-    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    let mut transmitter = Transmitter::new(Mode::Checksum);
+    assert_eq!(transmitter.receive_byte(NAK).expect("expected"), Event::Ready);
+    let e = Transmitter::new(Mode::Checksum).receive_byte(1).expect_err("expect the unexpected");
+    assert!(matches!(e, Error::UnexpectedByte(1)));
 }
 
 #[test]
 fn test_expect_byte_or_cancel() {
-    let mut buffer = vec![2, 0];
-    let b = Xmodem::new(Cursor::new(buffer.as_mut_slice()))
-        .expect_byte_or_cancel(2, "it's a 2")
-        .expect("got a 2");
-
-    assert_eq!(b, 2);
+    let mut receiver = started_receiver(Mode::Checksum);
+    assert_eq!(receiver.receive_byte(SOH).expect("header"), Event::Pending);
+    assert_eq!(receiver.receive_byte(1).expect("got a 1"), Event::Pending);
+    assert_eq!(receiver.receive_byte(254).expect("got a 254"), Event::Pending);
+    assert!(take_output(&mut receiver).is_empty());
 }
 
 #[test]
 fn test_expect_can() {
     let mut xmodem = Xmodem::new(Cursor::new(vec![CAN]));
-    assert_eq!(xmodem.expect_byte(CAN, "hi").expect("CAN"), CAN);
+    assert_eq!(xmodem.read_byte(false).expect("CAN"), CAN);
+}
+
+#[test]
+fn test_send_can_in_payload() {
+    let mut transmitter = Transmitter::new(Mode::Checksum);
+    feed(&mut transmitter, &[NAK]).expect("ready");
+    transmitter.send_packet(&[CAN; 128]).expect("queued");
+    assert_eq!(transmitter.output()[3], CAN);
 }
 
 #[test]
 fn test_unexpected_can() {
-    let e = Xmodem::new(Cursor::new(vec![CAN]))
-        .expect_byte(SOH, "want SOH")
+    let e = Transmitter::new(Mode::Checksum)
+        .receive_byte(CAN)
         .expect_err("have CAN");
 
-    assert_eq!(e.kind(), io::ErrorKind::ConnectionAborted);
+    assert!(matches!(e, Error::Cancelled));
 }
 
 #[test]
 fn test_cancel_on_unexpected() {
-    let mut buffer = vec![CAN, 0];
-    let e = Xmodem::new(Cursor::new(buffer.as_mut_slice()))
-        .expect_byte_or_cancel(SOH, "want SOH")
-        .expect_err("have CAN");
+    let mut receiver = started_receiver(Mode::Checksum);
+    receiver.receive_byte(SOH).expect("header");
+    let e = receiver.receive_byte(CAN).expect_err("have CAN");
 
-    assert_eq!(e.kind(), io::ErrorKind::ConnectionAborted);
-    assert_eq!(buffer[1], CAN);
+    assert!(matches!(e, Error::Cancelled));
+    assert_eq!(take_output(&mut receiver), vec![CAN, CAN]);
 
-    let mut buffer = vec![0, 0];
-    let e = Xmodem::new(Cursor::new(buffer.as_mut_slice()))
-        .expect_byte_or_cancel(SOH, "want SOH")
-        .expect_err("have 0");
+    let mut receiver = started_receiver(Mode::Checksum);
+    receiver.receive_byte(SOH).expect("header");
+    receiver.receive_byte(0).expect("number");
+    let e = receiver.receive_byte(255).expect_err("have 0");
 
-    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
-    assert_eq!(buffer[1], CAN);
+    assert!(matches!(e, Error::PacketNumberMismatch { expected: 1, got: 0 }));
+    assert_eq!(take_output(&mut receiver), vec![CAN, CAN]);
 }
 
 #[test]
@@ -166,7 +280,7 @@ fn test_raw_transmission() {
     });
 
     let rx_thread = std::thread::spawn(move || {
-        Xmodem::receive(&mut tx, &mut output[..]).expect("receive okay");
+        Xmodem::receive_with_mode(&mut tx, &mut output[..], Mode::Checksum, progress::noop).expect("receive okay");
         tx.2
     });
 
@@ -200,10 +314,10 @@ fn test_small_packet_eof_error() {
 
     let mut buffer = [1, 2, 3];
     let e = xmodem.read_packet(&mut buffer[..]).expect_err("read EOF");
-    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    assert!(matches!(e, Error::Io(ref e) if e.kind() == io::ErrorKind::UnexpectedEof));
 
     let e = xmodem.write_packet(&buffer).expect_err("write EOF");
-    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    assert!(matches!(e, Error::Io(ref e) if e.kind() == io::ErrorKind::UnexpectedEof));
 }
 
 #[test]
@@ -213,16 +327,13 @@ fn test_bad_control() {
         .read_packet(&mut packet[..])
         .expect_err("CAN");
 
-        println!("error {}", e);
-        println!("error {:?}", e.kind());
-
-    assert_eq!(e.kind(), io::ErrorKind::ConnectionAborted);
+    assert!(matches!(e, Error::Cancelled));
 
     let e = Xmodem::new(Cursor::new(vec![0, 0xFF]))
         .read_packet(&mut packet[..])
         .expect_err("bad contorl");
 
-    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    assert!(matches!(e, Error::UnexpectedByte(0xFF)));
 }
 
 #[test]
@@ -233,4 +344,921 @@ fn test_eot() {
         .expect("write empty buf for EOT");
 
     assert_eq!(&buffer[..], &[NAK, EOT, NAK, EOT, ACK]);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_crc_loop() {
+    let mut input = [0u8; 384];
+    for (i, chunk) in input.chunks_mut(128).enumerate() {
+        chunk.iter_mut().for_each(|b| *b = i as u8);
+    }
+
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        Xmodem::transmit_with_mode(&input[..], rx, Mode::Crc, progress::noop)
+    });
+    let rx_thread = std::thread::spawn(move || {
+        let mut output = [0u8; 384];
+        Xmodem::receive_with_mode(tx, &mut output[..], Mode::Crc, progress::noop).map(|_| output)
+    });
+
+    assert_eq!(tx_thread.join().expect("tx join okay").expect("tx okay"), 384);
+    let output = rx_thread.join().expect("rx join okay").expect("rx okay");
+    assert_eq!(&input[..], &output[..]);
+}
+
+#[test]
+fn test_crc_raw_transmission() {
+    let mut input = [0u8; 256];
+    let mut output = [0u8; 256];
+    (0..256usize).into_iter().enumerate().for_each(|(i, b)| input[i] = b as u8);
+
+    let (mut tx, mut rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        Xmodem::transmit_with_mode(&input[..], &mut rx, Mode::Crc, progress::noop)
+            .expect("transmit okay");
+        rx.2
+    });
+
+    let rx_thread = std::thread::spawn(move || {
+        Xmodem::receive_with_mode(&mut tx, &mut output[..], Mode::Crc, progress::noop)
+            .expect("receive okay");
+        tx.2
+    });
+
+    let rx_buf = tx_thread.join().expect("tx join okay");
+    let tx_buf = rx_thread.join().expect("rx join okay");
+
+    assert_eq!(&rx_buf[0..3], &[SOH, 1, 255 - 1]);
+    assert_eq!(&rx_buf[3..131], &input[..128]);
+    assert_eq!(&rx_buf[131..133], &get_crc(&input[..128]).to_be_bytes());
+
+    assert_eq!(&rx_buf[133..136], &[SOH, 2, 255 - 2]);
+    assert_eq!(&rx_buf[136..264], &input[128..]);
+    assert_eq!(&rx_buf[264..266], &get_crc(&input[128..]).to_be_bytes());
+
+    assert_eq!(&rx_buf[266..], &[EOT, EOT]);
+    assert_eq!(&tx_buf, &[CRC, ACK, ACK, NAK, ACK]);
+}
+
+#[test]
+fn test_crc_check_value() {
+    assert_eq!(get_crc(b"123456789"), 0x31C3);
+    assert_eq!(get_crc(&[]), 0);
+}
+
+#[test]
+fn test_crc_sender_checksum_receiver() {
+    let input = [7u8; 128];
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut transmitter = Xmodem::new_with_mode(rx, Mode::Crc, progress::noop);
+        transmitter.write_packet(&input).expect("packet okay");
+        transmitter.write_packet(&[]).expect("EOT okay");
+        transmitter.mode()
+    });
+    let rx_thread = std::thread::spawn(move || {
+        let mut output = [0u8; 128];
+        Xmodem::receive_with_mode(tx, &mut output[..], Mode::Checksum, progress::noop).map(|_| output)
+    });
+
+    assert_eq!(tx_thread.join().expect("tx join okay"), Mode::Checksum);
+    let output = rx_thread.join().expect("rx join okay").expect("rx okay");
+    assert_eq!(&input[..], &output[..]);
+}
+
+#[test]
+fn test_crc_fallback_to_checksum() {
+    let input = [9u8; 128];
+    let (tx, rx) = pipe();
+    let tx = tx.timeout(Duration::from_millis(50));
+    let tx_thread = std::thread::spawn(move || {
+        Xmodem::transmit_with_mode(&input[..], rx, Mode::Checksum, progress::noop)
+    });
+    let rx_thread = std::thread::spawn(move || {
+        let mut output = [0u8; 128];
+        let mut receiver = Xmodem::new_with_mode(tx, Mode::Crc, progress::noop);
+        receiver.read_packet(&mut output).expect("packet okay");
+        receiver.read_packet(&mut []).expect("EOT okay");
+        (receiver.mode(), receiver.inner.2, output)
+    });
+
+    assert_eq!(tx_thread.join().expect("tx join okay").expect("tx okay"), 128);
+    let (mode, sent, output) = rx_thread.join().expect("rx join okay");
+    assert_eq!(mode, Mode::Checksum);
+    assert_eq!(&sent[..], &[CRC, CRC, CRC, NAK, ACK, NAK, ACK]);
+    assert_eq!(&input[..], &output[..]);
+}
+
+#[test]
+fn test_crc_attempts_config() {
+    let mut receiver = Receiver::new_with_config(Mode::Crc, XmodemConfig::new().with_crc_attempts(5));
+    receiver.start();
+    for _ in 0..5 {
+        receiver.timeout(None).expect("handshake resent");
+    }
+
+    assert_eq!(take_output(&mut receiver), [CRC, CRC, CRC, CRC, CRC, NAK]);
+    assert_eq!(receiver.mode(), Mode::Checksum);
+}
+
+#[test]
+fn test_receive_defaults_to_crc() {
+    let input = [5u8; 128];
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || Xmodem::transmit(&input[..], rx));
+    let rx_thread = std::thread::spawn(move || {
+        let (mut tx, mut output) = (tx, vec![]);
+        Xmodem::receive(&mut tx, &mut output).expect("rx okay");
+        (tx.2, output)
+    });
+
+    assert_eq!(tx_thread.join().expect("tx join okay").expect("tx okay"), 128);
+    let (sent, output) = rx_thread.join().expect("rx join okay");
+    assert_eq!(sent[0], CRC);
+    assert_eq!(&output[..], &input[..]);
+}
+
+#[test]
+fn test_1k_loop() {
+    let mut input = [0u8; 3000];
+    (0..3000usize).for_each(|i| input[i] = (i % 251) as u8);
+
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        Xmodem::transmit_with_mode(&input[..], rx, Mode::Crc1k, progress::noop)
+    });
+    let rx_thread = std::thread::spawn(move || {
+        let mut output = vec![];
+        Xmodem::receive_with_mode(tx, &mut output, Mode::Crc, progress::noop).map(|n| (n, output))
+    });
+
+    assert_eq!(tx_thread.join().expect("tx join okay").expect("tx okay"), 3000);
+    let (n, output) = rx_thread.join().expect("rx join okay").expect("rx okay");
+    assert_eq!(n, 3072);
+    assert_eq!(&input[..], &output[..3000]);
+    assert!(output[3000..].iter().all(|&b| b == 0));
+}
+
+#[test]
+fn test_1k_raw_transmission() {
+    let mut input = [0u8; 1200];
+    (0..1200usize).for_each(|i| input[i] = i as u8);
+
+    let (mut tx, mut rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        Xmodem::transmit_with_mode(&input[..], &mut rx, Mode::Crc1k, progress::noop)
+            .expect("transmit okay");
+        rx.2
+    });
+    let rx_thread = std::thread::spawn(move || {
+        Xmodem::receive_with_mode(&mut tx, vec![], Mode::Crc, progress::noop)
+            .expect("receive okay");
+        tx.2
+    });
+
+    let rx_buf = tx_thread.join().expect("tx join okay");
+    let tx_buf = rx_thread.join().expect("rx join okay");
+
+    // one 1024-byte packet, then the 176-byte tail as two 128-byte packets
+    assert_eq!(&rx_buf[0..3], &[STX, 1, 255 - 1]);
+    assert_eq!(&rx_buf[3..1027], &input[..1024]);
+    assert_eq!(&rx_buf[1027..1029], &get_crc(&input[..1024]).to_be_bytes());
+
+    let mut tail = [0u8; 256];
+    tail[..176].copy_from_slice(&input[1024..]);
+    assert_eq!(&rx_buf[1029..1032], &[SOH, 2, 255 - 2]);
+    assert_eq!(&rx_buf[1032..1160], &tail[..128]);
+    assert_eq!(&rx_buf[1160..1162], &get_crc(&tail[..128]).to_be_bytes());
+    assert_eq!(&rx_buf[1162..1165], &[SOH, 3, 255 - 3]);
+    assert_eq!(&rx_buf[1165..1293], &tail[128..]);
+    assert_eq!(&rx_buf[1293..1295], &get_crc(&tail[128..]).to_be_bytes());
+
+    assert_eq!(&rx_buf[1295..], &[EOT, EOT]);
+    assert_eq!(&tx_buf, &[CRC, ACK, ACK, ACK, NAK, ACK]);
+}
+
+#[test]
+fn test_1k_falls_back_to_128_on_nak() {
+    let input = [3u8; 1024];
+    let (tx, mut rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        Xmodem::transmit_with_mode(&input[..], &mut rx, Mode::Crc1k, progress::noop).map(|n| (n, rx))
+    });
+    let rx_thread = std::thread::spawn(move || {
+        let mut output = [0u8; 1024];
+        Xmodem::receive_with_mode(tx, &mut output[..], Mode::Checksum, progress::noop).map(|_| output)
+    });
+
+    let (n, rx) = tx_thread.join().expect("tx join okay").expect("tx okay");
+    assert_eq!(n, 1024);
+    assert_eq!(rx.2.len(), 8 * (3 + 128 + 1) + 2);
+    for (i, packet) in rx.2.chunks(3 + 128 + 1).take(8).enumerate() {
+        assert_eq!(&packet[0..3], &[SOH, i as u8 + 1, 254 - i as u8]);
+    }
+    let output = rx_thread.join().expect("rx join okay").expect("rx okay");
+    assert_eq!(&input[..], &output[..]);
+}
+
+#[test]
+fn test_1k_packet_into_short_buffer() {
+    let mut buffer = vec![0, STX, 0, 0];
+    let mut packet = [0u8; 128];
+    let e = Xmodem::new(Cursor::new(buffer.as_mut_slice()))
+        .read_packet(&mut packet[..])
+        .expect_err("too short");
+
+    assert!(matches!(e, Error::Io(ref e) if e.kind() == io::ErrorKind::UnexpectedEof));
+    assert_eq!(&buffer[..], &[CRC, STX, CAN, CAN]);
+}
+
+#[test]
+fn test_ymodem_batch() {
+    let first: Vec<u8> = (0..200usize).map(|i| i as u8).collect();
+    let second: Vec<u8> = (0..1500usize).map(|i| (i % 7) as u8).collect();
+    let files = [
+        (FileInfo::new("kernel8.img").unwrap().with_size(200).with_mtime(0o14567), first.clone()),
+        (FileInfo::new("initrd").unwrap().with_size(1500), second.clone()),
+    ];
+
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut sender = Ymodem::new_with_mode(rx, Mode::Crc1k, progress::noop);
+        let sent = files.iter()
+            .map(|(info, data)| sender.send_file(info, &data[..]))
+            .collect::<Result<Vec<usize>>>();
+        sender.finish().map(|_| sent)
+    });
+    let rx_thread = std::thread::spawn(move || {
+        let mut receiver = Ymodem::new(tx);
+        let mut received = vec![];
+        loop {
+            let mut data = vec![];
+            match receiver.receive_file(&mut data) {
+                Ok(Some((info, n))) => received.push((info, n, data)),
+                Ok(None) => return Ok(received),
+                Err(e) => return Err(e),
+            }
+        }
+    });
+
+    let sent = tx_thread.join().expect("tx join okay").expect("tx okay").expect("files okay");
+    assert_eq!(sent, vec![200, 1500]);
+
+    let received = rx_thread.join().expect("rx join okay").expect("rx okay");
+    assert_eq!(received.len(), 2);
+
+    let (info, n, data) = &received[0];
+    assert_eq!((info.name(), info.size(), info.mtime()), ("kernel8.img", Some(200), Some(0o14567)));
+    assert_eq!((*n, &data[..]), (200, &first[..]));
+
+    let (info, n, data) = &received[1];
+    assert_eq!((info.name(), info.size(), info.mtime()), ("initrd", Some(1500), None));
+    assert_eq!((*n, &data[..]), (1500, &second[..]));
+}
+
+#[test]
+fn test_ymodem_block_zero() {
+    let mut block = [0xFFu8; 128];
+    FileInfo::new("a.bin").unwrap().with_size(1234).with_mtime(0o777).encode(&mut block);
+    assert_eq!(&block[..15], b"a.bin\x001234 777\x00");
+    assert!(block[15..].iter().all(|&b| b == 0));
+
+    let info = FileInfo::decode(&block).expect("valid").expect("not empty");
+    assert_eq!((info.name(), info.size(), info.mtime()), ("a.bin", Some(1234), Some(0o777)));
+
+    let info = FileInfo::decode(b"foo\0\0").expect("valid").expect("not empty");
+    assert_eq!((info.name(), info.size(), info.mtime()), ("foo", None, None));
+
+    assert!(FileInfo::decode(&[0u8; 128]).expect("valid").is_none());
+
+    let e = FileInfo::decode(b"foo\0nope\0").expect_err("bad size");
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_ymodem_invalid_name() {
+    let e = FileInfo::new(&"x".repeat(MAX_NAME_LEN + 1)).expect_err("too long");
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    assert!(FileInfo::new("").is_err());
+    assert!(FileInfo::new("a\0b").is_err());
+}
+
+#[test]
+fn test_packet_number_mismatch() {
+    let mut buffer = vec![0, SOH, 2, 253, 0, 0];
+    let mut packet = [0u8; 128];
+    let e = Xmodem::new(Cursor::new(buffer.as_mut_slice()))
+        .read_packet(&mut packet[..])
+        .expect_err("wrong number");
+
+    assert!(matches!(e, Error::PacketNumberMismatch { expected: 1, got: 2 }));
+    assert_eq!(&buffer[4..], &[CAN, CAN]);
+
+    // a garbled header isn't a reason to cancel
+    let mut buffer = vec![0, SOH, 1, 0, 0, 0];
+    let e = Xmodem::new(Cursor::new(buffer.as_mut_slice()))
+        .with_config(XmodemConfig::new().with_max_retries(1))
+        .read_packet(&mut packet[..])
+        .expect_err("wrong complement");
+
+    assert!(matches!(e, Error::ComplementMismatch { expected: 254, got: 0 }));
+    assert_eq!(&buffer[4..], &[0, 0]);
+}
+
+#[test]
+fn test_bad_checksum() {
+    let mut buffer = vec![0, SOH, 1, 254];
+    buffer.extend_from_slice(&[1u8; 128]);
+    buffer.extend_from_slice(&[0, 0, 0]);
+
+    let mut packet = [0u8; 128];
+    let e = Xmodem::new(Cursor::new(buffer.as_mut_slice()))
+        .read_packet(&mut packet[..])
+        .expect_err("bad CRC");
+
+    assert!(matches!(e, Error::BadChecksum));
+    assert_eq!(buffer[0], CRC);
+    assert_eq!(buffer[134], NAK);
+}
+
+#[test]
+fn test_timeout() {
+    let (tx, _rx) = pipe();
+    let mut packet = [0u8; 128];
+    let e = Xmodem::new(tx.timeout(Duration::from_millis(10)))
+        .read_packet(&mut packet[..])
+        .expect_err("nobody is sending");
+
+    assert!(matches!(e, Error::Timeout));
+}
+
+#[test]
+fn test_retries_exhausted() {
+    let (mut tx, rx) = pipe();
+    let rx_thread = std::thread::spawn(move || {
+        // a receiver that rejects every packet
+        use std::io::{Read, Write};
+        tx.write_all(&[NAK]).unwrap();
+        for _ in 0..10 {
+            let mut packet = [0u8; 3 + 128 + 1];
+            tx.read_exact(&mut packet).unwrap();
+            tx.write_all(&[NAK]).unwrap();
+        }
+    });
+
+    let e = Xmodem::transmit(&[0u8; 128][..], rx).expect_err("always rejected");
+    assert!(matches!(e, Error::RetriesExhausted));
+    rx_thread.join().expect("rx join okay");
+}
+
+#[test]
+fn test_error_into_io_error() {
+    let e: io::Error = Error::Cancelled.into();
+    assert_eq!(e.kind(), io::ErrorKind::ConnectionAborted);
+
+    let e: io::Error = Error::Timeout.into();
+    assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+
+    let e: io::Error = Error::PacketNumberMismatch { expected: 1, got: 2 }.into();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+    let e: io::Error = Error::Io(io::Error::other("inner")).into();
+    assert_eq!(e.kind(), io::ErrorKind::Other);
+}
+
+#[test]
+fn test_machine_handshake_and_packet() {
+    let mut receiver = Receiver::new(Mode::Crc);
+    receiver.start();
+    assert_eq!(take_output(&mut receiver), vec![CRC]);
+
+    let mut transmitter = Transmitter::new(Mode::Crc);
+    assert_eq!(feed(&mut transmitter, &[CRC]).expect("ready"), Event::Ready);
+    assert!(transmitter.is_ready());
+
+    let data: Vec<u8> = (0..128u8).collect();
+    assert_eq!(transmitter.send_packet(&data).expect("queued"), 128);
+    let packet = transmitter.output().to_vec();
+    transmitter.consume_output(packet.len());
+    assert_eq!(packet.len(), 3 + 128 + 2);
+    assert!(transmitter.output().is_empty());
+
+    let (last, rest) = packet.split_last().unwrap();
+    for &b in rest {
+        assert_eq!(receiver.receive_byte(b).expect("pending"), Event::Pending);
+    }
+    assert_eq!(receiver.receive_byte(*last).expect("packet"), Event::Packet(128));
+    assert_eq!(receiver.packet(), &data[..]);
+
+    let ack = take_output(&mut receiver);
+    assert_eq!(ack, vec![ACK]);
+    assert_eq!(feed(&mut transmitter, &ack).expect("acked"), Event::Packet(128));
+
+    transmitter.finish().expect("eot");
+    assert_eq!(transmitter.output(), &[EOT]);
+    transmitter.consume_output(1);
+    assert_eq!(receiver.receive_byte(EOT).expect("first EOT"), Event::Pending);
+    assert_eq!(feed(&mut transmitter, &take_output(&mut receiver)).expect("nak"), Event::Pending);
+    assert_eq!(transmitter.output(), &[EOT]);
+    assert_eq!(receiver.receive_byte(EOT).expect("second EOT"), Event::Done);
+    assert_eq!(feed(&mut transmitter, &take_output(&mut receiver)).expect("ack"), Event::Done);
+}
+
+/// Returns a `Transmitter` in `Mode::Crc` with packet `data` queued, and the
+/// packet.
+fn queued_packet(data: &[u8]) -> (Transmitter, Vec<u8>) {
+    let mut transmitter = Transmitter::new(Mode::Crc);
+    feed(&mut transmitter, &[CRC]).expect("ready");
+    transmitter.send_packet(data).expect("queued");
+    let packet = transmitter.output().to_vec();
+    transmitter.consume_output(packet.len());
+    (transmitter, packet)
+}
+
+#[test]
+fn test_machine_lost_ack() {
+    let (mut transmitter, packet) = queued_packet(&[1; 128]);
+    let mut receiver = started_receiver(Mode::Crc);
+    for &b in &packet {
+        receiver.receive_byte(b).expect("packet");
+    }
+    assert_eq!(take_output(&mut receiver), vec![ACK]);
+
+    // the `ACK` was lost, so the sender asks again after the receiver's NAK
+    assert_eq!(receiver.timeout(None).expect("ask again"), Event::Rejected);
+    assert_eq!(feed(&mut transmitter, &take_output(&mut receiver)).expect("nak"), Event::Rejected);
+    transmitter.send_packet(&[1; 128]).expect("queued again");
+    for &b in transmitter.output() {
+        assert_eq!(receiver.receive_byte(b).expect("duplicate"), Event::Pending);
+    }
+    assert_eq!(take_output(&mut receiver), vec![ACK]);
+
+    let (_, packet) = queued_packet(&[2; 128]);
+    let packet = [&[SOH, 2, 253][..], &packet[3..]].concat();
+    let (last, rest) = packet.split_last().unwrap();
+    for &b in rest {
+        receiver.receive_byte(b).expect("next packet");
+    }
+    assert_eq!(receiver.receive_byte(*last).expect("next packet"), Event::Packet(128));
+    assert_eq!(receiver.packet(), &[2; 128][..]);
+}
+
+#[test]
+fn test_machine_garbled_response() {
+    let (mut transmitter, _) = queued_packet(&[1; 128]);
+    assert_eq!(feed(&mut transmitter, &[ACK ^ 0x10]).expect("like a NAK"), Event::Rejected);
+    assert!(transmitter.is_ready());
+
+    let mut receiver = started_receiver(Mode::Crc);
+    receiver.receive_byte(SOH).expect("header");
+    receiver.receive_byte(1).expect("number");
+    receiver.receive_byte(0).expect("garbled complement");
+    for b in 0..20 {
+        assert_eq!(receiver.receive_byte(b).expect("ignored"), Event::Pending);
+    }
+    assert!(receiver.output().is_empty());
+    assert_eq!(receiver.timeout(None).expect("quiet"), Event::Rejected);
+    assert_eq!(take_output(&mut receiver), vec![NAK]);
+}
+
+#[test]
+fn test_machine_handshake_timeouts() {
+    let config = XmodemConfig::new().with_max_retries(5);
+    let mut receiver = Receiver::new_with_config(Mode::Crc, config);
+    receiver.start();
+    for _ in 0..5 {
+        receiver.timeout(None).expect("retry handshake");
+    }
+
+    assert_eq!(take_output(&mut receiver), vec![CRC, CRC, CRC, NAK, NAK, NAK]);
+    assert_eq!(receiver.mode(), Mode::Checksum);
+    assert!(matches!(receiver.timeout(None), Err(Error::Timeout)));
+}
+
+#[test]
+fn test_machine_nak_interval() {
+    let config = XmodemConfig::new()
+        .with_nak_interval(Duration::from_secs(1))
+        .with_byte_timeout(Duration::from_millis(300));
+    let mut receiver = Receiver::new_with_config(Mode::Checksum, config);
+    receiver.start();
+    take_output(&mut receiver);
+
+    receiver.timeout(Some(Duration::from_millis(600))).expect("waiting");
+    assert!(receiver.output().is_empty());
+    receiver.timeout(Some(Duration::from_millis(600))).expect("resend NAK");
+    assert_eq!(take_output(&mut receiver), vec![NAK]);
+
+    receiver.receive_byte(SOH).expect("header");
+    receiver.timeout(Some(Duration::from_millis(200))).expect("within byte timeout");
+    receiver.receive_byte(1).expect("number");
+    receiver.timeout(Some(Duration::from_millis(200))).expect("timer restarted");
+    assert_eq!(receiver.timeout(Some(Duration::from_millis(200))).expect("too slow"), Event::Rejected);
+    assert_eq!(take_output(&mut receiver), vec![NAK]);
+}
+
+#[test]
+fn test_machine_start_timeout() {
+    let config = XmodemConfig::new()
+        .with_max_retries(2)
+        .with_byte_timeout(Duration::from_millis(100))
+        .with_start_timeout(Duration::from_secs(1));
+    let mut transmitter = Transmitter::new_with_config(Mode::Crc, config);
+    transmitter.start();
+
+    transmitter.timeout(Some(Duration::from_millis(600))).expect("still waiting");
+    let e = transmitter.timeout(Some(Duration::from_millis(600))).expect_err("receiver never came");
+    assert!(matches!(e, Error::Timeout));
+
+    transmitter.restart(1);
+    transmitter.start();
+    assert_eq!(feed(&mut transmitter, &[CRC]).expect("ready"), Event::Ready);
+    transmitter.send_packet(&[0u8; 128]).expect("queued");
+    transmitter.timeout(Some(Duration::from_millis(100))).expect("receiver may still NAK");
+    let e = transmitter.timeout(Some(Duration::from_millis(100))).expect_err("byte timeout");
+    assert!(matches!(e, Error::Timeout));
+}
+
+#[test]
+fn test_machine_receiver_start_timeout() {
+    let config = XmodemConfig::new()
+        .with_max_retries(2)
+        .with_nak_interval(Duration::from_millis(50))
+        .with_start_timeout(Duration::from_millis(300));
+    let mut receiver = Receiver::new_with_config(Mode::Checksum, config);
+    receiver.start();
+    for _ in 0..5 {
+        receiver.timeout(Some(Duration::from_millis(50))).expect("handshake resent");
+    }
+
+    assert_eq!(take_output(&mut receiver), [NAK; 6]);
+    let e = receiver.timeout(Some(Duration::from_millis(50))).expect_err("sender never came");
+    assert!(matches!(e, Error::Timeout));
+}
+
+#[test]
+fn test_transmit_start_timeout() {
+    let (tx, rx) = pipe();
+    let config = XmodemConfig::new()
+        .with_byte_timeout(Duration::from_millis(20))
+        .with_start_timeout(Duration::from_millis(200))
+        .with_clock(test_clock);
+
+    let start = std::time::Instant::now();
+    let e = Xmodem::new(rx.timeout(Duration::from_millis(20)))
+        .with_config(config)
+        .transmit_all(&[0u8; 128][..])
+        .expect_err("nobody is receiving");
+    drop(tx);
+
+    assert!(matches!(e, Error::Timeout));
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
+
+/// Time since the first call, for `XmodemConfig::with_clock`.
+fn test_clock() -> Duration {
+    static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+    START.get_or_init(std::time::Instant::now).elapsed()
+}
+
+#[test]
+fn test_transmit_waits_for_late_receiver() {
+    let input = [4u8; 256];
+    let (tx, rx) = pipe();
+    let config = XmodemConfig::new()
+        .with_byte_timeout(Duration::from_millis(20))
+        .with_start_timeout(Duration::from_secs(5))
+        .with_clock(test_clock);
+    let tx_thread = std::thread::spawn(move || {
+        let rx = rx.timeout(Duration::from_millis(20));
+        Xmodem::new_with_mode(rx, Mode::Crc, progress::noop).with_config(config).transmit_all(&input[..])
+    });
+    let rx_thread = std::thread::spawn(move || {
+        // start after ten byte timeouts
+        std::thread::sleep(Duration::from_millis(200));
+        let mut output = [0u8; 256];
+        Xmodem::receive(tx, &mut output[..]).map(|_| output)
+    });
+
+    assert_eq!(tx_thread.join().expect("tx join okay").expect("tx okay"), 256);
+    let output = rx_thread.join().expect("rx join okay").expect("rx okay");
+    assert_eq!(&input[..], &output[..]);
+}
+
+#[test]
+fn test_cancel() {
+    let (tx, mut rx) = pipe();
+    let mut xmodem = Xmodem::new(tx);
+    xmodem.cancel().expect("cancelled");
+    assert_eq!(xmodem.inner.2, vec![CAN, CAN]);
+
+    let (tx, _rx2) = pipe();
+    let config = XmodemConfig::new().with_cancel_count(5);
+    let mut xmodem = Xmodem::new(tx).with_config(config);
+    xmodem.cancel().expect("cancelled");
+    assert_eq!(xmodem.inner.2, vec![CAN; 5]);
+
+    use std::io::Read;
+    let mut buf = [0u8; 2];
+    rx.read_exact(&mut buf).expect("read CANs");
+    assert_eq!(buf, [CAN, CAN]);
+}
+
+#[test]
+fn test_machine_rejects_until_retries_exhausted() {
+    let mut transmitter = Transmitter::new(Mode::Checksum);
+    feed(&mut transmitter, &[NAK]).expect("ready");
+    for _ in 0..9 {
+        transmitter.send_packet(&[0u8; 128]).expect("queued");
+        transmitter.consume_output(3 + 128 + 1);
+        assert_eq!(transmitter.receive_byte(NAK).expect("rejected"), Event::Rejected);
+    }
+
+    transmitter.send_packet(&[0u8; 128]).expect("queued");
+    let e = transmitter.receive_byte(NAK).expect_err("given up");
+    assert!(matches!(e, Error::RetriesExhausted));
+}
+
+#[test]
+fn test_progress_events() {
+    let input = [7u8; 200];
+
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut events = vec![];
+        Xmodem::transmit_with_progress(&input[..], rx, |p| events.push(p)).map(|_| events)
+    });
+    let rx_thread = std::thread::spawn(move || {
+        let mut events = vec![];
+        Xmodem::receive_with_progress(tx, vec![], |p| events.push(p)).map(|_| events)
+    });
+
+    let tx_events = tx_thread.join().expect("tx join okay").expect("tx okay");
+    assert_eq!(tx_events, vec![
+        Progress::Waiting,
+        Progress::Started,
+        Progress::Packet { block: 1, bytes: 128, total: None },
+        Progress::Packet { block: 2, bytes: 256, total: None },
+    ]);
+
+    let rx_events = rx_thread.join().expect("rx join okay").expect("rx okay");
+    assert_eq!(rx_events, vec![
+        Progress::Started,
+        Progress::Packet { block: 1, bytes: 128, total: None },
+        Progress::Packet { block: 2, bytes: 256, total: None },
+    ]);
+}
+
+#[test]
+fn test_ymodem_progress_total() {
+    let data = [1u8; 200];
+    let info = FileInfo::new("a.bin").unwrap().with_size(200);
+
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut sender = Ymodem::new(rx);
+        sender.send_file(&info, &data[..])?;
+        sender.finish()
+    });
+
+    let mut events = vec![];
+    let mut receiver = Ymodem::new_with_mode(tx, Mode::Crc, |p| events.push(p));
+    receiver.receive_file(vec![]).expect("rx okay").expect("a file");
+    receiver.receive_file(vec![]).expect("rx okay");
+    drop(receiver);
+    tx_thread.join().expect("tx join okay").expect("tx okay");
+
+    let packets: Vec<_> = events.into_iter().filter(|p| matches!(p, Progress::Packet { .. })).collect();
+    assert_eq!(packets, vec![
+        Progress::Packet { block: 0, bytes: 128, total: None },
+        Progress::Packet { block: 1, bytes: 128, total: Some(200) },
+        Progress::Packet { block: 2, bytes: 200, total: Some(200) },
+        Progress::Packet { block: 0, bytes: 128, total: None },
+    ]);
+}
+
+#[test]
+fn test_progress_retransmit_and_nak() {
+    let mut buffer = vec![0, SOH, 1, 254];
+    buffer.extend_from_slice(&[1u8; 128]);
+    buffer.extend_from_slice(&[0, 0, 0]);
+
+    let mut events = vec![];
+    let mut packet = [0u8; 128];
+    Xmodem::new_with_progress(Cursor::new(buffer.as_mut_slice()), |p| events.push(p))
+        .read_packet(&mut packet[..])
+        .expect_err("bad checksum");
+    assert_eq!(events, vec![Progress::Started, Progress::Nak { block: 1, attempt: 1 }]);
+
+    let (mut tx, rx) = pipe();
+    let rx_thread = std::thread::spawn(move || {
+        // a receiver that rejects the first packet twice
+        use std::io::{Read, Write};
+        tx.write_all(&[NAK]).unwrap();
+        for response in [NAK, NAK, ACK, NAK, ACK] {
+            let mut byte = [0u8; 1];
+            tx.read_exact(&mut byte).unwrap();
+            if byte[0] == SOH {
+                let mut rest = [0u8; 2 + 128 + 1];
+                tx.read_exact(&mut rest).unwrap();
+            }
+            tx.write_all(&[response]).unwrap();
+        }
+    });
+
+    let mut events = vec![];
+    Xmodem::transmit_with_progress(&[0u8; 128][..], rx, |p| events.push(p)).expect("tx okay");
+    rx_thread.join().expect("rx join okay");
+    assert_eq!(&events[2..], &[
+        Progress::Retransmit { block: 1, attempt: 1 },
+        Progress::Retransmit { block: 1, attempt: 2 },
+        Progress::Packet { block: 1, bytes: 128, total: None },
+    ]);
+}
+
+#[test]
+fn test_zmodem_crc32() {
+    assert_eq!(zmodem::get_crc32(b"123456789"), 0xCBF43926);
+}
+
+#[test]
+fn test_zmodem_hex_header() {
+    let (mut tx, _rx) = pipe();
+    tx = tx.timeout(Duration::from_millis(10));
+    let info = FileInfo::new("x").unwrap();
+    let err = Zmodem::new(&mut tx)
+        .with_config(XmodemConfig::new().with_max_retries(0))
+        .send_file(&info, &[])
+        .expect_err("no receiver");
+    assert!(matches!(err, Error::RetriesExhausted));
+    assert_eq!(&tx.2[..], b"**\x18B00000000000000\r\x8a\x11");
+}
+
+#[test]
+fn test_zmodem_loop() {
+    let data: Vec<u8> = (0..20_000).map(|i| (i % 256) as u8).collect();
+    let info = FileInfo::new("all-bytes.bin").unwrap().with_size(data.len() as u64);
+
+    let (tx, rx) = pipe();
+    let expected = data.clone();
+    let tx_thread = std::thread::spawn(move || {
+        let mut sender = Zmodem::new(rx);
+        assert_eq!(sender.send_file(&info, &data).expect("tx okay"), data.len());
+        sender.finish().expect("finish okay");
+    });
+
+    let mut receiver = Zmodem::new(tx);
+    let mut received = vec![];
+    let (info, n) = receiver.receive_file(&mut received).expect("rx okay").expect("a file");
+    assert_eq!(info.name(), "all-bytes.bin");
+    assert_eq!(info.size(), Some(expected.len() as u64));
+    assert_eq!(n, expected.len());
+    assert_eq!(received, expected);
+    assert!(receiver.receive_file(vec![]).expect("rx okay").is_none());
+    tx_thread.join().expect("tx join okay");
+}
+
+#[test]
+fn test_zmodem_corruption_recovery() {
+    let data: Vec<u8> = (0..10_000).map(|i| (i * 7 % 251) as u8).collect();
+    let info = FileInfo::new("noisy").unwrap();
+
+    // relay the sender's bytes to the receiver, corrupting one of the data
+    // subpackets on the way
+    let (to_relay, from_sender) = channel();
+    let (to_receiver, from_relay) = channel();
+    let (to_sender, from_receiver) = channel();
+    let timeout = Some(Duration::from_millis(100));
+    let tx = Pipe(to_relay, from_receiver, vec![], timeout);
+    let rx = Pipe(to_sender, from_relay, vec![], timeout);
+    std::thread::spawn(move || {
+        for (i, byte) in from_sender.iter().enumerate() {
+            let byte = if i == 3000 { byte ^ 0x01 } else { byte };
+            if to_receiver.send(byte).is_err() {
+                break;
+            }
+        }
+    });
+
+    let expected = data.clone();
+    let tx_thread = std::thread::spawn(move || {
+        let mut events = vec![];
+        let mut sender = Zmodem::new_with_progress(tx, |p| events.push(p));
+        sender.send_file(&info, &data).expect("tx okay");
+        sender.finish().expect("finish okay");
+        drop(sender);
+        events
+    });
+
+    let mut events = vec![];
+    let mut receiver = Zmodem::new_with_progress(rx, |p| events.push(p));
+    let mut received = vec![];
+    let (_, n) = receiver.receive_file(&mut received).expect("rx okay").expect("a file");
+    assert!(receiver.receive_file(vec![]).expect("rx okay").is_none());
+    let sent = tx_thread.join().expect("tx join okay");
+    drop(receiver);
+
+    assert_eq!(n, expected.len());
+    assert_eq!(received, expected);
+    assert!(events.iter().any(|p| matches!(p, Progress::Nak { .. })));
+    assert!(sent.iter().any(|p| matches!(p, Progress::Retransmit { .. })));
+}
+
+#[test]
+fn test_zmodem_resume() {
+    let data: Vec<u8> = (0..5000).map(|i| (i % 199) as u8).collect();
+    let info = FileInfo::new("partial").unwrap().with_size(data.len() as u64);
+
+    let (tx, rx) = pipe();
+    let expected = data[3000..].to_vec();
+    let tx_thread = std::thread::spawn(move || {
+        let mut sender = Zmodem::new(rx);
+        sender.send_file(&info, &data).expect("tx okay");
+        sender.finish().expect("finish okay");
+    });
+
+    let mut receiver = Zmodem::new(tx);
+    let mut received = vec![];
+    let (_, n) = receiver.resume_file(&mut received, 3000).expect("rx okay").expect("a file");
+    assert_eq!(n, 2000);
+    assert_eq!(received, expected);
+    assert!(receiver.receive_file(vec![]).expect("rx okay").is_none());
+    tx_thread.join().expect("tx join okay");
+}
+
+#[test]
+fn test_receive_into_too_large() {
+    let (mut tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || Xmodem::transmit(&[7u8; 300][..], rx));
+
+    let mut buf = [0u8; 256];
+    let e = Xmodem::new(&mut tx).receive_into(&mut buf).expect_err("too large");
+    assert!(matches!(e, Error::TooLarge { size: 384, capacity: 256 }), "{:?}", e);
+    assert_eq!(&buf[..], &[7u8; 256][..]);
+    // The third packet is refused with `CAN` rather than acknowledged. The
+    // sender hangs up after the first `CAN`, so the rest may be cut short.
+    assert_eq!(&tx.2[..4], &[CRC, ACK, ACK, CAN]);
+
+    let e = tx_thread.join().expect("tx join okay").expect_err("tx cancelled");
+    assert!(matches!(e, Error::Cancelled), "{:?}", e);
+}
+
+#[test]
+fn test_receive_into_exact_fit() {
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || Xmodem::transmit(&[7u8; 256][..], rx));
+
+    let mut buf = [0u8; 256];
+    assert_eq!(Xmodem::new(tx).receive_into(&mut buf).expect("rx okay"), 256);
+    assert_eq!(&buf[..], &[7u8; 256][..]);
+    assert_eq!(tx_thread.join().expect("tx join okay").expect("tx okay"), 256);
+}
+
+#[test]
+fn test_lossy_channel() {
+    let faults = Faults { drop: 1, flip: 1, duplicate: 1, delay: 2 };
+    let mut succeeded = 0;
+    for seed in 0..100 {
+        let mut rng = Rng::new(seed);
+        let data: Vec<u8> = (0..rng.below(2048)).map(|_| rng.next() as u8).collect();
+        // reads time out after longer than the relays delay a byte, and
+        // 128-byte packets mostly get through the faults unscathed
+        let (tx, rx) = lossy_pipe(seed, faults, Duration::from_millis(20));
+
+        let expected = data.clone();
+        let tx_thread = std::thread::spawn(move || {
+            Xmodem::new_with_mode(rx, Mode::Crc, progress::noop).transmit_all(&data[..])
+        });
+
+        let (done, result) = channel();
+        std::thread::spawn(move || {
+            let mut received = vec![];
+            let mut receiver = Xmodem::new_with_mode(tx, Mode::Crc, progress::noop);
+            let n = receiver.receive_all(&mut received);
+            let _ = done.send(n.map(|n| (n, received)));
+        });
+
+        let result = result.recv_timeout(Duration::from_secs(10)).expect("receiver hung");
+        let sent = tx_thread.join().expect("transmitter panicked");
+        match result {
+            Ok((n, received)) => {
+                assert_eq!(n, received.len(), "seed {}", seed);
+                assert_eq!(&received[..expected.len()], &expected[..], "seed {}", seed);
+                assert!(received[expected.len()..].iter().all(|&b| b == 0), "seed {}", seed);
+                assert!(received.len() - expected.len() < 128, "seed {}", seed);
+                succeeded += 1;
+            }
+            // the side that gives up first hangs up on the other, which then
+            // fails reading the closed pipe
+            Err(e) => {
+                let protocol = |e: &Error| !matches!(e, Error::Io(_));
+                assert!(protocol(&e) || sent.as_ref().is_err_and(protocol), "seed {}: {:?}, {:?}", seed, e, sent);
+            }
+        }
+    }
+
+    // about 97 of these seeds get through; the rest hit a fault XMODEM can't
+    // recover from, such as a duplicated `ACK` or a byte garbled into `CAN`
+    assert!(succeeded >= 90, "only {} of 100 transfers succeeded", succeeded);
+}
@@ -0,0 +1,276 @@
+use core::str;
+
+use shim::io;
+use shim::ioerr;
+
+use crate::{progress, Error, Mode, Progress, ProgressFn, Result, Xmodem, XmodemConfig, EOT};
+
+/// Maximum length, in bytes, of a file name sent or received in block 0.
+pub const MAX_NAME_LEN: usize = 64;
+
+/// Metadata about a single file in a YMODEM batch, carried in block 0.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FileInfo {
+    name: [u8; MAX_NAME_LEN],
+    name_len: usize,
+    size: Option<u64>,
+    mtime: Option<u64>,
+}
+
+impl FileInfo {
+    /// Returns a new `FileInfo` for a file named `name` with unknown size and
+    /// modification time.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if `name` is empty, longer than
+    /// `MAX_NAME_LEN` bytes or contains a NUL byte.
+    pub fn new(name: &str) -> io::Result<FileInfo> {
+        let bytes = name.as_bytes();
+        if bytes.is_empty() || bytes.len() > MAX_NAME_LEN || bytes.contains(&0) {
+            return ioerr!(InvalidInput, "invalid file name");
+        }
+
+        let mut info = FileInfo { name: [0; MAX_NAME_LEN], name_len: bytes.len(), size: None, mtime: None };
+        info.name[..bytes.len()].copy_from_slice(bytes);
+        Ok(info)
+    }
+
+    /// Sets the exact size of the file in bytes.
+    pub fn with_size(mut self, size: u64) -> FileInfo {
+        self.size = Some(size);
+        self
+    }
+
+    /// Sets the modification time of the file in seconds since the Unix epoch.
+    pub fn with_mtime(mut self, mtime: u64) -> FileInfo {
+        self.mtime = Some(mtime);
+        self
+    }
+
+    /// The file's name.
+    pub fn name(&self) -> &str {
+        // `new` and `decode` only accept valid UTF-8.
+        str::from_utf8(&self.name[..self.name_len]).unwrap_or("")
+    }
+
+    /// The file's exact size in bytes, if known.
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    /// The file's modification time in seconds since the Unix epoch, if known.
+    pub fn mtime(&self) -> Option<u64> {
+        self.mtime
+    }
+
+    /// Encodes this `FileInfo` into the 128-byte block 0 `buf`: the name and a
+    /// NUL, followed by the decimal size and the octal modification time
+    /// separated by a space. The modification time is only sent along with the
+    /// size.
+    pub(crate) fn encode(&self, buf: &mut [u8; 128]) {
+        buf.iter_mut().for_each(|b| *b = 0);
+        buf[..self.name_len].copy_from_slice(&self.name[..self.name_len]);
+
+        // The name is at most 64 bytes and each number at most 22 digits, so
+        // everything fits in 128 bytes.
+        let mut i = self.name_len + 1;
+        if let Some(size) = self.size {
+            i += write_num(&mut buf[i..], size, 10);
+            if let Some(mtime) = self.mtime {
+                buf[i] = b' ';
+                write_num(&mut buf[i + 1..], mtime, 8);
+            }
+        }
+    }
+
+    /// Decodes block 0 `buf`. Returns `None` if `buf` is the empty block 0 that
+    /// ends a batch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidData` if the name is not NUL-terminated
+    /// UTF-8 of at most `MAX_NAME_LEN` bytes or if the size or modification time
+    /// are not numbers.
+    pub(crate) fn decode(buf: &[u8]) -> io::Result<Option<FileInfo>> {
+        if buf.first().is_none_or(|&b| b == 0) {
+            return Ok(None);
+        }
+
+        let nul = match buf.iter().position(|&b| b == 0) {
+            Some(nul) => nul,
+            None => return ioerr!(InvalidData, "unterminated file name"),
+        };
+
+        let name = match str::from_utf8(&buf[..nul]) {
+            Ok(name) => name,
+            Err(_) => return ioerr!(InvalidData, "invalid file name"),
+        };
+
+        let mut info = match FileInfo::new(name) {
+            Ok(info) => info,
+            Err(_) => return ioerr!(InvalidData, "invalid file name"),
+        };
+
+        let rest = &buf[nul + 1..];
+        let rest = &rest[..rest.iter().position(|&b| b == 0).unwrap_or(rest.len())];
+        let mut fields = rest.split(|&b| b == b' ').filter(|f| !f.is_empty());
+        if let Some(size) = fields.next() {
+            info.size = Some(parse_num(size, 10)?);
+        }
+        if let Some(mtime) = fields.next() {
+            info.mtime = Some(parse_num(mtime, 8)?);
+        }
+
+        Ok(Some(info))
+    }
+}
+
+/// Writes the digits of `n` in `radix` to the start of `buf`, returning the
+/// number of digits written.
+fn write_num(buf: &mut [u8], mut n: u64, radix: u64) -> usize {
+    let mut digits = [0u8; 22];
+    let mut len = 0;
+    loop {
+        digits[len] = b'0' + (n % radix) as u8;
+        len += 1;
+        n /= radix;
+        if n == 0 {
+            break;
+        }
+    }
+
+    for (i, digit) in digits[..len].iter().rev().enumerate() {
+        buf[i] = *digit;
+    }
+
+    len
+}
+
+/// Parses `field` as an unsigned number in `radix`.
+fn parse_num(field: &[u8], radix: u32) -> io::Result<u64> {
+    match str::from_utf8(field).ok().and_then(|s| u64::from_str_radix(s, radix).ok()) {
+        Some(n) => Ok(n),
+        None => ioerr!(InvalidData, "invalid number in block 0"),
+    }
+}
+
+/// Implementation of the YMODEM batch protocol on top of [`Xmodem`].
+///
+/// Each file is preceded by block 0 carrying its [`FileInfo`], and the batch
+/// is ended by an empty block 0. Receivers use the file size to discard the
+/// padding in the final packet.
+pub struct Ymodem<T, F = ProgressFn> {
+    inner: Xmodem<T, F>,
+}
+
+impl<T: io::Read + io::Write> Ymodem<T> {
+    /// Returns a new `Ymodem` instance using CRC-16 with 128-byte packets.
+    pub fn new(inner: T) -> Self {
+        Ymodem::new_with_mode(inner, Mode::Crc, progress::noop)
+    }
+}
+
+impl<T: io::Read + io::Write, F: FnMut(Progress)> Ymodem<T, F> {
+    /// Returns a new `Ymodem` instance using `mode`. Senders in `Mode::Crc1k`
+    /// send file data in 1024-byte packets. YMODEM receivers request CRC-16, so
+    /// a sender in `Mode::Checksum` can only talk to a receiver that has fallen
+    /// back to the checksum. The function `f` is used as a callback to indicate
+    /// progress throughout the transfer; the total reported with
+    /// `Progress::Packet` is the size of the current file.
+    pub fn new_with_mode(inner: T, mode: Mode, f: F) -> Self {
+        Ymodem { inner: Xmodem::new_with_mode(inner, mode, f) }
+    }
+
+    /// Sets the retry, timeout and cancel policy for transfers with this
+    /// instance. See [`XmodemConfig`] for the defaults.
+    pub fn with_config(self, config: XmodemConfig) -> Self {
+        Ymodem { inner: self.inner.with_config(config) }
+    }
+
+    /// Aborts the batch by writing the configured number of `CAN` bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the inner stream fails.
+    pub fn cancel(&mut self) -> Result<()> {
+        self.inner.cancel()
+    }
+
+    /// Sends (uploads) a single file: block 0 describing `info`, followed by
+    /// all of `data`. Call `finish` once every file has been sent.
+    ///
+    /// Returns the number of bytes read from `data`, excluding padding zeroes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading from `data` or the XMODEM transfer fails.
+    pub fn send_file<R: io::Read>(&mut self, info: &FileInfo, data: R) -> Result<usize> {
+        let mut block = [0u8; 128];
+        info.encode(&mut block);
+
+        self.inner.transmitter().restart(0);
+        self.inner.reset_progress(0, None);
+        self.inner.write_packet_with_retries(&block)?;
+
+        // The receiver requests the file data with a fresh `C`.
+        self.inner.transmitter().restart(1);
+        self.inner.reset_progress(1, info.size());
+        self.inner.write_all_packets(data)
+    }
+
+    /// Ends the batch by sending an empty block 0.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the XMODEM transfer fails.
+    pub fn finish(&mut self) -> Result<()> {
+        self.inner.transmitter().restart(0);
+        self.inner.reset_progress(0, None);
+        self.inner.write_packet_with_retries(&[0u8; 128])?;
+        self.inner.flush()
+    }
+
+    /// Receives (downloads) the next file in the batch and writes its contents
+    /// into `into`. If the sender reported the file's size, the padding in the
+    /// final packet is discarded.
+    ///
+    /// Returns the file's metadata and the number of bytes written to `into`,
+    /// or `None` once the sender has ended the batch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to `into` or the XMODEM transfer fails. An
+    /// `Error::Io` of kind `InvalidData` is returned, and the transfer
+    /// cancelled, if block 0 is malformed. `Error::UnexpectedByte` is returned if the
+    /// sender ends a transmission in place of block 0.
+    pub fn receive_file<W: io::Write>(&mut self, into: W) -> Result<Option<(FileInfo, usize)>> {
+        let mut block = [0u8; 1024];
+
+        self.inner.receiver().restart(0);
+        self.inner.reset_progress(0, None);
+        let n = self.inner.read_packet_with_retries(&mut block)?;
+        if n == 0 {
+            return Err(Error::UnexpectedByte(EOT));
+        }
+
+        let info = match FileInfo::decode(&block[..n]) {
+            Ok(Some(info)) => info,
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                self.inner.cancel()?;
+                return Err(Error::Io(e));
+            }
+        };
+
+        self.inner.receiver().restart(1);
+        self.inner.reset_progress(1, info.size);
+        let received = self.inner.read_all_packets(into, info.size, None)?;
+        let written = match info.size {
+            Some(size) => (received as u64).min(size) as usize,
+            None => received,
+        };
+
+        Ok(Some((info, written)))
+    }
+}
@@ -0,0 +1,750 @@
+use shim::io;
+
+use crate::{get_crc, progress, update_crc, Error, FileInfo, Progress, ProgressFn, Result, XmodemConfig, CAN};
+
+const ZPAD: u8 = b'*';
+const ZDLE: u8 = CAN;
+const ZBIN: u8 = b'A';
+const ZHEX: u8 = b'B';
+const ZBIN32: u8 = b'C';
+
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+const DLE: u8 = 0x10;
+
+const ZRQINIT: u8 = 0;
+const ZRINIT: u8 = 1;
+const ZACK: u8 = 3;
+const ZFILE: u8 = 4;
+const ZSKIP: u8 = 5;
+const ZFIN: u8 = 8;
+const ZRPOS: u8 = 9;
+const ZDATA: u8 = 10;
+const ZEOF: u8 = 11;
+
+/// Subpacket ends: frame ends and a header follows.
+const ZCRCE: u8 = b'h';
+/// Subpacket ends: frame continues nonstop.
+const ZCRCG: u8 = b'i';
+/// Subpacket ends: frame continues, `ZACK` expected.
+const ZCRCQ: u8 = b'j';
+/// Subpacket ends: frame ends, `ZACK` expected.
+const ZCRCW: u8 = b'k';
+const ZRUB0: u8 = b'l';
+const ZRUB1: u8 = b'm';
+
+/// `ZRINIT` capability flags, sent in `ZF0`.
+const CANFDX: u8 = 0x01;
+const CANOVIO: u8 = 0x02;
+const CANFC32: u8 = 0x20;
+
+/// Maximum number of data bytes in a subpacket.
+const SUBPACKET_SIZE: usize = 1024;
+
+/// Number of bytes a sender streams before waiting for a `ZACK`.
+const WINDOW_SIZE: usize = 8 * SUBPACKET_SIZE;
+
+/// Number of bytes skipped while looking for a header before giving up. Large
+/// enough to skip the rest of a window after an error.
+const GARBAGE_LIMIT: usize = 4 * WINDOW_SIZE;
+
+/// The standard abort sequence: eight `CAN`s followed by backspaces to erase
+/// them from a terminal.
+const ABORT: [u8; 18] = [CAN, CAN, CAN, CAN, CAN, CAN, CAN, CAN, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8];
+
+/// A frame header: the frame type and four bytes of flags or file position.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Header {
+    frame: u8,
+    data: [u8; 4],
+}
+
+impl Header {
+    fn new(frame: u8, data: [u8; 4]) -> Header {
+        Header { frame, data }
+    }
+
+    fn with_position(frame: u8, position: u32) -> Header {
+        Header::new(frame, position.to_le_bytes())
+    }
+
+    fn position(&self) -> u32 {
+        u32::from_le_bytes(self.data)
+    }
+
+    /// The `ZF0` flags byte.
+    fn flags(&self) -> u8 {
+        self.data[3]
+    }
+
+    fn bytes(&self) -> [u8; 5] {
+        [self.frame, self.data[0], self.data[1], self.data[2], self.data[3]]
+    }
+}
+
+/// A byte read from ZDLE-escaped data.
+enum Escaped {
+    Byte(u8),
+    /// The end of a subpacket, with the kind of end.
+    End(u8),
+}
+
+/// Computes the CRC-32 (IEEE 802.3) of `buf`.
+pub(crate) fn get_crc32(buf: &[u8]) -> u32 {
+    !update_crc32(!0, buf)
+}
+
+/// Continues the CRC-32 `crc`, before final inversion, over `buf`.
+fn update_crc32(crc: u32, buf: &[u8]) -> u32 {
+    buf.iter().fold(crc, |crc, &b| {
+        let mut crc = crc ^ b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+        crc
+    })
+}
+
+/// Returns `true` if `byte` must be ZDLE-escaped.
+fn needs_escape(byte: u8) -> bool {
+    matches!(byte & 0x7F, ZDLE | DLE | XON | XOFF)
+}
+
+/// Appends `bytes` to `out` at `len`, ZDLE-escaping them, and returns the new
+/// length. `out` must have room for twice the length of `bytes`.
+fn escape_into(out: &mut [u8], mut len: usize, bytes: &[u8]) -> usize {
+    for &b in bytes {
+        if needs_escape(b) {
+            out[len] = ZDLE;
+            out[len + 1] = b ^ 0x40;
+            len += 2;
+        } else {
+            out[len] = b;
+            len += 1;
+        }
+    }
+
+    len
+}
+
+/// Implementation of the ZMODEM protocol.
+///
+/// Files are described by a [`FileInfo`] as in YMODEM. The sender streams file
+/// data in windows of 8 KiB subpackets protected by CRC-32 (or CRC-16 if the
+/// receiver can't do CRC-32) and waits for a `ZACK` after each window. On an
+/// error, the receiver asks for the data again from the last good position
+/// with `ZRPOS`, which a receiver can also use to resume an interrupted
+/// transfer.
+///
+/// Timeouts are those of the inner stream; of the [`XmodemConfig`], only the
+/// maximum number of retries applies.
+pub struct Zmodem<T, F = ProgressFn> {
+    inner: T,
+    progress: F,
+    config: XmodemConfig,
+    started: bool,
+    crc32: bool,
+}
+
+impl<T: io::Read + io::Write> Zmodem<T> {
+    /// Returns a new `Zmodem` instance with the internal reader/writer set to
+    /// `inner`. The returned instance can be used for both receiving and
+    /// sending.
+    pub fn new(inner: T) -> Self {
+        Zmodem::new_with_progress(inner, progress::noop)
+    }
+}
+
+impl<T: io::Read + io::Write, F: FnMut(Progress)> Zmodem<T, F> {
+    /// Returns a new `Zmodem` instance with the internal reader/writer set to
+    /// `inner`. The function `f` is used as a callback to indicate progress
+    /// throughout the transfer; blocks are subpackets for a receiver and
+    /// windows for a sender.
+    pub fn new_with_progress(inner: T, f: F) -> Self {
+        Zmodem { inner, progress: f, config: XmodemConfig::new(), started: false, crc32: true }
+    }
+
+    /// Sets the number of retries for transfers with this instance. See
+    /// [`XmodemConfig`].
+    pub fn with_config(mut self, config: XmodemConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Aborts the transfer in progress with the standard sequence of eight
+    /// `CAN` bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the inner stream fails.
+    pub fn cancel(&mut self) -> Result<()> {
+        self.inner.write_all(&ABORT)?;
+        self.started = false;
+        self.flush()
+    }
+
+    /// Sends (uploads) a single file: `ZFILE` describing `info`, followed by
+    /// `data` starting at the position the receiver asks for. Call `finish`
+    /// once every file has been sent.
+    ///
+    /// Returns the length of `data`, or 0 if the receiver skipped the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the ZMODEM transfer fails. `Error::RetriesExhausted`
+    /// is returned if the receiver doesn't acknowledge the file or its data
+    /// within the allowed number of retries. An `Error::Io` of kind
+    /// `InvalidInput` is returned if `data` is 4 GiB or larger.
+    pub fn send_file(&mut self, info: &FileInfo, data: &[u8]) -> Result<usize> {
+        if u32::try_from(data.len()).is_err() {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput, "file too large")));
+        }
+
+        self.start_send()?;
+
+        let mut block = [0u8; 128];
+        info.encode(&mut block);
+
+        let mut errors = 0;
+        let start = loop {
+            self.write_bin_header(Header::new(ZFILE, [0; 4]))?;
+            self.write_subpacket(&block, ZCRCW)?;
+            self.flush()?;
+            match self.read_header() {
+                Ok(h) if h.frame == ZRPOS => break h.position() as usize,
+                Ok(h) if h.frame == ZSKIP => return Ok(0),
+                Ok(_) | Err(Error::Timeout) | Err(Error::BadChecksum) => self.retry(&mut errors)?,
+                Err(e) => return Err(e),
+            }
+        };
+
+        self.send_data(data, start.min(data.len()))?;
+        Ok(data.len())
+    }
+
+    /// Ends the session with `ZFIN`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the ZMODEM transfer fails.
+    pub fn finish(&mut self) -> Result<()> {
+        let mut errors = 0;
+        loop {
+            self.write_hex_header(Header::new(ZFIN, [0; 4]))?;
+            self.flush()?;
+            match self.read_header() {
+                Ok(h) if h.frame == ZFIN => break,
+                Ok(_) | Err(Error::Timeout) | Err(Error::BadChecksum) => self.retry(&mut errors)?,
+                Err(e) => return Err(e),
+            }
+        }
+
+        // "Over and out".
+        self.inner.write_all(b"OO")?;
+        self.started = false;
+        self.flush()
+    }
+
+    /// Receives (downloads) the next file in the session and writes its
+    /// contents into `into`.
+    ///
+    /// Returns the file's metadata and the number of bytes written to `into`,
+    /// or `None` once the sender has ended the session.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to `into` or the ZMODEM transfer fails. An
+    /// `Error::Io` of kind `InvalidData` is returned, and the transfer
+    /// cancelled, if the file's metadata is malformed.
+    pub fn receive_file<W: io::Write>(&mut self, into: W) -> Result<Option<(FileInfo, usize)>> {
+        self.resume_file(into, 0)
+    }
+
+    /// Like [`receive_file()`](Zmodem::receive_file), but asks the sender to
+    /// start at `offset`, for a file whose first `offset` bytes were received
+    /// before. Only the remainder is written to `into`.
+    ///
+    /// # Errors
+    ///
+    /// As for `receive_file()`. An `Error::Io` of kind `InvalidInput` is
+    /// returned if `offset` is 4 GiB or larger.
+    pub fn resume_file<W: io::Write>(&mut self, mut into: W, offset: u64) -> Result<Option<(FileInfo, usize)>> {
+        let offset = match u32::try_from(offset) {
+            Ok(offset) => offset,
+            Err(_) => return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput, "offset too large"))),
+        };
+
+        if !self.started {
+            self.started = true;
+            (self.progress)(Progress::Started);
+        }
+
+        let mut block = [0u8; SUBPACKET_SIZE];
+        let info = match self.receive_file_info(&mut block)? {
+            Some(info) => info,
+            None => return Ok(None),
+        };
+
+        let mut position = offset;
+        let mut errors = 0;
+        let mut subpackets = 0;
+        self.write_hex_header(Header::with_position(ZRPOS, position))?;
+        loop {
+            self.flush()?;
+            match self.read_header() {
+                Ok(h) if h.frame == ZDATA && h.position() == position => {}
+                Ok(h) if h.frame == ZEOF && h.position() == position => {
+                    return Ok(Some((info, (position - offset) as usize)));
+                }
+                Ok(h) if h.frame == ZFILE => {
+                    // The sender missed our `ZRPOS`.
+                    let _ = self.read_subpacket(&mut block);
+                    self.write_hex_header(Header::with_position(ZRPOS, position))?;
+                    continue;
+                }
+                Ok(h) if h.frame == ZEOF => continue,
+                Ok(_) | Err(Error::Timeout) | Err(Error::BadChecksum) => {
+                    self.retry(&mut errors)?;
+                    self.write_hex_header(Header::with_position(ZRPOS, position))?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+
+            loop {
+                let (n, end) = match self.read_subpacket(&mut block) {
+                    Ok(subpacket) => subpacket,
+                    Err(Error::Timeout) | Err(Error::BadChecksum) => {
+                        self.retry(&mut errors)?;
+                        (self.progress)(Progress::Nak { block: subpackets + 1, attempt: errors });
+                        self.write_hex_header(Header::with_position(ZRPOS, position))?;
+                        break;
+                    }
+                    Err(e) => return Err(e),
+                };
+
+                into.write_all(&block[..n])?;
+                position += n as u32;
+                errors = 0;
+                subpackets += 1;
+                (self.progress)(Progress::Packet {
+                    block: subpackets,
+                    bytes: position as u64,
+                    total: info.size(),
+                });
+
+                match end {
+                    ZCRCG => continue,
+                    ZCRCQ => self.write_hex_header(Header::with_position(ZACK, position))?,
+                    ZCRCW => {
+                        self.write_hex_header(Header::with_position(ZACK, position))?;
+                        break;
+                    }
+                    _ => break,
+                }
+            }
+        }
+    }
+
+    /// Offers to receive a file with `ZRINIT` until the sender responds with
+    /// `ZFILE` or `ZFIN`, using `block` to read the file information.
+    ///
+    /// Returns `None` if the sender ended the session.
+    fn receive_file_info(&mut self, block: &mut [u8; SUBPACKET_SIZE]) -> Result<Option<FileInfo>> {
+        let mut errors = 0;
+        loop {
+            self.write_hex_header(Header::new(ZRINIT, [0, 0, 0, CANFC32 | CANOVIO | CANFDX]))?;
+            self.flush()?;
+            match self.read_header() {
+                Ok(h) if h.frame == ZFILE => match self.read_subpacket(block) {
+                    Ok((n, _)) => match FileInfo::decode(&block[..n]) {
+                        Ok(Some(info)) => return Ok(Some(info)),
+                        Ok(None) => {
+                            self.cancel()?;
+                            return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidData, "empty file name")));
+                        }
+                        Err(e) => {
+                            self.cancel()?;
+                            return Err(Error::Io(e));
+                        }
+                    },
+                    Err(Error::Timeout) | Err(Error::BadChecksum) => self.retry(&mut errors)?,
+                    Err(e) => return Err(e),
+                },
+                Ok(h) if h.frame == ZFIN => {
+                    self.write_hex_header(Header::new(ZFIN, [0; 4]))?;
+                    self.flush()?;
+
+                    // The sender's "OO" is a courtesy; don't wait if it's lost.
+                    let _ = self.read_byte().and_then(|_| self.read_byte());
+                    self.started = false;
+                    return Ok(None);
+                }
+                Ok(_) | Err(Error::Timeout) | Err(Error::BadChecksum) => self.retry(&mut errors)?,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Waits for the receiver's `ZRINIT`, sending `ZRQINIT` until it arrives.
+    /// Does nothing if the session has already started.
+    fn start_send(&mut self) -> Result<()> {
+        if self.started {
+            return Ok(());
+        }
+
+        (self.progress)(Progress::Waiting);
+        let mut errors = 0;
+        loop {
+            self.write_hex_header(Header::new(ZRQINIT, [0; 4]))?;
+            self.flush()?;
+            match self.read_header() {
+                Ok(h) if h.frame == ZRINIT => {
+                    self.crc32 = h.flags() & CANFC32 != 0;
+                    self.started = true;
+                    (self.progress)(Progress::Started);
+                    return Ok(());
+                }
+                Ok(_) | Err(Error::Timeout) | Err(Error::BadChecksum) => self.retry(&mut errors)?,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Streams `data` from `start` in windows, each acknowledged by the
+    /// receiver, going back whenever the receiver asks with `ZRPOS`. Ends with
+    /// `ZEOF` once the receiver has everything.
+    fn send_data(&mut self, data: &[u8], start: usize) -> Result<()> {
+        let mut position = start;
+        let mut errors = 0;
+        let mut windows = 0;
+        loop {
+            let window_end = (position + WINDOW_SIZE).min(data.len());
+            if position < data.len() {
+                self.write_bin_header(Header::with_position(ZDATA, position as u32))?;
+                let mut chunks = data[position..window_end].chunks(SUBPACKET_SIZE).peekable();
+                while let Some(chunk) = chunks.next() {
+                    let end = if chunks.peek().is_some() { ZCRCG } else { ZCRCW };
+                    self.write_subpacket(chunk, end)?;
+                }
+            } else {
+                self.write_bin_header(Header::with_position(ZEOF, data.len() as u32))?;
+            }
+            self.flush()?;
+
+            match self.read_header() {
+                Ok(h) if h.frame == ZACK && h.position() as usize == window_end && position < data.len() => {
+                    position = window_end;
+                    errors = 0;
+                    windows += 1;
+                    (self.progress)(Progress::Packet {
+                        block: windows,
+                        bytes: position as u64,
+                        total: Some(data.len() as u64),
+                    });
+                }
+                Ok(h) if h.frame == ZRINIT && position == data.len() => return Ok(()),
+                Ok(h) if h.frame == ZRPOS => {
+                    self.retry(&mut errors)?;
+                    position = (h.position() as usize).min(data.len());
+                    (self.progress)(Progress::Retransmit { block: windows + 1, attempt: errors });
+                }
+                Ok(_) | Err(Error::Timeout) | Err(Error::BadChecksum) => self.retry(&mut errors)?,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Counts a failed attempt in `errors`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::RetriesExhausted` once `errors` exceeds the allowed
+    /// number of retries.
+    fn retry(&mut self, errors: &mut usize) -> Result<()> {
+        *errors += 1;
+        match *errors > self.config.max_retries() {
+            true => Err(Error::RetriesExhausted),
+            false => Ok(()),
+        }
+    }
+
+    /// Reads a single byte from the inner I/O stream.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Timeout` if the inner stream times out and `Error::Io`
+    /// if reading from it fails otherwise.
+    fn read_byte(&mut self) -> Result<u8> {
+        let mut buf = [0u8; 1];
+        self.inner.read_exact(&mut buf).map_err(|e| match e.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Error::Timeout,
+            _ => Error::Io(e),
+        })?;
+
+        Ok(buf[0])
+    }
+
+    /// Reads a single byte of ZDLE-escaped data, skipping unescaped flow
+    /// control characters.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Cancelled` on five `CAN`s in a row and
+    /// `Error::BadChecksum` on an invalid escape.
+    fn read_escaped(&mut self) -> Result<Escaped> {
+        let mut cans = 0;
+        loop {
+            let b = self.read_byte()?;
+            if cans == 0 {
+                match b {
+                    ZDLE => cans = 1,
+                    b if b & 0x7F == XON || b & 0x7F == XOFF => continue,
+                    b => return Ok(Escaped::Byte(b)),
+                }
+                continue;
+            }
+
+            match b {
+                ZDLE => {
+                    cans += 1;
+                    if cans == 5 {
+                        return Err(Error::Cancelled);
+                    }
+                }
+                ZCRCE | ZCRCG | ZCRCQ | ZCRCW => return Ok(Escaped::End(b)),
+                ZRUB0 => return Ok(Escaped::Byte(0x7F)),
+                ZRUB1 => return Ok(Escaped::Byte(0xFF)),
+                b if b & 0x7F == XON || b & 0x7F == XOFF => continue,
+                b if b & 0x60 == 0x40 => return Ok(Escaped::Byte(b ^ 0x40)),
+                _ => return Err(Error::BadChecksum),
+            }
+        }
+    }
+
+    /// Fills `buf` with ZDLE-escaped data bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::BadChecksum` if a subpacket ends instead.
+    fn read_escaped_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        for b in buf.iter_mut() {
+            match self.read_escaped()? {
+                Escaped::Byte(byte) => *b = byte,
+                Escaped::End(_) => return Err(Error::BadChecksum),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the next header, skipping anything before it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::BadChecksum` if the header is corrupt,
+    /// `Error::Cancelled` on five `CAN`s in a row and `Error::UnexpectedByte`
+    /// if no header arrives within `GARBAGE_LIMIT` bytes.
+    fn read_header(&mut self) -> Result<Header> {
+        let mut cans = 0;
+        for _ in 0..GARBAGE_LIMIT {
+            let mut b = self.read_byte()?;
+            if b == CAN {
+                cans += 1;
+                if cans == 5 {
+                    return Err(Error::Cancelled);
+                }
+                continue;
+            }
+
+            cans = 0;
+            if b != ZPAD {
+                continue;
+            }
+
+            while b == ZPAD {
+                b = self.read_byte()?;
+            }
+            if b != ZDLE {
+                continue;
+            }
+
+            return match self.read_byte()? {
+                ZHEX => self.read_hex_header(),
+                ZBIN32 => self.read_bin_header(true),
+                ZBIN => self.read_bin_header(false),
+                _ => continue,
+            };
+        }
+
+        Err(Error::UnexpectedByte(ZPAD))
+    }
+
+    /// Reads the rest of a hex header after `ZPAD ZPAD ZDLE ZHEX`.
+    fn read_hex_header(&mut self) -> Result<Header> {
+        let mut bytes = [0u8; 7];
+        for b in bytes.iter_mut() {
+            let hi = self.read_byte()?;
+            let lo = self.read_byte()?;
+            *b = match (hex_value(hi), hex_value(lo)) {
+                (Some(hi), Some(lo)) => hi << 4 | lo,
+                _ => return Err(Error::BadChecksum),
+            };
+        }
+
+        // The header ends with CR LF, the LF possibly with its high bit set.
+        if self.read_byte()? & 0x7F == b'\r' {
+            self.read_byte()?;
+        }
+
+        if get_crc(&bytes[..5]) != u16::from_be_bytes([bytes[5], bytes[6]]) {
+            return Err(Error::BadChecksum);
+        }
+
+        Ok(Header::new(bytes[0], [bytes[1], bytes[2], bytes[3], bytes[4]]))
+    }
+
+    /// Reads the rest of a binary header after `ZPAD ZDLE ZBIN32` or
+    /// `ZPAD ZDLE ZBIN`. Subpackets that follow use the same CRC.
+    fn read_bin_header(&mut self, crc32: bool) -> Result<Header> {
+        let mut bytes = [0u8; 9];
+        let len = if crc32 { 9 } else { 7 };
+        self.read_escaped_exact(&mut bytes[..len])?;
+
+        let valid = match crc32 {
+            true => get_crc32(&bytes[..5]) == u32::from_le_bytes([bytes[5], bytes[6], bytes[7], bytes[8]]),
+            false => get_crc(&bytes[..5]) == u16::from_be_bytes([bytes[5], bytes[6]]),
+        };
+        if !valid {
+            return Err(Error::BadChecksum);
+        }
+
+        self.crc32 = crc32;
+        Ok(Header::new(bytes[0], [bytes[1], bytes[2], bytes[3], bytes[4]]))
+    }
+
+    /// Reads a data subpacket into `buf`, returning its length and how it
+    /// ended.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::BadChecksum` if the subpacket is corrupt or too long.
+    fn read_subpacket(&mut self, buf: &mut [u8; SUBPACKET_SIZE]) -> Result<(usize, u8)> {
+        let mut len = 0;
+        let end = loop {
+            match self.read_escaped()? {
+                Escaped::Byte(_) if len == SUBPACKET_SIZE => return Err(Error::BadChecksum),
+                Escaped::Byte(b) => {
+                    buf[len] = b;
+                    len += 1;
+                }
+                Escaped::End(end) => break end,
+            }
+        };
+
+        let mut check = [0u8; 4];
+        let valid = match self.crc32 {
+            true => {
+                self.read_escaped_exact(&mut check)?;
+                !update_crc32(update_crc32(!0, &buf[..len]), &[end]) == u32::from_le_bytes(check)
+            }
+            false => {
+                self.read_escaped_exact(&mut check[..2])?;
+                update_crc(get_crc(&buf[..len]), &[end]) == u16::from_be_bytes([check[0], check[1]])
+            }
+        };
+
+        match valid {
+            true => Ok((len, end)),
+            false => Err(Error::BadChecksum),
+        }
+    }
+
+    /// Writes `header` as a hex header.
+    fn write_hex_header(&mut self, header: Header) -> Result<()> {
+        const HEX: &[u8; 16] = b"0123456789abcdef";
+
+        let bytes = header.bytes();
+        let crc = get_crc(&bytes).to_be_bytes();
+
+        let mut buf = [0u8; 4 + 14 + 3];
+        buf[..4].copy_from_slice(&[ZPAD, ZPAD, ZDLE, ZHEX]);
+        for (i, b) in bytes.iter().chain(crc.iter()).enumerate() {
+            buf[4 + 2 * i] = HEX[(b >> 4) as usize];
+            buf[5 + 2 * i] = HEX[(b & 0xF) as usize];
+        }
+        buf[18] = b'\r';
+        buf[19] = b'\n' | 0x80;
+
+        // Undo a possible XOFF, except where the other side may have exited.
+        let len = match header.frame {
+            ZFIN | ZACK => 20,
+            _ => {
+                buf[20] = XON;
+                21
+            }
+        };
+
+        Ok(self.inner.write_all(&buf[..len])?)
+    }
+
+    /// Writes `header` as a binary header, with a CRC-32 if the receiver can
+    /// check it.
+    fn write_bin_header(&mut self, header: Header) -> Result<()> {
+        let bytes = header.bytes();
+        let mut buf = [0u8; 3 + 2 * 9];
+        buf[..3].copy_from_slice(&[ZPAD, ZDLE, if self.crc32 { ZBIN32 } else { ZBIN }]);
+
+        let mut len = escape_into(&mut buf, 3, &bytes);
+        len = match self.crc32 {
+            true => escape_into(&mut buf, len, &get_crc32(&bytes).to_le_bytes()),
+            false => escape_into(&mut buf, len, &get_crc(&bytes).to_be_bytes()),
+        };
+
+        Ok(self.inner.write_all(&buf[..len])?)
+    }
+
+    /// Writes `data`, at most `SUBPACKET_SIZE` bytes, as a subpacket ending in
+    /// `end`.
+    fn write_subpacket(&mut self, data: &[u8], end: u8) -> Result<()> {
+        let mut buf = [0u8; 2 * SUBPACKET_SIZE + 2 + 2 * 4];
+        let mut len = escape_into(&mut buf, 0, data);
+        buf[len] = ZDLE;
+        buf[len + 1] = end;
+        len += 2;
+
+        len = match self.crc32 {
+            true => {
+                let crc = !update_crc32(update_crc32(!0, data), &[end]);
+                escape_into(&mut buf, len, &crc.to_le_bytes())
+            }
+            false => {
+                let crc = update_crc(get_crc(data), &[end]);
+                escape_into(&mut buf, len, &crc.to_be_bytes())
+            }
+        };
+
+        Ok(self.inner.write_all(&buf[..len])?)
+    }
+
+    /// Flush this output stream, ensuring that all intermediately buffered
+    /// contents reach their destination.
+    ///
+    /// # Errors
+    ///
+    /// It is considered an error if not all bytes could be written due to I/O
+    /// errors or EOF being reached.
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.inner.flush()?)
+    }
+}
+
+/// The value of the lowercase or uppercase hex digit `c`.
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
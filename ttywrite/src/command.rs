@@ -0,0 +1,147 @@
+//! Client for the bootloader's command mode; see `bootimg::proto`.
+
+use std::io::{self, Read, Write};
+
+use bootimg::crc32;
+use bootimg::proto::{Decoded, Decoder, Info, Request, Status, COMMAND_MODE, MAX_DATA, MAX_FRAME};
+
+/// XMODEM receivers start a transfer with `NAK` or, for CRCs, `C`.
+const NAK: u8 = 0x15;
+const CRC: u8 = b'C';
+
+/// A bootloader in command mode.
+pub struct Client<T> {
+    inner: T,
+    decoder: Decoder,
+    info: Info,
+}
+
+impl<T: Read + Write> Client<T> {
+    /// Waits for the bootloader on `inner` to ask for an XMODEM upload, then
+    /// switches it to command mode instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bootloader doesn't respond, or doesn't speak
+    /// the command protocol.
+    pub fn connect(mut inner: T) -> io::Result<Client<T>> {
+        let mut byte = [0u8; 1];
+        while byte[0] != CRC && byte[0] != NAK {
+            inner.read_exact(&mut byte)?;
+        }
+
+        inner.write_all(&[COMMAND_MODE])?;
+        // Filled in from the response to `Hello`.
+        let info = Info { protocol: 0, load_start: 0, load_end: 0, max_data: MAX_DATA as u32 };
+        let mut client = Client { inner, decoder: Decoder::new(), info };
+        let payload = client.request(Request::Hello)?;
+        client.info = Info::parse(&payload).ok_or_else(|| invalid_data("short hello response"))?;
+        Ok(client)
+    }
+
+    /// The bootloader's version and limits.
+    pub fn info(&self) -> Info {
+        self.info
+    }
+
+    /// Writes `data` to the bootloader's memory at `addr`, then checks it
+    /// arrived intact.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bootloader rejects the upload or the memory's
+    /// CRC-32 doesn't match `data`'s afterwards.
+    pub fn upload(&mut self, addr: u64, data: &[u8], mut progress: impl FnMut(usize)) -> io::Result<()> {
+        let mut sent = 0;
+        for chunk in data.chunks(self.max_data()) {
+            self.request(Request::Upload { addr: addr + sent as u64, data: chunk })?;
+            sent += chunk.len();
+            progress(sent);
+        }
+
+        match self.crc(addr, data.len() as u64)? == crc32(data) {
+            true => Ok(()),
+            false => Err(invalid_data("uploaded data failed its CRC-32")),
+        }
+    }
+
+    /// Returns the CRC-32 of `len` bytes of the bootloader's memory at `addr`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bootloader rejects the request.
+    pub fn crc(&mut self, addr: u64, len: u64) -> io::Result<u32> {
+        let payload = self.request(Request::Crc { addr, len })?;
+        match payload.get(..4) {
+            Some(crc) => Ok(u32::from_le_bytes([crc[0], crc[1], crc[2], crc[3]])),
+            None => Err(invalid_data("short CRC response")),
+        }
+    }
+
+    /// Reads `len` bytes of the bootloader's memory at `addr`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bootloader rejects the request.
+    pub fn peek(&mut self, addr: u64, len: u64) -> io::Result<Vec<u8>> {
+        let mut memory = Vec::new();
+        while (memory.len() as u64) < len {
+            let chunk = (len - memory.len() as u64).min(self.max_data() as u64) as u32;
+            let payload = self.request(Request::Peek { addr: addr + memory.len() as u64, len: chunk })?;
+            if payload.len() != chunk as usize {
+                return Err(invalid_data("short peek response"));
+            }
+            memory.extend_from_slice(&payload);
+        }
+
+        Ok(memory)
+    }
+
+    /// Makes the bootloader jump to `addr`, ending command mode.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bootloader rejects the request.
+    pub fn go(mut self, addr: u64) -> io::Result<()> {
+        self.request(Request::Go { addr }).map(|_| ())
+    }
+
+    /// The largest upload or peek per request the bootloader takes.
+    fn max_data(&self) -> usize {
+        (self.info.max_data as usize).clamp(1, MAX_DATA)
+    }
+
+    /// Sends `request` and returns the payload of the bootloader's response.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the response is corrupt or its status isn't
+    /// `Status::Ok`.
+    fn request(&mut self, request: Request) -> io::Result<Vec<u8>> {
+        let mut frame = [0u8; MAX_FRAME];
+        let len = request.encode(&mut frame);
+        self.inner.write_all(&frame[..len])?;
+        self.inner.flush()?;
+
+        let mut byte = [0u8; 1];
+        loop {
+            self.inner.read_exact(&mut byte)?;
+            match self.decoder.push(byte[0]) {
+                Decoded::Pending => continue,
+                Decoded::BadFrame => return Err(invalid_data("corrupt response")),
+                Decoded::Frame => break,
+            }
+        }
+
+        let (status, payload) = self.decoder.frame();
+        match Status::from_code(status) {
+            Some(Status::Ok) => Ok(payload.to_vec()),
+            Some(status) => Err(io::Error::other(format!("bootloader rejected request: {:?}", status))),
+            None => Err(invalid_data("unknown response status")),
+        }
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
@@ -0,0 +1,232 @@
+//! Named profiles of target and line settings, read from TOML files:
+//!
+//! ```toml
+//! # Profile to use without --profile.
+//! default = "pi"
+//!
+//! [profiles.pi]
+//! baud = 115200
+//! width = 8
+//! stop-bits = 1
+//! flow-control = "none"
+//! timeout = 10
+//!
+//! # The target, as on the command line, or the USB adapter to find.
+//! [profiles.pi.usb]
+//! vid = "0403"
+//! pid = "6001"
+//! serial = "A50285BI"
+//!
+//! [profiles.qemu]
+//! target = "tcp://localhost:4444"
+//! ```
+//!
+//! The user's profiles are in `$XDG_CONFIG_HOME/ttywrite/config.toml`, by
+//! default `~/.config/ttywrite/config.toml`. A project's are in the nearest
+//! `ttywrite.toml` in the current directory or above, and override the user's
+//! setting by setting.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::usb::UsbMatch;
+
+/// Name of a project's configuration file.
+pub const PROJECT_FILE: &str = "ttywrite.toml";
+
+/// Settings for one board or setup. Unset ones come from the command line or
+/// its defaults.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Profile {
+    /// Target as on the command line: a path, `tcp://` or `rfc2217://`.
+    pub target: Option<String>,
+    /// USB serial adapter to use as the target, if `target` isn't set.
+    pub usb: Option<UsbMatch>,
+    pub baud: Option<u32>,
+    pub width: Option<u8>,
+    pub stop_bits: Option<u8>,
+    pub flow_control: Option<String>,
+    /// Read timeout in seconds.
+    pub timeout: Option<u64>,
+}
+
+impl Profile {
+    /// Returns this profile with the settings `over` sets replacing its own.
+    /// A target and a USB adapter are one choice: setting either replaces both.
+    fn merge(self, over: Profile) -> Profile {
+        let (target, usb) = if over.target.is_some() || over.usb.is_some() {
+            (over.target, over.usb)
+        } else {
+            (self.target, self.usb)
+        };
+
+        Profile {
+            target,
+            usb,
+            baud: over.baud.or(self.baud),
+            width: over.width.or(self.width),
+            stop_bits: over.stop_bits.or(self.stop_bits),
+            flow_control: over.flow_control.or(self.flow_control),
+            timeout: over.timeout.or(self.timeout),
+        }
+    }
+}
+
+/// The profiles from every configuration file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Profile to use when none is named.
+    default: Option<String>,
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+}
+
+impl Config {
+    /// Reads the user's configuration file and the project's, or `project`
+    /// in its place. Missing files are empty, except `project`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the problem if a file can't be read or isn't
+    /// a valid configuration.
+    pub fn load(project: Option<&Path>) -> Result<Config, String> {
+        let mut config = match user_file() {
+            Some(path) if path.is_file() => Config::read(&path)?,
+            _ => Config::default(),
+        };
+
+        let project = match project {
+            Some(path) => Some(path.to_path_buf()),
+            None => project_file(),
+        };
+        if let Some(path) = project {
+            config = config.merge(Config::read(&path)?);
+        }
+        Ok(config)
+    }
+
+    /// Reads the configuration file at `path`.
+    fn read(path: &Path) -> Result<Config, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("reading {}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Returns this configuration with `over`'s default and profiles replacing
+    /// its own, setting by setting.
+    fn merge(mut self, over: Config) -> Config {
+        for (name, profile) in over.profiles {
+            let merged = match self.profiles.remove(&name) {
+                Some(base) => base.merge(profile),
+                None => profile,
+            };
+            self.profiles.insert(name, merged);
+        }
+
+        Config { default: over.default.or(self.default), profiles: self.profiles }
+    }
+
+    /// Returns the name of the profile to use: `name` if given, or the
+    /// default one, if there is one.
+    pub fn profile_name<'a>(&'a self, name: Option<&'a str>) -> Option<&'a str> {
+        name.or(self.default.as_deref())
+    }
+
+    /// Returns the profile called `name`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the problem if there is no such profile.
+    pub fn profile(&self, name: &str) -> Result<&Profile, String> {
+        self.profiles.get(name).ok_or_else(|| {
+            let known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            if known.is_empty() {
+                format!("no profile {}: no profiles are configured", name)
+            } else {
+                format!("no profile {}, only {}", name, known.join(", "))
+            }
+        })
+    }
+}
+
+/// Returns the path of the user's configuration file, if there is a home.
+fn user_file() -> Option<PathBuf> {
+    let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("ttywrite").join("config.toml"))
+}
+
+/// Returns the path of the nearest `PROJECT_FILE` in the current directory or
+/// above, if any.
+fn project_file() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    cwd.ancestors().map(|dir| dir.join(PROJECT_FILE)).find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `user` and `project` configurations and merges them.
+    fn merged(user: &str, project: &str) -> Config {
+        let user: Config = toml::from_str(user).expect("user config parses");
+        let project: Config = toml::from_str(project).expect("project config parses");
+        user.merge(project)
+    }
+
+    #[test]
+    fn test_project_overrides_user_by_setting() {
+        let config = merged(
+            "[profiles.pi]\ntarget = \"/dev/ttyUSB0\"\nbaud = 9600\nwidth = 7\n",
+            "[profiles.pi]\nbaud = 115200\ntimeout = 3\n",
+        );
+
+        let profile = config.profile("pi").expect("pi is configured");
+        assert_eq!(profile, &Profile {
+            target: Some("/dev/ttyUSB0".to_string()),
+            usb: None,
+            baud: Some(115200),
+            width: Some(7),
+            stop_bits: None,
+            flow_control: None,
+            timeout: Some(3),
+        });
+    }
+
+    #[test]
+    fn test_target_and_usb_replace_each_other() {
+        let user = "[profiles.pi]\nbaud = 9600\n[profiles.pi.usb]\nvid = \"0403\"\npid = \"6001\"\n";
+        let config = merged(user, "[profiles.pi]\ntarget = \"tcp://localhost:4444\"\n");
+        let profile = config.profile("pi").expect("pi is configured");
+        assert_eq!(profile.target.as_deref(), Some("tcp://localhost:4444"));
+        assert_eq!(profile.usb, None);
+        assert_eq!(profile.baud, Some(9600));
+
+        let config = merged("[profiles.pi]\ntarget = \"/dev/ttyAMA0\"\n", "[profiles.pi.usb]\nvid = \"0403\"\npid = \"6015\"\n");
+        let profile = config.profile("pi").expect("pi is configured");
+        assert_eq!(profile.target, None);
+        assert_eq!(profile.usb, Some(UsbMatch { vid: "0403".to_string(), pid: "6015".to_string(), serial: None }));
+    }
+
+    #[test]
+    fn test_default_profile() {
+        let config = merged("default = \"pi\"\n[profiles.pi]\n", "default = \"qemu\"\n[profiles.qemu]\n");
+        assert_eq!(config.profile_name(None), Some("qemu"));
+        assert_eq!(config.profile_name(Some("pi")), Some("pi"));
+        assert_eq!(merged("", "").profile_name(None), None);
+    }
+
+    #[test]
+    fn test_unknown_profile() {
+        let config = merged("[profiles.pi]\n", "[profiles.qemu]\n");
+        assert_eq!(config.profile("zero").expect_err("no such profile"), "no profile zero, only pi, qemu");
+
+        let e = merged("", "").profile("pi").expect_err("no profiles");
+        assert_eq!(e, "no profile pi: no profiles are configured");
+    }
+}
@@ -1,95 +1,603 @@
+mod command;
+mod config;
 mod parsers;
+mod port;
+mod rfc2217;
+mod status;
+mod terminal;
+mod usb;
+mod watch;
 
 use clap::command;
 use serial;
-use xmodem::Xmodem;
+use bootimg::{ed25519, lz4, Header};
+use xmodem::{FileInfo, Mode, Xmodem, Ymodem, Zmodem};
 use xmodem::Progress;
 
 use std::path::PathBuf;
 use std::time::Duration;
 
-use serial::core::{CharSize, BaudRate, StopBits, FlowControl, SerialDevice, SerialPortSettings};
+use serial::core::{CharSize, BaudRate, StopBits, FlowControl};
 
-use parsers::{parse_width, parse_stop_bits, parse_flow_control, parse_baud_rate};
-use clap::{Parser, ValueHint};
+use command::Client;
+use config::{Config, Profile};
+use port::{LineSettings, Port, Target};
+use status::{Outcome, StatusFilter};
+use terminal::{Ended, Newline};
+use watch::{Reset, Watcher};
+use parsers::{parse_target, parse_width, parse_stop_bits, parse_flow_control, parse_baud_rate, parse_addr, parse_upload, parse_range, parse_secret_key, parse_cmdline, parse_escape, parse_newline, parse_reset};
+use clap::{ArgGroup, Parser, ValueHint};
 
 /// Simple program to greet a person
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
+#[command(group(ArgGroup::new("interactive").args(["terminal", "watch"]).multiple(true)))]
 struct Args {
     /// Input file (defaults to stdin if not set)
     #[arg(short = 'i', value_hint = ValueHint::FilePath)]
     input: Option<PathBuf>,
 
-    /// Set baud rate
-    #[arg(short = 'b', long = "baud", value_parser = parse_baud_rate, default_value = "115200")]
-    baud_rate: BaudRate,
+    /// Set baud rate [default: 115200]
+    #[arg(short = 'b', long = "baud", value_parser = parse_baud_rate)]
+    baud_rate: Option<BaudRate>,
 
-    /// Set timeout in seconds
-    #[arg(short = 't', long = "timeout",  default_value = "10")]
-    timeout: u64,
+    /// Set timeout in seconds [default: 10]
+    #[arg(short = 't', long = "timeout")]
+    timeout: Option<u64>,
 
-    /// Set data character width in bits
-    #[arg(short = 'w', long = "width", value_parser = parse_width, default_value = "8")]
-    char_width: CharSize,
+    /// Set data character width in bits [default: 8]
+    #[arg(short = 'w', long = "width", value_parser = parse_width)]
+    char_width: Option<CharSize>,
 
-    /// Path to TTY device
-    #[arg(value_hint = ValueHint::FilePath)]
-    tty_path: PathBuf,
+    /// Path to TTY device or pty, or tcp://HOST:PORT or rfc2217://HOST:PORT
+    /// for a serial port over TCP (line settings only apply to a TTY device
+    /// or through RFC 2217). Defaults to the profile's target or USB adapter
+    #[arg(value_name = "TARGET", value_parser = parse_target, value_hint = ValueHint::FilePath)]
+    target: Option<Target>,
 
-    /// Enable flow control ('hardware' or 'software')
-    #[arg(short = 'f', long = "flow-control", value_parser = parse_flow_control, default_value = "none")]
-    flow_control: FlowControl,
+    /// Enable flow control ('hardware' or 'software') [default: none]
+    #[arg(short = 'f', long = "flow-control", value_parser = parse_flow_control)]
+    flow_control: Option<FlowControl>,
 
-    /// Set number of stop bits
-    #[arg(short = 's', long = "stop-bits", value_parser = parse_stop_bits, default_value = "1")]
-    stop_bits: StopBits,
+    /// Set number of stop bits [default: 1]
+    #[arg(short = 's', long = "stop-bits", value_parser = parse_stop_bits)]
+    stop_bits: Option<StopBits>,
+
+    /// Take the target and line settings not given from the profile NAME
+    /// (defaults to the configuration's default profile)
+    #[arg(short = 'p', long = "profile", value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Read profiles from FILE in place of the nearest ttywrite.toml, on top
+    /// of the user's ~/.config/ttywrite/config.toml
+    #[arg(long = "config", value_name = "FILE", value_hint = ValueHint::FilePath)]
+    config: Option<PathBuf>,
 
     /// Disable XMODEM
     #[arg(short = 'r', long = "raw")]
     raw: bool,
+
+    /// Send 1024-byte XMODEM-1K packets when the receiver requests CRC
+    #[arg(short = 'k', long = "1k", conflicts_with = "raw")]
+    one_k: bool,
+
+    /// Use YMODEM, sending the file name, size and modification time
+    #[arg(short = 'y', long = "ymodem", conflicts_with = "raw")]
+    ymodem: bool,
+
+    /// Use ZMODEM, streaming the file with CRC-32 and resuming after errors
+    #[arg(short = 'z', long = "zmodem", conflicts_with_all = ["raw", "ymodem", "one_k"])]
+    zmodem: bool,
+
+    /// Prefix the input with a boot image header for the bootloader
+    #[arg(long = "boot-header")]
+    boot_header: bool,
+
+    /// Load address written to the boot image header
+    #[arg(long = "load-addr", value_parser = parse_addr, default_value = "0x80000")]
+    load_addr: u64,
+
+    /// Entry point written to the boot image header (defaults to the load address)
+    #[arg(long = "entry", value_parser = parse_addr, requires = "boot_header")]
+    entry: Option<u64>,
+
+    /// Command line for the kernel, carried in the boot image
+    #[arg(long = "cmdline", value_name = "ARGS", value_parser = parse_cmdline, requires = "boot_header")]
+    cmdline: Option<String>,
+
+    /// Sign the boot image with the Ed25519 private key in KEY, raw or DER
+    /// (`openssl genpkey -algorithm ed25519 -outform DER`)
+    #[arg(long = "sign", value_name = "KEY", value_parser = parse_secret_key, requires = "boot_header")]
+    sign: Option<[u8; ed25519::SECRET_KEY_LEN]>,
+
+    /// Compress the input, after adding any boot image header, into an LZ4 frame
+    #[arg(long = "lz4")]
+    lz4: bool,
+
+    /// Print the bootloader's protocol version and memory limits
+    #[arg(long = "info")]
+    info: bool,
+
+    /// Upload FILE to ADDR with the bootloader's command mode, then verify it
+    #[arg(long = "upload", value_name = "ADDR=FILE", value_parser = parse_upload)]
+    upload: Vec<(u64, PathBuf)>,
+
+    /// Print the CRC-32 of LEN bytes of the bootloader's memory at ADDR
+    #[arg(long = "crc", value_name = "ADDR:LEN", value_parser = parse_range)]
+    crc: Vec<(u64, u64)>,
+
+    /// Dump LEN bytes of the bootloader's memory at ADDR
+    #[arg(long = "peek", value_name = "ADDR:LEN", value_parser = parse_range)]
+    peek: Vec<(u64, u64)>,
+
+    /// Make the bootloader jump to ADDR once everything else is done
+    #[arg(long = "go", value_name = "ADDR", value_parser = parse_addr)]
+    go: Option<u64>,
+
+    /// Receive a file with XMODEM into FILE, or stdout for '-', such as one
+    /// the kernel sends with `xsend`
+    #[arg(long = "receive", value_name = "FILE", value_hint = ValueHint::FilePath,
+          conflicts_with_all = ["input", "raw", "ymodem", "zmodem", "boot_header", "lz4", "info", "upload", "crc", "peek", "go"])]
+    receive: Option<PathBuf>,
+
+    /// Bridge stdin and stdout to the TTY after the transfer, or in place of
+    /// one without an input file
+    #[arg(long = "terminal")]
+    terminal: bool,
+
+    /// After the transfer, reload the input file whenever it changes: reset
+    /// the board, upload the file again and reattach the terminal
+    #[arg(long = "watch", requires = "input", conflicts_with_all = ["raw", "ymodem", "zmodem"])]
+    watch: bool,
+
+    /// How --watch resets the board: 'magic' sends a sequence the kernel and
+    /// bootloader watch for, 'dtr' or 'rts' pulses that line
+    #[arg(long = "reset", value_parser = parse_reset, default_value = "magic", requires = "watch")]
+    reset: Reset,
+
+    /// Key that starts terminal commands, as '^X' or a character
+    #[arg(long = "escape", value_name = "KEY", value_parser = parse_escape, default_value = "^]", requires = "interactive")]
+    escape: u8,
+
+    /// Echo typed characters locally in terminal mode
+    #[arg(long = "echo", requires = "interactive")]
+    echo: bool,
+
+    /// What Enter sends in terminal mode ('cr', 'lf' or 'crlf')
+    #[arg(long = "send-newline", value_parser = parse_newline, default_value = "cr", requires = "interactive")]
+    send_newline: Newline,
+
+    /// What received lines end with in terminal mode ('cr', 'lf' or 'crlf')
+    #[arg(long = "recv-newline", value_parser = parse_newline, default_value = "crlf", requires = "interactive")]
+    recv_newline: Newline,
+
+    /// Append what terminal mode prints to FILE
+    #[arg(long = "log", value_name = "FILE", value_hint = ValueHint::FilePath, requires = "interactive")]
+    log: Option<PathBuf>,
 }
 
-fn progress_fn(progress: Progress) {
-    println!("Progress: {:?}", progress);
+impl Args {
+    /// Fills in the target and line settings not given on the command line
+    /// from `profile`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the problem if a setting in `profile` is
+    /// invalid, or if its USB adapter can't be found.
+    fn apply_profile(&mut self, profile: &Profile) -> Result<(), String> {
+        fn setting<T, E: std::fmt::Display>(name: &str, value: Result<T, E>) -> Result<T, String> {
+            value.map_err(|e| format!("{}: {}", name, e))
+        }
+
+        if self.target.is_none() {
+            self.target = match (&profile.target, &profile.usb) {
+                (Some(target), _) => Some(setting("target", parse_target(target))?),
+                (None, Some(usb)) => Some(Target::Device(setting("usb", usb::find(usb))?)),
+                (None, None) => None,
+            };
+        }
+        if let (None, Some(baud)) = (self.baud_rate, profile.baud) {
+            self.baud_rate = Some(setting("baud", parse_baud_rate(&baud.to_string()))?);
+        }
+        if let (None, Some(width)) = (self.char_width, profile.width) {
+            self.char_width = Some(setting("width", parse_width(&width.to_string()))?);
+        }
+        if let (None, Some(stop_bits)) = (self.stop_bits, profile.stop_bits) {
+            self.stop_bits = Some(setting("stop-bits", parse_stop_bits(&stop_bits.to_string()))?);
+        }
+        if let (None, Some(flow_control)) = (self.flow_control, &profile.flow_control) {
+            self.flow_control = Some(setting("flow-control", parse_flow_control(flow_control))?);
+        }
+        self.timeout = self.timeout.or(profile.timeout);
+        Ok(())
+    }
+
+    /// Returns the line settings given, or their defaults.
+    fn line_settings(&self) -> LineSettings {
+        LineSettings {
+            baud_rate: self.baud_rate.unwrap_or(BaudRate::Baud115200),
+            char_size: self.char_width.unwrap_or(CharSize::Bits8),
+            stop_bits: self.stop_bits.unwrap_or(StopBits::Stop1),
+            flow_control: self.flow_control.unwrap_or(FlowControl::FlowNone),
+        }
+    }
+
+    /// Returns the read timeout given, or its default.
+    fn read_timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout.unwrap_or(10))
+    }
+
+    /// Returns `true` if any command for the bootloader's command mode was
+    /// given, in place of an XMODEM transfer.
+    fn has_commands(&self) -> bool {
+        self.info || !self.upload.is_empty() || !self.crc.is_empty() || !self.peek.is_empty() || self.go.is_some()
+    }
 }
 
-fn main() {
-    use std::fs::File;
-    use std::io::{self, BufReader, Write, Read};
-
-    let opt = Args::parse();
-    let mut port = serial::open(&opt.tty_path).expect("path points to invalid TTY");
-
-    let mut settings = port.read_settings().expect("valid");
-    settings.set_baud_rate(opt.baud_rate).expect("valid baud rate");
-    settings.set_char_size(opt.char_width);
-    settings.set_stop_bits(opt.stop_bits);
-    settings.set_flow_control(opt.flow_control);
-    port.write_settings(&settings).expect("valid settings");
-    port.set_timeout(Duration::from_secs(opt.timeout)).expect("valid timeout");
-
-    // FIXME: Implement the `ttywrite` utility.
-    match opt.input {
-        None => {
-            loop {
-                let mut buffer = String::new();
-                io::stdin().read_to_string(&mut buffer).expect("valid read");
-                if opt.raw {
-                    (&mut port).write_all(&buffer.as_bytes()).expect("valid write");
-                } else {
-                    Xmodem::transmit_with_progress(buffer.as_bytes(), &mut port, progress_fn).expect("valid transmit");
-                }
+/// Returns a progress callback that prints each packet with a percentage of
+/// `size`, if known, and the number of packets retransmitted so far.
+fn progress_fn(size: Option<u64>) -> impl FnMut(Progress) {
+    let mut retransmits = 0;
+    move |progress| match progress {
+        Progress::Packet { block, bytes, total } => match total.or(size) {
+            Some(total) if total > 0 => {
+                let percent = bytes.min(total) * 100 / total;
+                println!("Progress: block {}, {}/{} bytes ({}%), {} retransmits", block, bytes, total, percent, retransmits);
             }
+            _ => println!("Progress: block {}, {} bytes, {} retransmits", block, bytes, retransmits),
         },
+        Progress::Retransmit { .. } => retransmits += 1,
+        progress => println!("Progress: {:?}", progress),
+    }
+}
+
+/// Returns a progress callback for receiving that prints each packet and the
+/// number of packets rejected so far to stderr, leaving stdout for the data.
+fn receive_progress_fn() -> impl FnMut(Progress) {
+    let mut naks = 0;
+    move |progress| match progress {
+        Progress::Packet { block, bytes, .. } => eprintln!("Progress: block {}, {} bytes, {} rejected", block, bytes, naks),
+        Progress::Nak { .. } => naks += 1,
+        progress => eprintln!("Progress: {:?}", progress),
+    }
+}
+
+/// How long to wait after an upload for the receiver's first status line, if
+/// it wrote none before, until taking it for something other than the
+/// bootloader.
+const FIRST_STATUS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Sends `data` with XMODEM to `port`, then prints the bootloader's status
+/// lines until it jumps to the image or reports an error, and returns which.
+/// Returns `None` if the receiver doesn't write status lines.
+fn transmit_xmodem<R>(data: R, port: &mut StatusFilter<&mut Port>, mode: Mode, size: Option<u64>, timeout: Duration) -> std::io::Result<Option<Outcome>>
+    where R: std::io::Read
+{
+    let sent = Xmodem::transmit_with_mode(data, &mut *port, mode, progress_fn(size));
+    // Even after a failed transfer, the bootloader says why.
+    let outcome = await_outcome(port, timeout);
+    sent?;
+    Ok(outcome)
+}
+
+/// Reads the bootloader's status lines until it jumps to the image or reports
+/// an error. Waits `FIRST_STATUS_TIMEOUT` for the first line if none came
+/// yet, then `timeout` for each.
+fn await_outcome(port: &mut StatusFilter<&mut Port>, timeout: Duration) -> Option<Outcome> {
+    if !port.seen_status() {
+        port.get_mut().set_timeout(FIRST_STATUS_TIMEOUT).ok()?;
+        let first = port.wait_for_status();
+        port.get_mut().set_timeout(timeout).ok()?;
+        first.ok()?;
+    }
+
+    port.wait_for_outcome().ok()
+}
+
+/// Sends `data` as a single-file YMODEM batch described by `info`.
+fn transmit_ymodem<R, W>(info: &FileInfo, data: R, to: W, mode: Mode) -> std::io::Result<usize>
+    where R: std::io::Read, W: std::io::Read + std::io::Write
+{
+    let mut sender = Ymodem::new_with_mode(to, mode, progress_fn(info.size()));
+    let written = sender.send_file(info, data)?;
+    sender.finish()?;
+    Ok(written)
+}
+
+/// Sends `data` as a single-file ZMODEM batch described by `info`.
+fn transmit_zmodem<W>(info: &FileInfo, data: &[u8], to: W) -> std::io::Result<usize>
+    where W: std::io::Read + std::io::Write
+{
+    let mut sender = Zmodem::new_with_progress(to, progress_fn(info.size()));
+    let written = sender.send_file(info, data)?;
+    sender.finish()?;
+    Ok(written)
+}
+
+/// Returns `payload` prefixed with a boot image header for the load address
+/// and entry point in `opt`, and followed by its command line.
+fn boot_image(payload: &[u8], opt: &Args) -> Vec<u8> {
+    let cmdline = opt.cmdline.as_deref().unwrap_or("");
+    let header = Header::new_with_cmdline(payload, cmdline, opt.load_addr, opt.entry.unwrap_or(opt.load_addr));
+    let mut image = header.to_bytes().to_vec();
+    image.extend_from_slice(payload);
+    image.extend_from_slice(cmdline.as_bytes());
+    image
+}
+
+/// Returns `input` as it should be sent: with a boot image header, signed,
+/// then compressed, as `opt` asks.
+fn prepare(input: Vec<u8>, opt: &Args) -> Vec<u8> {
+    let mut image = match opt.boot_header {
+        true => boot_image(&input, opt),
+        false => input,
+    };
+
+    if let Some(secret) = &opt.sign {
+        let signature = ed25519::sign(secret, &image);
+        image.extend_from_slice(&signature);
+    }
+
+    if !opt.lz4 {
+        return image;
+    }
+
+    let mut frame = vec![0; lz4::max_compressed_len(image.len())];
+    let len = lz4::compress(&image, &mut frame);
+    frame.truncate(len);
+    frame
+}
+
+/// Receives a file with XMODEM from `port` into the file at `path`, or stdout
+/// if it is `-`. Returns the number of bytes received, padding included.
+fn receive(port: &mut Port, path: &std::path::Path) -> std::io::Result<usize> {
+    use std::io::Write;
+
+    // Drop what the other side printed before starting to send, such as the
+    // echo of the command that started it, which isn't a packet.
+    port.discard_input()?;
+
+    let mut into: Box<dyn Write> = match path.to_str() {
+        Some("-") => Box::new(std::io::stdout().lock()),
+        _ => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+    };
+    let received = Xmodem::receive_with_mode(port, &mut into, Mode::Crc, receive_progress_fn())?;
+    into.flush()?;
+    Ok(received)
+}
+
+/// Returns the terminal mode settings `opt` asks for, opening the log.
+fn terminal_settings(opt: &Args) -> std::io::Result<terminal::Settings> {
+    let log = match &opt.log {
+        Some(path) => Some(std::fs::OpenOptions::new().create(true).append(true).open(path)?),
+        None => None,
+    };
+
+    Ok(terminal::Settings {
+        escape: opt.escape,
+        echo: opt.echo,
+        send_newline: opt.send_newline,
+        recv_newline: opt.recv_newline,
+        log,
+    })
+}
+
+/// Runs terminal mode on `port` as `opt` asks.
+fn run_terminal(opt: &Args, port: &mut Port) -> std::io::Result<()> {
+    terminal::run(port, &mut terminal_settings(opt)?)
+}
+
+/// Runs terminal mode on `port`, and whenever the file at `path` changes,
+/// loads it again as `opt` asks and reattaches. Failed reloads are reported
+/// and wait for the next change.
+fn watch(opt: &Args, port: &mut Port, path: &std::path::Path) -> Result<(), Failure> {
+    let mut settings = terminal_settings(opt).context("opening the log")?;
+    let mut watcher = Watcher::new(path);
+    loop {
+        eprintln!("Watching {} for changes", path.display());
+        match terminal::run_until(port, &mut settings, || watcher.changed()).context("terminal mode")? {
+            Ended::Stopped => {}
+            Ended::Quit | Ended::Closed => return Ok(()),
+        }
+
+        watcher.settle();
+        eprintln!("{} changed, reloading", path.display());
+        if let Err(failure) = reload(opt, port, path) {
+            eprintln!("ttywrite: {}", failure);
+        }
+    }
+}
+
+/// Resets the board on `port` and uploads the file at `path` to its
+/// bootloader with XMODEM.
+///
+/// # Errors
+///
+/// Returns a failure if reading the file, the reset or the transfer fails, or
+/// if the bootloader rejects the image.
+fn reload(opt: &Args, port: &mut Port, path: &std::path::Path) -> Result<(), Failure> {
+    let image = prepare(std::fs::read(path).context(format_args!("reading {}", path.display()))?, opt);
+    opt.reset.perform(port).context("resetting the board")?;
+
+    let mut port = StatusFilter::new(port);
+    port.wait_for_receiving().context("waiting for the bootloader")?;
+    let mode = if opt.one_k { Mode::Crc1k } else { Mode::Crc };
+    let size = Some(image.len() as u64);
+    let timeout = opt.read_timeout();
+    let outcome = transmit_xmodem(&image[..], &mut port, mode, size, timeout).context("XMODEM transfer")?;
+    if outcome == Some(Outcome::Failed) {
+        return Err(Failure("the bootloader rejected the image".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Carries out the bootloader commands in `opt`: info, uploads, CRCs, peeks
+/// and finally go, in that order.
+fn run_commands<T: std::io::Read + std::io::Write>(opt: &Args, port: T) -> std::io::Result<()> {
+    let mut client = Client::connect(port)?;
+    if opt.info {
+        let info = client.info();
+        println!("Protocol {}, load area {:#x}..{:#x}, {} bytes per request",
+                 info.protocol, info.load_start, info.load_end, info.max_data);
+    }
+
+    for (addr, path) in &opt.upload {
+        let data = std::fs::read(path)?;
+        let total = data.len();
+        client.upload(*addr, &data, |sent| {
+            println!("Progress: {} {}/{} bytes ({}%)", path.display(), sent, total, sent * 100 / total.max(1));
+        })?;
+        println!("Uploaded {} to {:#x}", path.display(), addr);
+    }
+
+    for &(addr, len) in &opt.crc {
+        println!("CRC-32 of {:#x}..{:#x}: {:#010x}", addr, addr + len, client.crc(addr, len)?);
+    }
+
+    for &(addr, len) in &opt.peek {
+        let memory = client.peek(addr, len)?;
+        for (i, line) in memory.chunks(16).enumerate() {
+            let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+            println!("{:#010x}: {}", addr + 16 * i as u64, hex.join(" "));
+        }
+    }
+
+    if let Some(addr) = opt.go {
+        client.go(addr)?;
+        println!("Jumped to {:#x}", addr);
+    }
+
+    Ok(())
+}
+
+/// Why `ttywrite` failed, for the user.
+#[derive(Debug)]
+struct Failure(String);
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Describes a failed step.
+trait Context<T> {
+    /// Returns the error, if any, as a failure while doing `what`.
+    fn context(self, what: impl std::fmt::Display) -> Result<T, Failure>;
+}
+
+impl<T, E: std::fmt::Display> Context<T> for Result<T, E> {
+    fn context(self, what: impl std::fmt::Display) -> Result<T, Failure> {
+        self.map_err(|e| Failure(format!("{}: {}", what, e)))
+    }
+}
+
+/// Exits with status 1 and a message on failure, including when the
+/// bootloader rejects the image. Invalid arguments exit with status 2.
+fn main() {
+    let mut opt = Args::parse();
+    if let Err(failure) = configure(&mut opt).and_then(|_| run(&opt)) {
+        eprintln!("ttywrite: {}", failure);
+        std::process::exit(1);
+    }
+}
+
+/// Applies the profile `opt` names, or the default one, from the
+/// configuration files.
+fn configure(opt: &mut Args) -> Result<(), Failure> {
+    let config = Config::load(opt.config.as_deref()).map_err(Failure)?;
+    let named = opt.profile.clone();
+    let Some(name) = config.profile_name(named.as_deref()) else {
+        return Ok(());
+    };
+
+    let profile = config.profile(name).map_err(Failure)?;
+    opt.apply_profile(profile).context(format_args!("profile {}", name))
+}
+
+/// Does what `opt` asks.
+fn run(opt: &Args) -> Result<(), Failure> {
+    let Some(target) = &opt.target else {
+        return Err(Failure("no target: give one, or a profile with a target or USB adapter".to_string()));
+    };
+    let mut port = Port::open(target, &opt.line_settings(), opt.read_timeout()).context(format_args!("opening {}", target))?;
+
+    if opt.has_commands() {
+        run_commands(opt, &mut port).context("running bootloader commands")?;
+    } else if let Some(path) = &opt.receive {
+        let received = receive(&mut port, path).context(format_args!("receiving into {}", path.display()))?;
+        eprintln!("Received {} bytes", received);
+    } else if opt.input.is_some() || !opt.terminal {
+        transfer(opt, &mut port)?;
+    }
+
+    match &opt.input {
+        Some(path) if opt.watch => watch(opt, &mut port, path)?,
+        _ if opt.terminal => run_terminal(opt, &mut port).context("terminal mode")?,
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Sends the input, a file or all of stdin, once as `opt` asks.
+///
+/// # Errors
+///
+/// Returns a failure if reading the input or the transfer fails, or if the
+/// bootloader rejects the image.
+fn transfer(opt: &Args, port: &mut Port) -> Result<(), Failure> {
+    use std::fs::File;
+    use std::io::{self, BufReader, Read, Write};
+
+    let (name, mut size, mtime, mut input): (String, _, _, Box<dyn Read>) = match &opt.input {
+        None => ("stdin".to_string(), None, None, Box::new(io::stdin().lock())),
         Some(path) => {
-            let mut input = BufReader::new(File::open(path).expect("file should exist"));
-            if opt.raw {
-                std::io::copy(&mut input, &mut port).expect("valid copy");
-            } else {
-                Xmodem::transmit_with_progress(input, &mut port, progress_fn).expect("valid transmit");
-            }
-        },
+            let file = File::open(path).context(format_args!("opening {}", path.display()))?;
+            let metadata = file.metadata().context(format_args!("reading {}", path.display()))?;
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("input").to_string();
+            let mtime = metadata.modified().ok().and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok());
+            (name, Some(metadata.len()), mtime.map(|t| t.as_secs()), Box::new(BufReader::new(file)))
+        }
+    };
+
+    // ZMODEM sends from memory; a boot image is built there.
+    if opt.boot_header || opt.lz4 || opt.zmodem {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data).context(format_args!("reading {}", name))?;
+        let data = if opt.boot_header || opt.lz4 { prepare(data, opt) } else { data };
+        size = Some(data.len() as u64);
+        input = Box::new(io::Cursor::new(data));
     }
 
-}
\ No newline at end of file
+    let mode = if opt.one_k { Mode::Crc1k } else { Mode::Crc };
+    if opt.raw {
+        io::copy(&mut input, port).context("sending")?;
+        port.flush().context("sending")?;
+    } else if opt.ymodem || opt.zmodem {
+        let mut info = FileInfo::new(&name).context(format_args!("naming {} in the batch", name))?;
+        if let Some(size) = size {
+            info = info.with_size(size);
+        }
+        if let Some(mtime) = mtime {
+            info = info.with_mtime(mtime);
+        }
+
+        if opt.zmodem {
+            let mut data = Vec::new();
+            input.read_to_end(&mut data).context(format_args!("reading {}", name))?;
+            transmit_zmodem(&info, &data, &mut *port).context("ZMODEM transfer")?;
+        } else {
+            transmit_ymodem(&info, input, &mut *port, mode).context("YMODEM transfer")?;
+        }
+    } else {
+        let timeout = opt.read_timeout();
+        let outcome = transmit_xmodem(input, &mut StatusFilter::new(port), mode, size, timeout).context("XMODEM transfer")?;
+        if outcome == Some(Outcome::Failed) {
+            return Err(Failure("the bootloader rejected the image".to_string()));
+        }
+    }
+
+    Ok(())
+}
@@ -1,5 +1,13 @@
+use std::path::PathBuf;
+
+use bootimg::{ed25519, MAX_CMDLINE};
+
 use serial::core::{CharSize, BaudRate, StopBits, FlowControl};
 
+use crate::port::Target;
+use crate::terminal::Newline;
+use crate::watch::Reset;
+
 pub fn parse_width(s: &str) -> Result<CharSize, &'static str> {
     match s {
         "5" => Ok(CharSize::Bits5),
@@ -29,4 +37,100 @@ pub fn parse_flow_control(s: &str) -> Result<FlowControl, &'static str> {
 
 pub fn parse_baud_rate(s: &str) -> Result<BaudRate, ::std::num::ParseIntError> {
     Ok(BaudRate::from_speed(s.parse()?))
-}
\ No newline at end of file
+}
+
+/// Parses a target: `tcp://HOST:PORT`, `rfc2217://HOST:PORT`, or a path to a
+/// TTY device or pty.
+pub fn parse_target(s: &str) -> Result<Target, &'static str> {
+    let host_port = |addr: &str| match addr.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(addr.to_string()),
+        _ => Err("address must be HOST:PORT"),
+    };
+
+    if let Some(addr) = s.strip_prefix("tcp://") {
+        Ok(Target::Tcp(host_port(addr)?))
+    } else if let Some(addr) = s.strip_prefix("rfc2217://") {
+        Ok(Target::Rfc2217(host_port(addr)?))
+    } else {
+        Ok(Target::Device(PathBuf::from(s)))
+    }
+}
+
+/// Parses an address in hex with a `0x` prefix, or in decimal.
+pub fn parse_addr(s: &str) -> Result<u64, ::std::num::ParseIntError> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(&hex.replace('_', ""), 16),
+        None => s.parse(),
+    }
+}
+
+/// Parses `ADDR=FILE`, with `ADDR` as for `parse_addr`.
+pub fn parse_upload(s: &str) -> Result<(u64, PathBuf), String> {
+    let (addr, path) = s.split_once('=').ok_or("value must be ADDR=FILE")?;
+    Ok((parse_addr(addr).map_err(|e| e.to_string())?, PathBuf::from(path)))
+}
+
+/// Parses `ADDR:LEN`, both as for `parse_addr`.
+pub fn parse_range(s: &str) -> Result<(u64, u64), String> {
+    let (addr, len) = s.split_once(':').ok_or("value must be ADDR:LEN")?;
+    Ok((parse_addr(addr).map_err(|e| e.to_string())?, parse_addr(len).map_err(|e| e.to_string())?))
+}
+
+/// Parses a key: `^X` for a control key, or a single ASCII character.
+pub fn parse_escape(s: &str) -> Result<u8, &'static str> {
+    match s.as_bytes() {
+        [b'^', key @ b'@'..=b'_'] => Ok(key ^ 0x40),
+        [b'^', key @ b'a'..=b'z'] => Ok(key.to_ascii_uppercase() ^ 0x40),
+        [key] if key.is_ascii() => Ok(*key),
+        _ => Err("value must be '^X' or a single ASCII character")
+    }
+}
+
+pub fn parse_newline(s: &str) -> Result<Newline, &'static str> {
+    match s {
+        "cr" => Ok(Newline::Cr),
+        "lf" => Ok(Newline::Lf),
+        "crlf" => Ok(Newline::CrLf),
+        _ => Err("value must be 'cr', 'lf' or 'crlf'")
+    }
+}
+
+pub fn parse_reset(s: &str) -> Result<Reset, &'static str> {
+    match s {
+        "magic" => Ok(Reset::Magic),
+        "dtr" => Ok(Reset::Dtr),
+        "rts" => Ok(Reset::Rts),
+        _ => Err("value must be 'magic', 'dtr' or 'rts'")
+    }
+}
+
+/// Checks a kernel command line: at most `MAX_CMDLINE` bytes, without NULs.
+pub fn parse_cmdline(s: &str) -> Result<String, String> {
+    if s.len() > MAX_CMDLINE {
+        return Err(format!("command line longer than {} bytes", MAX_CMDLINE));
+    }
+    if s.contains('\0') {
+        return Err("command line contains a NUL".to_string());
+    }
+
+    Ok(s.to_string())
+}
+
+/// DER prefix of an Ed25519 private key in PKCS #8, as written by
+/// `openssl genpkey -algorithm ed25519 -outform DER`.
+const PKCS8_PREFIX: [u8; 16] = [
+    0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+
+/// Reads the Ed25519 secret key in the file at `s`, either the raw 32-byte
+/// seed or DER.
+pub fn parse_secret_key(s: &str) -> Result<[u8; ed25519::SECRET_KEY_LEN], String> {
+    let file = std::fs::read(s).map_err(|e| format!("reading {}: {}", s, e))?;
+    let key = match (file.len(), file.strip_prefix(&PKCS8_PREFIX[..])) {
+        (32, _) => &file[..],
+        (48, Some(key)) => key,
+        _ => return Err(format!("{} is neither a raw nor a DER Ed25519 private key", s)),
+    };
+
+    Ok(key.try_into().expect("32-byte key"))
+}
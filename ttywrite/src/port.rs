@@ -0,0 +1,214 @@
+//! What `ttywrite` talks to: a TTY, a pty, or a serial port over TCP.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serial::core::{BaudRate, CharSize, FlowControl, SerialDevice, SerialPortSettings, StopBits};
+use serial::{SerialPort, SystemPort};
+
+use crate::rfc2217::Rfc2217;
+
+/// Where to connect, as given on the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// A TTY device or pty at this path.
+    Device(PathBuf),
+    /// A raw TCP connection to `HOST:PORT`, like QEMU's `-serial tcp:...`.
+    Tcp(String),
+    /// A Telnet connection to `HOST:PORT` whose line is set with RFC 2217.
+    Rfc2217(String),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::Device(path) => write!(f, "{}", path.display()),
+            Target::Tcp(addr) => write!(f, "tcp://{}", addr),
+            Target::Rfc2217(addr) => write!(f, "rfc2217://{}", addr),
+        }
+    }
+}
+
+/// Line settings for a serial port.
+#[derive(Debug, Copy, Clone)]
+pub struct LineSettings {
+    pub baud_rate: BaudRate,
+    pub char_size: CharSize,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+}
+
+/// A modem control line.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Line {
+    Dtr,
+    Rts,
+}
+
+/// An open connection to a `Target`.
+pub enum Port {
+    /// A TTY device or pty.
+    Device(SystemPort),
+    /// A raw TCP connection.
+    Tcp(TcpStream),
+    /// A Telnet connection with RFC 2217.
+    Rfc2217(Rfc2217<TcpStream>),
+}
+
+/// Returns `true` if `path` names a pseudo-terminal, whose line settings mean
+/// nothing.
+fn is_pty(path: &Path) -> bool {
+    path.canonicalize().is_ok_and(|path| path.starts_with("/dev/pts"))
+}
+
+/// Connects to `addr` over TCP, sending each write straight away.
+fn connect(addr: &str) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect(addr)?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+impl Port {
+    /// Opens `target`, applying `settings` where they mean something: to a
+    /// TTY device, or through an RFC 2217 server. Reads time out after
+    /// `timeout`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if opening, connecting to or configuring the target
+    /// fails.
+    pub fn open(target: &Target, settings: &LineSettings, timeout: Duration) -> io::Result<Port> {
+        let mut port = match target {
+            Target::Device(path) => {
+                let mut port = serial::open(path)?;
+                if !is_pty(path) {
+                    let mut line = port.read_settings()?;
+                    line.set_baud_rate(settings.baud_rate)?;
+                    line.set_char_size(settings.char_size);
+                    line.set_stop_bits(settings.stop_bits);
+                    line.set_flow_control(settings.flow_control);
+                    port.write_settings(&line)?;
+                }
+                Port::Device(port)
+            }
+            Target::Tcp(addr) => Port::Tcp(connect(addr)?),
+            Target::Rfc2217(addr) => Port::Rfc2217(Rfc2217::new(connect(addr)?, settings)?),
+        };
+
+        port.set_timeout(timeout)?;
+        Ok(port)
+    }
+
+    /// Sets how long reads wait for a byte before failing with
+    /// `io::ErrorKind::TimedOut`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the timeout can't be set, such as a zero timeout
+    /// on a TCP connection.
+    pub fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        match self {
+            Port::Device(port) => Ok(SerialPort::set_timeout(port, timeout)?),
+            Port::Tcp(stream) => stream.set_read_timeout(Some(timeout)),
+            Port::Rfc2217(telnet) => telnet.get_ref().set_read_timeout(Some(timeout)),
+        }
+    }
+
+    /// Asserts `line` if `level` is `true`, or releases it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the line can't be set, as over raw TCP.
+    pub fn set_line(&mut self, line: Line, level: bool) -> io::Result<()> {
+        match (self, line) {
+            (Port::Device(port), Line::Dtr) => Ok(SerialPort::set_dtr(port, level)?),
+            (Port::Device(port), Line::Rts) => Ok(SerialPort::set_rts(port, level)?),
+            (Port::Tcp(_), _) => Err(io::Error::new(io::ErrorKind::Unsupported, "raw TCP has no modem control lines")),
+            (Port::Rfc2217(telnet), Line::Dtr) => telnet.set_dtr(level),
+            (Port::Rfc2217(telnet), Line::Rts) => telnet.set_rts(level),
+        }
+    }
+
+    /// Drops whatever was received but not yet read.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if flushing the device or reading the connection
+    /// fails.
+    pub fn discard_input(&mut self) -> io::Result<()> {
+        if let Port::Device(port) = self {
+            return termios::tcflush(port.as_raw_fd(), termios::TCIFLUSH);
+        }
+
+        self.set_nonblocking(true)?;
+        let mut buf = [0u8; 1024];
+        let drained = loop {
+            match self.read(&mut buf) {
+                Ok(0) => break Ok(()),
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+
+        self.set_nonblocking(false)?;
+        drained
+    }
+
+    /// Makes reads on a connection fail with `WouldBlock` instead of waiting.
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Port::Device(_) => Ok(()),
+            Port::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            Port::Rfc2217(telnet) => telnet.get_ref().set_nonblocking(nonblocking),
+        }
+    }
+}
+
+impl Read for Port {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = match self {
+            Port::Device(port) => port.read(buf),
+            Port::Tcp(stream) => stream.read(buf),
+            Port::Rfc2217(telnet) => telnet.read(buf),
+        };
+
+        // A socket's read timeout fails as `WouldBlock` on Unix.
+        read.map_err(|e| match e.kind() {
+            io::ErrorKind::WouldBlock => io::ErrorKind::TimedOut.into(),
+            _ => e,
+        })
+    }
+}
+
+impl Write for Port {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Port::Device(port) => port.write(buf),
+            Port::Tcp(stream) => stream.write(buf),
+            Port::Rfc2217(telnet) => telnet.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Port::Device(port) => port.flush(),
+            Port::Tcp(stream) => stream.flush(),
+            Port::Rfc2217(telnet) => telnet.flush(),
+        }
+    }
+}
+
+impl AsRawFd for Port {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Port::Device(port) => port.as_raw_fd(),
+            Port::Tcp(stream) => stream.as_raw_fd(),
+            Port::Rfc2217(telnet) => telnet.get_ref().as_raw_fd(),
+        }
+    }
+}
@@ -0,0 +1,355 @@
+//! A serial port behind a Telnet server with RFC 2217 COM port control, as
+//! offered by `ser2net` and `socat`-style stand-ins.
+
+use std::io::{self, Read, Write};
+
+use serial::core::{CharSize, FlowControl, StopBits};
+
+use crate::port::LineSettings;
+
+/// Telnet commands.
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+/// Telnet options: 8-bit data, no go-aheads and COM port control.
+const BINARY: u8 = 0;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+const COM_PORT_OPTION: u8 = 44;
+
+/// COM port control commands, from client to server.
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+
+/// `SET_PARITY` value for no parity; `ttywrite` has no parity setting.
+const PARITY_NONE: u8 = 1;
+
+/// `SET_CONTROL` values that set the DTR and RTS lines.
+const DTR_ON: u8 = 8;
+const DTR_OFF: u8 = 9;
+const RTS_ON: u8 = 11;
+const RTS_OFF: u8 = 12;
+
+/// Where the decoder is in the stream from the server.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Data,
+    Iac,
+    /// After `IAC` and this `WILL`, `WONT`, `DO` or `DONT`.
+    Negotiation(u8),
+    /// In a subnegotiation, whose content is ignored.
+    Sub,
+    SubIac,
+}
+
+/// A Telnet connection to a serial port, carrying its data and setting its
+/// line with RFC 2217. Reads take the Telnet commands out of the data and
+/// answer them; writes escape `IAC`.
+pub struct Rfc2217<S> {
+    inner: S,
+    state: State,
+    /// Options this side has agreed to perform, as `bit`s, so each request
+    /// is only answered once.
+    ours: u64,
+    /// Options the server has been asked or agreed to perform, as `bit`s.
+    theirs: u64,
+    /// Options this side has refused to perform, and the server has been
+    /// refused, as `bit`s, so each refusal is only sent once.
+    ours_refused: u64,
+    theirs_refused: u64,
+}
+
+/// Returns `option`'s bit in a set of options. The options past 63 have none:
+/// they are never agreed to, and refused every time they're asked for.
+fn bit(option: u8) -> u64 {
+    1u64.checked_shl(option as u32).unwrap_or(0)
+}
+
+impl<S: Read + Write> Rfc2217<S> {
+    /// Negotiates a binary connection with COM port control on `inner` and
+    /// sets the server's line to `settings`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to `inner` fails.
+    pub fn new(inner: S, settings: &LineSettings) -> io::Result<Rfc2217<S>> {
+        let mut telnet = Rfc2217 { inner, state: State::Data, ours: 0, theirs: 0, ours_refused: 0, theirs_refused: 0 };
+        for option in [BINARY, SUPPRESS_GO_AHEAD, COM_PORT_OPTION] {
+            telnet.ours |= bit(option);
+            telnet.inner.write_all(&[IAC, WILL, option])?;
+        }
+        for option in [BINARY, SUPPRESS_GO_AHEAD] {
+            telnet.theirs |= bit(option);
+            telnet.inner.write_all(&[IAC, DO, option])?;
+        }
+
+        // Sent without waiting for the server to agree to COM port control;
+        // one that refuses it ignores them.
+        let baud_rate = (settings.baud_rate.speed() as u32).to_be_bytes();
+        telnet.command(SET_BAUDRATE, &baud_rate)?;
+        telnet.command(SET_DATASIZE, &[match settings.char_size {
+            CharSize::Bits5 => 5,
+            CharSize::Bits6 => 6,
+            CharSize::Bits7 => 7,
+            CharSize::Bits8 => 8,
+        }])?;
+        telnet.command(SET_PARITY, &[PARITY_NONE])?;
+        telnet.command(SET_STOPSIZE, &[match settings.stop_bits {
+            StopBits::Stop1 => 1,
+            StopBits::Stop2 => 2,
+        }])?;
+        telnet.command(SET_CONTROL, &[match settings.flow_control {
+            FlowControl::FlowNone => 1,
+            FlowControl::FlowSoftware => 2,
+            FlowControl::FlowHardware => 3,
+        }])?;
+        telnet.inner.flush()?;
+        Ok(telnet)
+    }
+
+    /// Asserts the server's DTR line if `level` is `true`, or releases it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the server fails.
+    pub fn set_dtr(&mut self, level: bool) -> io::Result<()> {
+        self.command(SET_CONTROL, &[if level { DTR_ON } else { DTR_OFF }])?;
+        self.inner.flush()
+    }
+
+    /// Asserts the server's RTS line if `level` is `true`, or releases it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the server fails.
+    pub fn set_rts(&mut self, level: bool) -> io::Result<()> {
+        self.command(SET_CONTROL, &[if level { RTS_ON } else { RTS_OFF }])?;
+        self.inner.flush()
+    }
+
+    /// Sends the COM port control `command` with `value`.
+    fn command(&mut self, command: u8, value: &[u8]) -> io::Result<()> {
+        let mut sub = vec![IAC, SB, COM_PORT_OPTION, command];
+        escape(value, &mut sub);
+        sub.extend_from_slice(&[IAC, SE]);
+        self.inner.write_all(&sub)
+    }
+}
+
+impl<S> Rfc2217<S> {
+    /// Returns the connection to the server.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Decodes `byte` from the server. Returns it if it is data; queues any
+    /// answer to a Telnet command in `replies`.
+    fn decode(&mut self, byte: u8, replies: &mut Vec<u8>) -> Option<u8> {
+        let (state, data) = match (self.state, byte) {
+            (State::Data, IAC) => (State::Iac, None),
+            (State::Data, byte) => (State::Data, Some(byte)),
+            (State::Iac, IAC) => (State::Data, Some(IAC)),
+            (State::Iac, WILL | WONT | DO | DONT) => (State::Negotiation(byte), None),
+            (State::Iac, SB) => (State::Sub, None),
+            (State::Iac, _) => (State::Data, None),
+            (State::Negotiation(command), option) => {
+                self.negotiate(command, option, replies);
+                (State::Data, None)
+            }
+            (State::Sub, IAC) => (State::SubIac, None),
+            (State::Sub, _) => (State::Sub, None),
+            (State::SubIac, IAC) => (State::Sub, None),
+            (State::SubIac, _) => (State::Data, None),
+        };
+
+        self.state = state;
+        data
+    }
+
+    /// Answers the server's `command` about `option`: agrees to the options
+    /// this side asked for, refuses the rest, and acknowledges nothing twice.
+    fn negotiate(&mut self, command: u8, option: u8, replies: &mut Vec<u8>) {
+        let ours = self.ours & bit(option) != 0;
+        let theirs = self.theirs & bit(option) != 0;
+        let reply = match command {
+            DO if !matches!(option, BINARY | SUPPRESS_GO_AHEAD | COM_PORT_OPTION) => {
+                let refused = self.ours_refused & bit(option) != 0;
+                self.ours_refused |= bit(option);
+                (!refused).then_some(WONT)
+            }
+            DO if !ours => Some(WILL),
+            DONT if ours => {
+                if option == COM_PORT_OPTION {
+                    eprintln!("RFC 2217 server refused COM port control, line settings not applied");
+                }
+                Some(WONT)
+            }
+            WILL if !matches!(option, BINARY | SUPPRESS_GO_AHEAD) => {
+                let refused = self.theirs_refused & bit(option) != 0;
+                self.theirs_refused |= bit(option);
+                (!refused).then_some(DONT)
+            }
+            WILL if !theirs => Some(DO),
+            WONT if theirs => Some(DONT),
+            _ => None,
+        };
+
+        if let Some(reply) = reply {
+            match reply {
+                WILL => self.ours |= bit(option),
+                WONT => self.ours &= !bit(option),
+                DO => self.theirs |= bit(option),
+                _ => self.theirs &= !bit(option),
+            }
+            replies.extend_from_slice(&[IAC, reply, option]);
+        }
+    }
+}
+
+/// Appends `data` to `out`, doubling each `IAC`.
+fn escape(data: &[u8], out: &mut Vec<u8>) {
+    for &byte in data {
+        out.push(byte);
+        if byte == IAC {
+            out.push(IAC);
+        }
+    }
+}
+
+impl<S: Read + Write> Read for Rfc2217<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // Decodes in place, which never gives more bytes than were read; reads
+        // again if all that came were commands.
+        loop {
+            let n = self.inner.read(buf)?;
+            if n == 0 {
+                return Ok(0);
+            }
+
+            let mut replies = Vec::new();
+            let mut len = 0;
+            for i in 0..n {
+                if let Some(byte) = self.decode(buf[i], &mut replies) {
+                    buf[len] = byte;
+                    len += 1;
+                }
+            }
+
+            self.inner.write_all(&replies)?;
+            if len > 0 {
+                return Ok(len);
+            }
+        }
+    }
+}
+
+impl<S: Write> Write for Rfc2217<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut escaped = Vec::with_capacity(buf.len());
+        escape(buf, &mut escaped);
+        self.inner.write_all(&escaped)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial::core::BaudRate;
+    use std::io::Cursor;
+
+    /// A server that sends `input` and keeps what it's sent.
+    struct Server {
+        input: Cursor<Vec<u8>>,
+        sent: Vec<u8>,
+    }
+
+    impl Read for Server {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Server {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sent.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Connects to a server that will send `input`, forgetting the
+    /// negotiation sent to it.
+    fn connect(input: &[u8]) -> Rfc2217<Server> {
+        let settings = LineSettings {
+            baud_rate: BaudRate::Baud115200,
+            char_size: CharSize::Bits8,
+            stop_bits: StopBits::Stop1,
+            flow_control: FlowControl::FlowNone,
+        };
+
+        let server = Server { input: Cursor::new(input.to_vec()), sent: vec![] };
+        let mut telnet = Rfc2217::new(server, &settings).expect("negotiation sent");
+        telnet.inner.sent.clear();
+        telnet
+    }
+
+    /// Reads everything `telnet` decodes.
+    fn read_all(telnet: &mut Rfc2217<Server>) -> Vec<u8> {
+        let mut data = vec![];
+        telnet.read_to_end(&mut data).expect("read okay");
+        data
+    }
+
+    #[test]
+    fn test_escaped_iac() {
+        let mut telnet = connect(&[b'a', IAC, IAC, b'b']);
+        assert_eq!(read_all(&mut telnet), [b'a', IAC, b'b']);
+        assert!(telnet.inner.sent.is_empty());
+    }
+
+    #[test]
+    fn test_subnegotiation_dropped() {
+        let mut telnet = connect(&[1, IAC, SB, COM_PORT_OPTION, 101, 0, IAC, IAC, 0, IAC, SE, 2]);
+        assert_eq!(read_all(&mut telnet), [1, 2]);
+        assert!(telnet.inner.sent.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_options_refused_once() {
+        let mut telnet = connect(&[IAC, DO, 24, IAC, DO, 24, IAC, WILL, 24, IAC, WILL, 24, b'x']);
+        assert_eq!(read_all(&mut telnet), [b'x']);
+        assert_eq!(telnet.inner.sent, [IAC, WONT, 24, IAC, DONT, 24]);
+    }
+
+    #[test]
+    fn test_agreed_options_not_answered() {
+        let mut telnet = connect(&[IAC, DO, BINARY, IAC, WILL, SUPPRESS_GO_AHEAD, IAC, DO, COM_PORT_OPTION]);
+        assert!(read_all(&mut telnet).is_empty());
+        assert!(telnet.inner.sent.is_empty());
+    }
+
+    #[test]
+    fn test_write_escapes_iac() {
+        let mut telnet = connect(&[]);
+        assert_eq!(telnet.write(&[1, IAC, 2]).expect("write okay"), 3);
+        assert_eq!(telnet.inner.sent, [1, IAC, IAC, 2]);
+    }
+}
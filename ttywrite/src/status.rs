@@ -0,0 +1,190 @@
+//! The bootloader's status lines; see `bootimg::status`.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+use bootimg::status::{self, Event, PREFIX};
+
+/// Longest line taken for a status line; anything longer is passed on.
+const MAX_LINE: usize = 256;
+
+/// Wraps the port to a bootloader, taking its status lines out of what's
+/// read and printing them, so that an XMODEM sender only sees the
+/// receiver's protocol bytes.
+pub struct StatusFilter<T> {
+    inner: T,
+    /// A line being read that may still turn out to be a status line.
+    line: Vec<u8>,
+    /// Bytes for the reader, in order.
+    pending: VecDeque<u8>,
+    /// Whether any status line was read.
+    seen: bool,
+    /// How the current boot attempt ended, if it has.
+    outcome: Option<Outcome>,
+    /// Whether the bootloader said it is waiting for an upload and hasn't
+    /// reported anything since.
+    receiving: bool,
+}
+
+/// How a boot attempt ended.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The bootloader jumped to the image.
+    Jumped,
+    /// The transfer failed or the image was rejected.
+    Failed,
+}
+
+impl<T> StatusFilter<T> {
+    /// Returns a filter reading from and writing to `inner`.
+    pub fn new(inner: T) -> StatusFilter<T> {
+        StatusFilter { inner, line: Vec::new(), pending: VecDeque::new(), seen: false, outcome: None, receiving: false }
+    }
+
+    /// Returns `true` if the other side wrote any status line, and so is the
+    /// bootloader.
+    pub fn seen_status(&self) -> bool {
+        self.seen
+    }
+
+    /// Returns the wrapped port.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Sorts a byte read from the port into a status line or the bytes for
+    /// the reader.
+    fn push(&mut self, byte: u8) {
+        if self.line.is_empty() && byte != PREFIX.as_bytes()[0] {
+            self.pending.push_back(byte);
+            return;
+        }
+
+        self.line.push(byte);
+        let prefix = &PREFIX.as_bytes()[..self.line.len().min(PREFIX.len())];
+        if !self.line.starts_with(prefix) || self.line.len() > MAX_LINE {
+            self.pending.extend(self.line.drain(..));
+        } else if byte == b'\n' {
+            let line = String::from_utf8_lossy(&self.line).into_owned();
+            self.line.clear();
+            self.print(line.trim_end());
+        }
+    }
+
+    /// Prints the status line `line`.
+    fn print(&mut self, line: &str) {
+        self.seen = true;
+        let Some(event) = status::parse(line) else {
+            println!("Bootloader: {}", &line[PREFIX.len()..]);
+            return;
+        };
+
+        self.outcome = match event {
+            Event::Ready { .. } | Event::Receiving { .. } => None,
+            Event::Jumping { .. } => Some(Outcome::Jumped),
+            Event::Error { .. } => Some(Outcome::Failed),
+            _ => self.outcome,
+        };
+        self.receiving = matches!(event, Event::Receiving { .. });
+
+        match event {
+            Event::Ready { load_start, load_end } => {
+                println!("Bootloader ready, loading images to {:#x}..{:#x}", load_start, load_end)
+            }
+            Event::Receiving { wait } => println!("Bootloader waiting {}s for an upload", wait),
+            Event::Received { bytes } => println!("Bootloader received {} bytes", bytes),
+            Event::Error { code, kind, message } => println!("Bootloader error {} ({}): {}", code, kind, message),
+            Event::NotKept { message } => println!("Bootloader can't keep the image for fallback: {}", message),
+            Event::Fallback { failures } => {
+                println!("Bootloader booting its fallback image, {} failed boots so far", failures)
+            }
+            Event::FallbackFailed { failures } => {
+                println!("Bootloader's fallback image failed {} boots, it needs an upload", failures)
+            }
+            Event::Jumping { addr } => println!("Bootloader jumping to {:#x}", addr),
+        }
+    }
+}
+
+impl<T: Read> StatusFilter<T> {
+    /// Reads and prints status lines until the first one, if none was read
+    /// yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading fails, including if the port times out
+    /// first.
+    pub fn wait_for_status(&mut self) -> io::Result<()> {
+        self.read_until(|filter| filter.seen)
+    }
+
+    /// Reads and prints status lines until the bootloader says it is waiting
+    /// for an upload, such as after a reset, dropping everything before.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading fails, including if the port times out
+    /// first.
+    pub fn wait_for_receiving(&mut self) -> io::Result<()> {
+        self.read_until(|filter| filter.receiving)?;
+        // What came before is the old image's output, not the receiver's.
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Reads and prints status lines until the bootloader jumps to the image
+    /// or reports an error, and returns which.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading fails, including if the port times out
+    /// first.
+    pub fn wait_for_outcome(&mut self) -> io::Result<Outcome> {
+        self.read_until(|filter| filter.outcome.is_some())?;
+        Ok(self.outcome.expect("outcome"))
+    }
+
+    /// Reads from the port until `done` holds, leaving whatever follows
+    /// unread.
+    fn read_until(&mut self, done: impl Fn(&Self) -> bool) -> io::Result<()> {
+        let mut byte = [0u8; 1];
+        while !done(self) {
+            if self.inner.read(&mut byte)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.push(byte[0]);
+        }
+        Ok(())
+    }
+}
+
+impl<T: Read> Read for StatusFilter<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut received = [0u8; 64];
+        while self.pending.is_empty() && !buf.is_empty() {
+            let n = self.inner.read(&mut received)?;
+            if n == 0 {
+                // Whatever was held back isn't a status line after all.
+                self.pending.extend(self.line.drain(..));
+                break;
+            }
+            received[..n].iter().for_each(|&byte| self.push(byte));
+        }
+
+        let n = buf.len().min(self.pending.len());
+        for (slot, byte) in buf.iter_mut().zip(self.pending.drain(..n)) {
+            *slot = byte;
+        }
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for StatusFilter<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,262 @@
+//! Terminal mode: bridges stdin and stdout to the TTY, like `screen`.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use termios::Termios;
+
+/// What ends a line: what Enter sends, or what the other side sends.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Newline {
+    /// `\r`, as typed in a raw terminal.
+    Cr,
+    /// `\n`.
+    Lf,
+    /// `\r\n`, as the Pi's UART writes.
+    CrLf,
+}
+
+impl Newline {
+    fn as_bytes(self) -> &'static [u8] {
+        match self {
+            Newline::Cr => b"\r",
+            Newline::Lf => b"\n",
+            Newline::CrLf => b"\r\n",
+        }
+    }
+}
+
+/// How a terminal session behaves.
+#[derive(Debug)]
+pub struct Settings {
+    /// Key that starts a command to the terminal instead of being sent.
+    pub escape: u8,
+    /// Whether typed bytes are printed locally as well as sent.
+    pub echo: bool,
+    /// What Enter sends.
+    pub send_newline: Newline,
+    /// What the other side ends lines with, to be printed as `\r\n`.
+    pub recv_newline: Newline,
+    /// File the session's output is appended to.
+    pub log: Option<File>,
+}
+
+/// Returns how to write byte `byte` as typed, `^X` for a control key.
+pub fn key_name(byte: u8) -> String {
+    match byte {
+        0..=0x1F => format!("^{}", (byte ^ 0x40) as char),
+        0x7F => "^?".to_string(),
+        _ => (byte as char).to_string(),
+    }
+}
+
+/// Puts the terminal on `fd` in raw mode, if it is one, until dropped.
+struct RawMode {
+    fd: RawFd,
+    saved: Option<Termios>,
+}
+
+impl RawMode {
+    fn enter(fd: RawFd) -> io::Result<RawMode> {
+        if unsafe { libc::isatty(fd) } == 0 {
+            return Ok(RawMode { fd, saved: None });
+        }
+
+        let saved = Termios::from_fd(fd)?;
+        let mut raw = saved;
+        termios::cfmakeraw(&mut raw);
+        termios::tcsetattr(fd, termios::TCSANOW, &raw)?;
+        Ok(RawMode { fd, saved: Some(saved) })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if let Some(saved) = &self.saved {
+            let _ = termios::tcsetattr(self.fd, termios::TCSANOW, saved);
+        }
+    }
+}
+
+/// A terminal session on a port.
+struct Session<'a, T> {
+    port: &'a mut T,
+    settings: &'a mut Settings,
+    stdout: io::Stdout,
+    /// Whether the last byte typed was the escape key.
+    escaped: bool,
+    /// Whether the last byte received was a `\r` that may start a `\r\n`.
+    after_cr: bool,
+}
+
+impl<T: Read + Write> Session<'_, T> {
+    /// Prints `bytes` and logs them.
+    fn output(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.stdout.write_all(bytes)?;
+        self.stdout.flush()?;
+        if let Some(log) = &mut self.settings.log {
+            log.write_all(bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Prints a message from the terminal itself, on a line of its own.
+    fn notice(&self, message: &str) {
+        eprint!("\r\n[{}]\r\n", message);
+    }
+
+    /// Prints `received`, with the other side's line endings as `\r\n`.
+    fn received(&mut self, received: &[u8]) -> io::Result<()> {
+        let mut out = Vec::with_capacity(received.len() * 2);
+        for &byte in received {
+            match (self.settings.recv_newline, byte) {
+                (Newline::Lf, b'\n') => out.extend_from_slice(b"\r\n"),
+                (Newline::Cr, b'\r') => out.extend_from_slice(b"\r\n"),
+                // Swallow the `\n` of a `\r\n` already ended.
+                (Newline::Cr, b'\n') if self.after_cr => {}
+                _ => out.push(byte),
+            }
+            self.after_cr = byte == b'\r';
+        }
+        self.output(&out)
+    }
+
+    /// Sends or acts on `typed`. Returns `false` once asked to quit.
+    fn typed(&mut self, typed: &[u8]) -> io::Result<bool> {
+        let mut send = Vec::with_capacity(typed.len());
+        let mut echo = Vec::with_capacity(typed.len());
+        for &byte in typed {
+            if self.escaped {
+                self.escaped = false;
+                match byte {
+                    b'q' | b'.' => {
+                        self.port.write_all(&send)?;
+                        return Ok(false);
+                    }
+                    b'e' => {
+                        self.settings.echo = !self.settings.echo;
+                        let state = if self.settings.echo { "on" } else { "off" };
+                        self.notice(&format!("local echo {}", state));
+                    }
+                    b'?' | b'h' => self.help(),
+                    b if b == self.settings.escape => {
+                        send.push(b);
+                        echo.push(b);
+                    }
+                    _ => {}
+                }
+            } else if byte == self.settings.escape {
+                self.escaped = true;
+            } else if byte == b'\r' || byte == b'\n' {
+                send.extend_from_slice(self.settings.send_newline.as_bytes());
+                echo.extend_from_slice(b"\r\n");
+            } else {
+                send.push(byte);
+                echo.push(byte);
+            }
+        }
+
+        self.port.write_all(&send)?;
+        if self.settings.echo {
+            self.output(&echo)?;
+        }
+        Ok(true)
+    }
+
+    /// Prints what can follow the escape key.
+    fn help(&self) {
+        let escape = key_name(self.settings.escape);
+        self.notice(&format!(
+            "{0} q: quit, {0} e: toggle local echo, {0} {0}: send {0}, {0} ?: this help",
+            escape
+        ));
+    }
+}
+
+/// Reads from the file descriptor `fd` straight away, unlike `io::Stdin`,
+/// which may keep bytes `poll` doesn't know about.
+fn read_fd(fd: RawFd, buf: &mut [u8]) -> io::Result<usize> {
+    match unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) } {
+        n if n < 0 => Err(io::Error::last_os_error()),
+        n => Ok(n as usize),
+    }
+}
+
+/// How often `run_until` checks whether to stop when nothing happens.
+const STOP_INTERVAL: libc::c_int = 200;
+
+/// Why a terminal session ended.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Ended {
+    /// The escape key and `q` were typed.
+    Quit,
+    /// The port closed.
+    Closed,
+    /// The caller asked to stop.
+    Stopped,
+}
+
+/// Bridges stdin and stdout to `port` until the escape key and `q`, or until
+/// the port closes. Stdin closing only stops what's sent.
+///
+/// # Errors
+///
+/// Returns an error if reading or writing either side, or the log, fails.
+pub fn run<T: Read + Write + AsRawFd>(port: &mut T, settings: &mut Settings) -> io::Result<()> {
+    run_until(port, settings, || false).map(|_| ())
+}
+
+/// Like `run`, but also stops as soon as `stop` returns `true`, which it is
+/// asked at least every `STOP_INTERVAL` milliseconds. Returns why the session
+/// ended.
+///
+/// # Errors
+///
+/// Returns an error if reading or writing either side, or the log, fails.
+pub fn run_until<T, F>(port: &mut T, settings: &mut Settings, mut stop: F) -> io::Result<Ended>
+    where T: Read + Write + AsRawFd, F: FnMut() -> bool
+{
+    let stdin_fd = io::stdin().as_raw_fd();
+    let port_fd = port.as_raw_fd();
+    let escape = key_name(settings.escape);
+
+    eprintln!("Terminal mode: {} q to quit, {} ? for help", escape, escape);
+    let _raw = RawMode::enter(stdin_fd)?;
+    let mut session = Session { port, settings, stdout: io::stdout(), escaped: false, after_cr: false };
+    let mut stdin_open = true;
+    let mut buf = [0u8; 1024];
+    loop {
+        if stop() {
+            return Ok(Ended::Stopped);
+        }
+
+        let mut fds = [
+            libc::pollfd { fd: port_fd, events: libc::POLLIN, revents: 0 },
+            libc::pollfd { fd: if stdin_open { stdin_fd } else { -1 }, events: libc::POLLIN, revents: 0 },
+        ];
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, STOP_INTERVAL) } < 0 {
+            match io::Error::last_os_error() {
+                e if e.kind() == io::ErrorKind::Interrupted => continue,
+                e => return Err(e),
+            }
+        }
+
+        if fds[0].revents & (libc::POLLIN | libc::POLLHUP | libc::POLLERR) != 0 {
+            match session.port.read(&mut buf) {
+                Ok(0) => return Ok(Ended::Closed),
+                Ok(n) => session.received(&buf[..n])?,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        if fds[1].revents & (libc::POLLIN | libc::POLLHUP | libc::POLLERR) != 0 {
+            match read_fd(stdin_fd, &mut buf)? {
+                0 => stdin_open = false,
+                n if !session.typed(&buf[..n])? => return Ok(Ended::Quit),
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Finding a USB serial adapter's TTY through sysfs.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+/// Where Linux lists TTY devices.
+const SYS_CLASS_TTY: &str = "/sys/class/tty";
+
+/// Which USB device to use: a vendor and product ID, in hex, and optionally
+/// the device's serial number.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UsbMatch {
+    pub vid: String,
+    pub pid: String,
+    pub serial: Option<String>,
+}
+
+impl fmt::Display for UsbMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.vid, self.pid)?;
+        if let Some(serial) = &self.serial {
+            write!(f, " serial {}", serial)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the sysfs attribute file `name` in `dir`, without its newline.
+fn attribute(dir: &Path, name: &str) -> Option<String> {
+    Some(std::fs::read_to_string(dir.join(name)).ok()?.trim_end().to_string())
+}
+
+/// Returns `true` if the USB device whose sysfs directory is `dir` is the one
+/// `usb` describes.
+fn matches(dir: &Path, usb: &UsbMatch) -> bool {
+    let hex_eq = |name, want: &str| {
+        attribute(dir, name).is_some_and(|id| id.eq_ignore_ascii_case(want.trim_start_matches("0x")))
+    };
+
+    hex_eq("idVendor", &usb.vid)
+        && hex_eq("idProduct", &usb.pid)
+        && usb.serial.as_ref().is_none_or(|serial| attribute(dir, "serial").as_ref() == Some(serial))
+}
+
+/// Returns the path of the TTY device of the one USB serial adapter `usb`
+/// describes, such as `/dev/ttyUSB0`.
+///
+/// # Errors
+///
+/// Returns an error if sysfs can't be read, or if no adapter or more than one
+/// matches.
+pub fn find(usb: &UsbMatch) -> io::Result<PathBuf> {
+    let mut found = Vec::new();
+    for entry in std::fs::read_dir(SYS_CLASS_TTY)? {
+        let entry = entry?;
+        let Ok(device) = entry.path().join("device").canonicalize() else {
+            continue;
+        };
+
+        // The TTY hangs off a USB interface, whose parent is the device.
+        let usb_device = device.ancestors().find(|dir| dir.join("idVendor").is_file());
+        if usb_device.is_some_and(|dir| matches(dir, usb)) {
+            found.push(Path::new("/dev").join(entry.file_name()));
+        }
+    }
+
+    found.sort();
+    match found.len() {
+        0 => Err(io::Error::new(io::ErrorKind::NotFound, format!("no USB serial device {} is plugged in", usb))),
+        1 => Ok(found.remove(0)),
+        _ => {
+            let paths: Vec<String> = found.iter().map(|path| path.display().to_string()).collect();
+            let message = format!("USB serial devices {} all match {}; give a serial number or the path", paths.join(", "), usb);
+            Err(io::Error::other(message))
+        }
+    }
+}
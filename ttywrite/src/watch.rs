@@ -0,0 +1,90 @@
+//! Watch mode: noticing that the image changed and restarting the board to
+//! load it again.
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use bootimg::proto::RESET;
+
+use crate::port::{Line, Port};
+
+/// How long a reset line is held.
+const RESET_PULSE: Duration = Duration::from_millis(100);
+
+/// How long a changed file must stay the same to be taken as written.
+const SETTLE_TIME: Duration = Duration::from_millis(300);
+
+/// How to restart the board.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Reset {
+    /// Send `bootimg::proto::RESET`, which the kernel and bootloader watch for.
+    Magic,
+    /// Pulse DTR, wired to the board's reset.
+    Dtr,
+    /// Pulse RTS, wired to the board's reset.
+    Rts,
+}
+
+impl Reset {
+    /// Restarts the board on `port`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the port or setting the line fails.
+    pub fn perform(self, port: &mut Port) -> io::Result<()> {
+        let line = match self {
+            Reset::Magic => {
+                port.write_all(&RESET)?;
+                return port.flush();
+            }
+            Reset::Dtr => Line::Dtr,
+            Reset::Rts => Line::Rts,
+        };
+
+        port.set_line(line, true)?;
+        thread::sleep(RESET_PULSE);
+        port.set_line(line, false)
+    }
+}
+
+/// What a file looked like: its modification time and length, if it exists.
+type Stamp = Option<(SystemTime, u64)>;
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Watches a file for changes.
+pub struct Watcher {
+    path: PathBuf,
+    last: Stamp,
+}
+
+impl Watcher {
+    /// Returns a watcher of the file at `path`, as it is now.
+    pub fn new(path: &Path) -> Watcher {
+        Watcher { path: path.to_path_buf(), last: stamp(path) }
+    }
+
+    /// Returns `true` if the file changed since last asked.
+    pub fn changed(&mut self) -> bool {
+        let now = stamp(&self.path);
+        let changed = now != self.last;
+        self.last = now;
+        changed
+    }
+
+    /// Waits until the file exists and stops changing, as once a build has
+    /// finished writing it.
+    pub fn settle(&mut self) {
+        loop {
+            thread::sleep(SETTLE_TIME);
+            if !self.changed() && self.last.is_some() {
+                return;
+            }
+        }
+    }
+}